@@ -0,0 +1,78 @@
+CREATE TABLE  IF NOT EXISTS metadata (
+  url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  json_ld TEXT NOT NULL,
+  opengraph TEXT NOT NULL,
+  microdata TEXT NOT NULL,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_metadata__url ON metadata(url);
+
+
+CREATE TABLE  IF NOT EXISTS assets (
+  url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  kind TEXT NOT NULL,
+  content_type TEXT,
+  content BLOB NOT NULL,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_assets__url ON assets(url);
+
+
+-- Original form of urls which were changed by normalization,
+-- or pages which declare another url as canonical
+CREATE TABLE  IF NOT EXISTS aliases (
+  original_url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  url TEXT NOT NULL,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_aliases__url ON aliases(url);
+
+
+CREATE TABLE  IF NOT EXISTS fingerprints (
+  url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  -- 64 bit SimHash of page text, stored as signed integer
+  simhash INTEGER NOT NULL,
+  -- 1 if page was near-duplicate of an earlier page when scraped
+  near_duplicate INTEGER NOT NULL DEFAULT 0,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_fingerprints__url ON fingerprints(url);
+
+
+-- Urls skipped by crawler trap heuristics, page processors or fetch checks (not html, too large)
+CREATE TABLE  IF NOT EXISTS skipped (
+  url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  reason TEXT NOT NULL,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_skipped__url ON skipped(url);
+
+
+-- Pages and assets whose body was cut at `--max-body-size` (with `--oversize truncate`)
+CREATE TABLE  IF NOT EXISTS truncated (
+  url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+
+-- Data added by page processors (`--extract-text`, `--process-command`), as json object
+CREATE TABLE  IF NOT EXISTS page_data (
+  url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  data TEXT NOT NULL,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_page_data__url ON page_data(url);
+
+
+-- One row per run, written when the run ends
+CREATE TABLE  IF NOT EXISTS sessions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  started TEXT NOT NULL,
+  ended TEXT NOT NULL,
+  exit_reason TEXT NOT NULL,
+  pages INTEGER NOT NULL,
+  assets INTEGER NOT NULL,
+  errors INTEGER NOT NULL,
+  bytes INTEGER NOT NULL,
+  frontier INTEGER NOT NULL
+);
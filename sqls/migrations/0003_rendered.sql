@@ -0,0 +1,6 @@
+CREATE TABLE rendered (
+  url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  content TEXT NOT NULL,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX idx_rendered__time ON rendered(time);
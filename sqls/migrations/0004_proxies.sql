@@ -0,0 +1,6 @@
+CREATE TABLE proxies (
+  url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  proxy TEXT NOT NULL,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX idx_proxies__proxy ON proxies(proxy);
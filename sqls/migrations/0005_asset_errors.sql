@@ -0,0 +1,6 @@
+CREATE TABLE asset_errors (
+  url TEXT NOT NULL UNIQUE ON CONFLICT REPLACE,
+  kind TEXT NOT NULL,
+  msg TEXT NOT NULL,
+  time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
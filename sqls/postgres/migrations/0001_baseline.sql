@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS results (
+  url TEXT PRIMARY KEY,
+  content TEXT NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_scrape_results__time ON results(time);
+
+
+CREATE TABLE IF NOT EXISTS errors (
+  url TEXT PRIMARY KEY,
+  msg TEXT NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_errors__time ON errors(time);
+
+
+CREATE TABLE IF NOT EXISTS links (
+  url TEXT PRIMARY KEY,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_links__time ON links(time);
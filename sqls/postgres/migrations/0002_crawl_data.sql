@@ -0,0 +1,65 @@
+CREATE TABLE IF NOT EXISTS metadata (
+  url TEXT PRIMARY KEY,
+  json_ld JSONB NOT NULL,
+  opengraph JSONB NOT NULL,
+  microdata JSONB NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+
+CREATE TABLE IF NOT EXISTS assets (
+  url TEXT PRIMARY KEY,
+  kind TEXT NOT NULL,
+  content_type TEXT,
+  content BYTEA NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+
+CREATE TABLE IF NOT EXISTS aliases (
+  original_url TEXT PRIMARY KEY,
+  url TEXT NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_aliases__url ON aliases(url);
+
+
+CREATE TABLE IF NOT EXISTS skipped (
+  url TEXT PRIMARY KEY,
+  reason TEXT NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+
+CREATE TABLE IF NOT EXISTS fingerprints (
+  url TEXT PRIMARY KEY,
+  simhash BIGINT NOT NULL,
+  near_duplicate BOOLEAN NOT NULL DEFAULT false,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+
+CREATE TABLE IF NOT EXISTS truncated (
+  url TEXT PRIMARY KEY,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+
+CREATE TABLE IF NOT EXISTS page_data (
+  url TEXT PRIMARY KEY,
+  data JSONB NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+
+CREATE TABLE IF NOT EXISTS sessions (
+  id BIGSERIAL PRIMARY KEY,
+  started TIMESTAMPTZ NOT NULL,
+  ended TIMESTAMPTZ NOT NULL,
+  exit_reason TEXT NOT NULL,
+  pages BIGINT NOT NULL,
+  assets BIGINT NOT NULL,
+  errors BIGINT NOT NULL,
+  bytes BIGINT NOT NULL,
+  frontier BIGINT NOT NULL
+);
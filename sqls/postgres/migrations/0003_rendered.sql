@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS rendered (
+  url TEXT PRIMARY KEY,
+  content TEXT NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
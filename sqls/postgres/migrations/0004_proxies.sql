@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS proxies (
+  url TEXT PRIMARY KEY,
+  proxy TEXT NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_proxies__proxy ON proxies(proxy);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS asset_errors (
+  url TEXT PRIMARY KEY,
+  kind TEXT NOT NULL,
+  msg TEXT NOT NULL,
+  time TIMESTAMPTZ NOT NULL DEFAULT now()
+);
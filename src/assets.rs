@@ -0,0 +1,220 @@
+//! Static assets (scripts, stylesheets, images, fonts) referenced by pages.
+use std::fmt;
+use std::sync::LazyLock;
+
+use regex::Regex;
+use select::document::Document;
+use select::predicate::{Attr, Name};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::fetch::{self, FetchConfig};
+
+static CSS_IMPORT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"@import\s+(?:url\(\s*)?["']?([^"')\s;]+)"#).unwrap());
+static CSS_URL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"url\(\s*["']?([^"')]+?)["']?\s*\)"#).unwrap());
+static CSS_FONT_FACE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)@font-face\s*\{[^}]*\}").unwrap());
+const FONT_EXTENSIONS: &[&str] = &[".woff", ".woff2", ".ttf", ".otf", ".eot"];
+static JS_LITERAL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"["'`]((?:https?://|/)[^"'`\s<>{}\\]+)["'`]"#).unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetKind {
+    /// `<script src>`
+    #[value(name = "js")]
+    Script,
+    /// `<link rel=stylesheet>` and css `@import`
+    #[value(name = "css")]
+    Stylesheet,
+    /// `<img src>` and css `url(...)` other than fonts
+    #[value(name = "img")]
+    Image,
+    /// css `url(...)` in `@font-face`, or of a font file (`.woff`, `.ttf`...)
+    #[value(name = "font")]
+    Font,
+}
+
+impl AssetKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssetKind::Script => "script",
+            AssetKind::Stylesheet => "stylesheet",
+            AssetKind::Image => "image",
+            AssetKind::Font => "font",
+        }
+    }
+}
+
+impl fmt::Display for AssetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AssetConfig {
+    /// Asset types to fetch and store, nothing is fetched if empty
+    pub kinds: Vec<AssetKind>,
+    /// Look for urls in string literals of fetched js files
+    pub discover_js_urls: bool,
+}
+
+impl AssetConfig {
+    pub fn wants(&self, kind: AssetKind) -> bool {
+        self.kinds.contains(&kind)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssetResult {
+    pub status: u16,
+    #[serde(with = "crate::cluster::base64_bytes")]
+    pub content: Vec<u8>,
+    pub content_type: Option<String>,
+    /// Pages discovered inside the asset (only from js heuristics)
+    pub links: Vec<Url>,
+    /// Other assets referenced by this asset (css `url(...)`/`@import`)
+    pub assets: Vec<(AssetKind, Url)>,
+    /// Content was cut at `max_body_size`
+    pub truncated: bool,
+    /// Set by [`crate::proxy::ProxyPool`]
+    #[serde(default)]
+    pub proxy: Option<String>,
+}
+
+pub async fn fetch_asset(
+    url: &Url,
+    kind: AssetKind,
+    client: reqwest::Client,
+    discover_js_urls: bool,
+    config: &FetchConfig,
+) -> anyhow::Result<AssetResult> {
+    let reqwest_url: reqwest::Url = reqwest::Url::parse(url.as_ref())?;
+    let response = client.get(reqwest_url).send().await?.error_for_status()?;
+    let status = response.status().as_u16();
+    let content_type = fetch::content_type(response.headers()).map(str::to_string);
+    let body = fetch::read_body(response, config).await?;
+    let content = body.content;
+
+    let mut links = vec![];
+    let mut assets = vec![];
+    match kind {
+        AssetKind::Stylesheet => {
+            assets = css_references(url, &String::from_utf8_lossy(&content));
+        }
+        AssetKind::Script if discover_js_urls => {
+            links = js_string_urls(url, &String::from_utf8_lossy(&content));
+        }
+        _ => {}
+    }
+
+    Ok(AssetResult {
+        status,
+        content,
+        content_type,
+        links,
+        assets,
+        truncated: body.truncated,
+        proxy: None,
+    })
+}
+
+/// All assets referenced from html `document` found at `url`
+pub fn html_references(url: &Url, document: &Document) -> Vec<(AssetKind, Url)> {
+    let mut references: Vec<(AssetKind, &str)> = vec![];
+    for node in document.find(Name("script")) {
+        if let Some(src) = node.attr("src") {
+            references.push((AssetKind::Script, src));
+        }
+    }
+    for node in document.find(Name("link")) {
+        let is_stylesheet = node
+            .attr("rel")
+            .map(|rel| {
+                rel.split_whitespace()
+                    .any(|x| x.eq_ignore_ascii_case("stylesheet"))
+            })
+            .unwrap_or(false);
+        if let (true, Some(href)) = (is_stylesheet, node.attr("href")) {
+            references.push((AssetKind::Stylesheet, href));
+        }
+    }
+    for node in document.find(Name("img")) {
+        if let Some(src) = node.attr("src") {
+            references.push((AssetKind::Image, src));
+        }
+    }
+    let mut rv: Vec<_> = references
+        .into_iter()
+        .filter_map(|(kind, value)| Some((kind, join(url, value)?)))
+        .collect();
+
+    for node in document.find(Name("style")) {
+        rv.extend(css_references(url, &node.text()));
+    }
+    for node in document.find(Attr("style", ())) {
+        if let Some(style) = node.attr("style") {
+            rv.extend(css_references(url, style));
+        }
+    }
+    rv
+}
+
+/// `url(...)` and `@import` references in a stylesheet
+pub fn css_references(url: &Url, css: &str) -> Vec<(AssetKind, Url)> {
+    let mut rv = vec![];
+    let mut imported = vec![];
+    for capture in CSS_IMPORT_RE.captures_iter(css) {
+        let value = &capture[1];
+        imported.push(value.to_string());
+        if let Some(x) = join(url, value) {
+            rv.push((AssetKind::Stylesheet, x));
+        }
+    }
+    let font_faces: Vec<_> = CSS_FONT_FACE_RE.find_iter(css).map(|x| x.range()).collect();
+    for capture in CSS_URL_RE.captures_iter(css) {
+        let value = capture[1].trim();
+        if value.starts_with("data:") || imported.iter().any(|x| x == value) {
+            continue;
+        }
+        let Some(x) = join(url, value) else { continue };
+        let start = capture.get(0).unwrap().start();
+        let path = x.path().to_ascii_lowercase();
+        let kind = if font_faces.iter().any(|x| x.contains(&start))
+            || FONT_EXTENSIONS.iter().any(|x| path.ends_with(x))
+        {
+            AssetKind::Font
+        } else {
+            AssetKind::Image
+        };
+        rv.push((kind, x));
+    }
+    rv
+}
+
+/// Heuristic: string literals in js that look like absolute http(s) urls or root relative paths
+pub fn js_string_urls(url: &Url, js: &str) -> Vec<Url> {
+    JS_LITERAL_RE
+        .captures_iter(js)
+        .filter_map(|capture| {
+            let value = &capture[1];
+            // `//` is mostly a comment or protocol relative cdn url, `/` alone is rarely useful
+            if value == "/" || value.starts_with("//") {
+                return None;
+            }
+            join(url, value)
+        })
+        .collect()
+}
+
+fn join(base: &Url, value: &str) -> Option<Url> {
+    let mut rv = base.join(value.trim()).ok()?;
+    if !matches!(rv.scheme(), "http" | "https") {
+        return None;
+    }
+    rv.set_fragment(None);
+    Some(rv)
+}
@@ -1,5 +1,11 @@
 mod args;
+#[allow(dead_code)]
 mod repl;
 
-pub use args::{Args, Command};
+pub use args::{
+    Args, Command, ConfigArgs, DbArgs, DbCommand, ExportArgs, ExportKind, InspectArgs,
+    InspectCommand, LogArgs, LogFormat, MirrorArgs, ProxyArgs, ScrapeArgs, SeenKind, ServeArgs,
+    StorageKind, StreamFormat, WorkerArgs,
+};
+#[allow(unused_imports)]
 pub use repl::{Repl, ReplCommand};
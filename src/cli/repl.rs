@@ -6,7 +6,7 @@ use tokio::io::{self, AsyncBufReadExt, BufReader, Lines, Stdin};
 use clap::{Command, CommandFactory, FromArgMatches, Parser};
 use std::{io::Write, sync::Arc, time::Duration};
 
-use crate::orchestrator::RuntimeConfig;
+use waper::RuntimeConfig;
 
 #[derive(Parser, Debug)]
 #[command(about="Repl to control waper runtime.", long_about = None)]
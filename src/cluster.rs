@@ -0,0 +1,632 @@
+//! Crawling from many machines. The coordinator is a normal crawl (frontier, filters, dedup,
+//! storage and limits all stay in one process) whose requests are made by workers: they lease
+//! batches of urls over http, fetch and parse them and report the results back.
+//!
+//! Workers keep no state, so they can join or leave at any time. Urls leased by a worker which
+//! stops reporting are handed to another one after the lease timeout.
+//!
+//! With a token set on both sides, requests to the coordinator without it are refused.
+//!
+//! ```no_run
+//! # async fn run(db: waper::Database) -> anyhow::Result<()> {
+//! use std::time::Duration;
+//! use waper::cluster::{Coordinator, Worker, WorkerConfig};
+//!
+//! let coordinator = Coordinator::new(Duration::from_secs(120));
+//! coordinator.spawn_server("0.0.0.0:7879".parse()?)?;
+//! let mut orchestrator = waper::Orchestrator::builder(db)
+//!     .seeds(["https://example.com/".parse()?])
+//!     .fetcher(coordinator.clone())
+//!     .build()?;
+//! orchestrator.start(false).await?;
+//! coordinator.finish().await;
+//!
+//! // on other machines
+//! let worker = Worker::new(WorkerConfig::new("http://10.0.0.1:7879/".parse()?))?;
+//! worker.run().await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use futures::{FutureExt, StreamExt};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, Notify};
+use tokio::time::Instant;
+use tracing::Instrument;
+use url::Url;
+
+use crate::assets::{AssetKind, AssetResult};
+use crate::fetch::{FetchConfig, Fetcher, Rejected};
+use crate::metrics;
+use crate::prelude::*;
+use crate::proxy::{ProxyConfig, ProxyPool};
+use crate::scraper::ScrapingResult;
+
+/// How long a lease request waits for urls when there are none
+const LEASE_WAIT: Duration = Duration::from_secs(10);
+
+/// How long [`Coordinator::finish`] keeps answering, so workers waiting for urls hear about it
+const FINISH_GRACE: Duration = Duration::from_secs(1);
+
+/// A url leased to a worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub url: Url,
+    /// `None` for pages
+    pub asset: Option<AssetKind>,
+    pub discover_js_urls: bool,
+    pub fetch: FetchConfig,
+}
+
+/// What a worker got for a [`Job`]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Page(Box<ScrapingResult>),
+    Asset(AssetResult),
+    Rejected(Rejected),
+    Error {
+        message: String,
+        /// See [`metrics::error_class`]
+        class: String,
+    },
+}
+
+impl Outcome {
+    fn new<T>(result: anyhow::Result<T>, ok: impl FnOnce(T) -> Self) -> Self {
+        match result {
+            Ok(x) => ok(x),
+            Err(e) => match e.downcast::<Rejected>() {
+                Ok(rejected) => Outcome::Rejected(rejected),
+                Err(e) => Outcome::Error {
+                    class: metrics::error_class(&e).to_string(),
+                    message: format!("{e:?}"),
+                },
+            },
+        }
+    }
+
+    fn into_error(self) -> anyhow::Error {
+        match self {
+            Outcome::Rejected(x) => x.into(),
+            Outcome::Error { message, class } => RemoteError { message, class }.into(),
+            Outcome::Page(_) | Outcome::Asset(_) => {
+                anyhow::anyhow!("Worker sent a result of the wrong kind")
+            }
+        }
+    }
+}
+
+/// Request failed on a worker, stored in `errors` just like a local failure
+#[derive(Debug)]
+pub struct RemoteError {
+    pub message: String,
+    pub class: String,
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaseRequest {
+    /// Only used in logs
+    pub worker: String,
+    pub max: usize,
+    /// Wait up to 10 seconds for urls if there are none right now
+    pub wait: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Lease {
+    pub jobs: Vec<Job>,
+    /// Crawl is over, no more urls will be leased
+    pub finished: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Report {
+    pub worker: String,
+    pub results: Vec<JobResult>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JobResult {
+    pub id: u64,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Reported {
+    /// Results which were still awaited, others were already reported after their lease expired
+    pub accepted: usize,
+}
+
+/// [`Fetcher`] which waits for workers to make the requests, serves them with [`Self::router`]
+#[derive(Clone)]
+pub struct Coordinator {
+    shared: Arc<Shared>,
+    token: Option<Arc<str>>,
+}
+
+struct Shared {
+    state: Mutex<LeaseState>,
+    /// Woken when urls are added or the crawl finishes
+    changed: Notify,
+    lease_timeout: Duration,
+}
+
+#[derive(Default)]
+struct LeaseState {
+    next_id: u64,
+    /// Ids of jobs waiting for a worker, expired leases go to the front
+    pending: VecDeque<u64>,
+    jobs: HashMap<u64, Entry>,
+    finished: bool,
+}
+
+struct Entry {
+    job: Job,
+    reply: oneshot::Sender<Outcome>,
+    /// Worker and when its lease expires
+    lease: Option<(String, Instant)>,
+}
+
+impl LeaseState {
+    fn take(&mut self, worker: &str, max: usize, lease_timeout: Duration) -> Vec<Job> {
+        let now = Instant::now();
+        let mut expired = vec![];
+        self.jobs.retain(|id, entry| {
+            // orchestrator stopped waiting for it (shutdown timeout)
+            if entry.reply.is_closed() {
+                return false;
+            }
+            if let Some((worker, deadline)) = &entry.lease {
+                if *deadline <= now {
+                    warn!("Lease of {} by {} expired", entry.job.url, worker);
+                    expired.push(*id);
+                }
+            }
+            true
+        });
+        expired.sort_unstable();
+        for id in expired.into_iter().rev() {
+            if let Some(entry) = self.jobs.get_mut(&id) {
+                entry.lease = None;
+            }
+            self.pending.push_front(id);
+        }
+
+        let mut rv = vec![];
+        while rv.len() < max {
+            let Some(id) = self.pending.pop_front() else {
+                break;
+            };
+            // already reported or given up on
+            let Some(entry) = self.jobs.get_mut(&id) else {
+                continue;
+            };
+            entry.lease = Some((worker.to_string(), now + lease_timeout));
+            rv.push(entry.job.clone());
+        }
+        rv
+    }
+}
+
+impl Coordinator {
+    /// Urls not reported `lease_timeout` after being leased are leased again,
+    /// should be longer than the total timeout of a request
+    pub fn new(lease_timeout: Duration) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Default::default(),
+                changed: Notify::new(),
+                lease_timeout,
+            }),
+            token: None,
+        }
+    }
+
+    /// Only serve workers sending `token`, see [`WorkerConfig::token`]
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into().into());
+        self
+    }
+
+    /// Tell workers the crawl is over, they exit once their requests are reported
+    pub async fn finish(&self) {
+        self.shared.state.lock().finished = true;
+        self.shared.changed.notify_waiters();
+        tokio::time::sleep(FINISH_GRACE).await;
+    }
+
+    /// `/lease` and `/report`, used by [`Worker`]
+    pub fn router(&self) -> Router {
+        let mut router = Router::new()
+            .route("/lease", post(lease))
+            .route("/report", post(report));
+        if let Some(token) = &self.token {
+            router = router.route_layer(middleware::from_fn_with_state(token.clone(), authorize));
+        }
+        router.with_state(self.shared.clone())
+    }
+
+    /// Serve [`Self::router`] on `addr` from a background task, returns the bound address
+    pub fn spawn_server(&self, addr: SocketAddr) -> anyhow::Result<SocketAddr> {
+        let server = axum::Server::try_bind(&addr)?.serve(self.router().into_make_service());
+        let addr = server.local_addr();
+        info!("Waiting for workers on http://{}/", addr);
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("Coordinator server failed: {:?}", e);
+            }
+        });
+        Ok(addr)
+    }
+
+    async fn submit(
+        &self,
+        url: &Url,
+        asset: Option<AssetKind>,
+        discover_js_urls: bool,
+        config: &FetchConfig,
+    ) -> anyhow::Result<Outcome> {
+        let (reply, outcome) = oneshot::channel();
+        {
+            let mut state = self.shared.state.lock();
+            let id = state.next_id;
+            state.next_id += 1;
+            let job = Job {
+                id,
+                url: url.clone(),
+                asset,
+                discover_js_urls,
+                fetch: config.clone(),
+            };
+            state.jobs.insert(
+                id,
+                Entry {
+                    job,
+                    reply,
+                    lease: None,
+                },
+            );
+            state.pending.push_back(id);
+        }
+        self.shared.changed.notify_waiters();
+        outcome.await.context("Coordinator dropped the request")
+    }
+}
+
+#[async_trait]
+impl Fetcher for Coordinator {
+    async fn page(&self, url: &Url, config: &FetchConfig) -> anyhow::Result<ScrapingResult> {
+        match self.submit(url, None, false, config).await? {
+            Outcome::Page(x) => Ok(*x),
+            other => Err(other.into_error()),
+        }
+    }
+
+    async fn asset(
+        &self,
+        url: &Url,
+        kind: AssetKind,
+        discover_js_urls: bool,
+        config: &FetchConfig,
+    ) -> anyhow::Result<AssetResult> {
+        match self
+            .submit(url, Some(kind), discover_js_urls, config)
+            .await?
+        {
+            Outcome::Asset(x) => Ok(x),
+            other => Err(other.into_error()),
+        }
+    }
+}
+
+/// Refuse requests without `Authorization: Bearer <token>`
+async fn authorize<B>(
+    State(token): State<Arc<str>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let expected = format!("Bearer {token}");
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .is_some_and(|x| x.as_bytes() == expected.as_bytes());
+    if !authorized {
+        warn!(
+            "Refused request to {} without the cluster token",
+            request.uri()
+        );
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+async fn lease(
+    State(shared): State<Arc<Shared>>,
+    Json(request): Json<LeaseRequest>,
+) -> Json<Lease> {
+    let deadline = Instant::now()
+        + if request.wait {
+            LEASE_WAIT
+        } else {
+            Duration::ZERO
+        };
+    loop {
+        let changed = shared.changed.notified();
+        tokio::pin!(changed);
+        // registered before looking, so urls added meanwhile are not missed
+        changed.as_mut().enable();
+        {
+            let mut state = shared.state.lock();
+            let jobs = state.take(&request.worker, request.max, shared.lease_timeout);
+            if !jobs.is_empty() || state.finished || Instant::now() >= deadline {
+                if !jobs.is_empty() {
+                    debug!("Leased {} urls to {}", jobs.len(), request.worker);
+                }
+                return Json(Lease {
+                    jobs,
+                    finished: state.finished,
+                });
+            }
+        }
+        // also wake up now and then to hand out expired leases
+        let wake = deadline.min(Instant::now() + Duration::from_secs(1));
+        let _ = tokio::time::timeout_at(wake, changed).await;
+    }
+}
+
+async fn report(State(shared): State<Arc<Shared>>, Json(report): Json<Report>) -> Json<Reported> {
+    let mut accepted = 0;
+    for result in report.results {
+        let entry = shared.state.lock().jobs.remove(&result.id);
+        match entry {
+            // error drop: orchestrator stopped waiting for it
+            Some(entry) => accepted += entry.reply.send(result.outcome).is_ok() as usize,
+            None => debug!(
+                "Ignoring result {} from {}, it was already reported",
+                result.id, report.worker
+            ),
+        }
+    }
+    Json(Reported { accepted })
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    /// Address the coordinator is listening on, e.g. `http://10.0.0.1:7879/`
+    pub coordinator: Url,
+    /// Shown in the coordinator's logs
+    pub name: String,
+    pub max_parallel_requests: usize,
+    /// Keep retrying an unreachable coordinator this long before giving up
+    pub retry_for: Duration,
+    /// Make requests through these proxies instead of directly
+    pub proxy: Option<ProxyConfig>,
+    /// Connect and total timeouts of the requests, body limits come with each job
+    pub fetch: FetchConfig,
+    /// Sent to a coordinator which requires one, see [`Coordinator::token`]
+    pub token: Option<String>,
+}
+
+impl WorkerConfig {
+    pub fn new(coordinator: Url) -> Self {
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "worker".to_string());
+        Self {
+            coordinator,
+            name: format!("{host}-{}", std::process::id()),
+            max_parallel_requests: 5,
+            retry_for: Duration::from_secs(60),
+            proxy: None,
+            fetch: FetchConfig::default(),
+            token: None,
+        }
+    }
+}
+
+/// Fetches urls leased from a [`Coordinator`]
+pub struct Worker {
+    config: WorkerConfig,
+    api: reqwest::Client,
+    fetcher: Box<dyn Fetcher>,
+}
+
+impl Worker {
+    pub fn new(mut config: WorkerConfig) -> anyhow::Result<Self> {
+        // so `join` keeps the whole path
+        if !config.coordinator.path().ends_with('/') {
+            let path = format!("{}/", config.coordinator.path());
+            config.coordinator.set_path(&path);
+        }
+        let api = reqwest::ClientBuilder::new()
+            .timeout(LEASE_WAIT + Duration::from_secs(120))
+            .build()
+            .context("Failed to create http client for the coordinator")?;
+        let fetcher: Box<dyn Fetcher> = match &config.proxy {
+            Some(proxy) => Box::new(ProxyPool::new(proxy.clone(), &config.fetch)?),
+            None => Box::new(
+                config
+                    .fetch
+                    .client()
+                    .context("Failed to create http client")?,
+            ),
+        };
+        Ok(Self {
+            config,
+            api,
+            fetcher,
+        })
+    }
+
+    /// Runs till the coordinator's crawl is over, returns number of urls fetched
+    pub async fn run(&self) -> anyhow::Result<u64> {
+        info!(
+            "Working for {} as {}",
+            self.config.coordinator, self.config.name
+        );
+        let max = self.config.max_parallel_requests.max(1);
+        let mut tasks = futures::stream::FuturesUnordered::new();
+        let mut finished = false;
+        let mut fetched = 0;
+        loop {
+            if !finished && tasks.len() < max {
+                let request = LeaseRequest {
+                    worker: self.config.name.clone(),
+                    max: max - tasks.len(),
+                    // only wait for urls when there's nothing else to do
+                    wait: tasks.is_empty(),
+                };
+                let lease: Lease = self.call("lease", &request).await?;
+                finished = lease.finished;
+                tasks.extend(lease.jobs.into_iter().map(|job| self.fetch(job)));
+            }
+            let Some(result) = tasks.next().await else {
+                if finished {
+                    break;
+                }
+                continue;
+            };
+            // everything else finished meanwhile is reported in one go
+            let mut results = vec![result];
+            while let Some(Some(result)) = tasks.next().now_or_never() {
+                results.push(result);
+            }
+            fetched += results.len() as u64;
+            let report = Report {
+                worker: self.config.name.clone(),
+                results,
+            };
+            let _: Reported = self.call("report", &report).await?;
+        }
+        info!("Crawl is over, fetched {} urls", fetched);
+        Ok(fetched)
+    }
+
+    async fn fetch(&self, job: Job) -> JobResult {
+        let span = tracing::info_span!("fetch", url = %job.url, id = job.id);
+        let outcome = async {
+            let outcome = match job.asset {
+                None => Outcome::new(self.fetcher.page(&job.url, &job.fetch).await, |x| {
+                    Outcome::Page(Box::new(x))
+                }),
+                Some(kind) => Outcome::new(
+                    self.fetcher
+                        .asset(&job.url, kind, job.discover_js_urls, &job.fetch)
+                        .await,
+                    Outcome::Asset,
+                ),
+            };
+            debug!("Fetched {}", job.url);
+            outcome
+        }
+        .instrument(span)
+        .await;
+        JobResult {
+            id: job.id,
+            outcome,
+        }
+    }
+
+    /// POST `body` as json, retrying while the coordinator can't be reached (e.g. restarting)
+    async fn call<T: Serialize, R: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &T,
+    ) -> anyhow::Result<R> {
+        let url = self.config.coordinator.join(path)?;
+        let body = serde_json::to_vec(body)?;
+        let started = Instant::now();
+        let mut delay = Duration::from_millis(100);
+        loop {
+            let mut request = self
+                .api
+                .post(url.as_str())
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(token) = &self.config.token {
+                request = request.bearer_auth(token);
+            }
+            let response = request.send().await;
+            match response {
+                Ok(response) => {
+                    let bytes = response.error_for_status()?.bytes().await?;
+                    return serde_json::from_slice(&bytes)
+                        .with_context(|| format!("Invalid response from {url}"));
+                }
+                Err(e) if e.is_connect() && started.elapsed() < self.config.retry_for => {
+                    warn!("Coordinator unreachable, retrying in {:?}: {}", delay, e);
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(Duration::from_secs(5));
+                }
+                Err(e) => return Err(e).with_context(|| format!("Request to {url} failed")),
+            }
+        }
+    }
+}
+
+/// `HeaderMap` as a list of name, value pairs
+pub(crate) mod header_list {
+    use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(headers: &HeaderMap, serializer: S) -> Result<S::Ok, S::Error> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), String::from_utf8_lossy(value.as_bytes())))
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    /// Invalid headers are dropped
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HeaderMap, D::Error> {
+        let pairs = Vec::<(String, String)>::deserialize(deserializer)?;
+        Ok(pairs
+            .into_iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    HeaderName::from_bytes(name.as_bytes()).ok()?,
+                    HeaderValue::from_str(&value).ok()?,
+                ))
+            })
+            .collect())
+    }
+}
+
+/// Bytes as a base64 string, json arrays of numbers are about 4 times larger
+pub(crate) mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        STANDARD.decode(value).map_err(serde::de::Error::custom)
+    }
+}
@@ -1,7 +1,57 @@
+use std::path::{Path, PathBuf};
+
 use anyhow::Context;
-use sqlx::sqlite;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use prometheus::HistogramTimer;
+use serde_json::{Map, Value};
+use sqlx::query_builder::Separated;
+use sqlx::sqlite::{self, SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{QueryBuilder, Sqlite, Transaction};
 use url::Url;
 
+use crate::assets::AssetKind;
+use crate::metadata::PageMetadata;
+use crate::metrics::metrics;
+use crate::session::{unix_time, Session};
+use crate::storage::{Storage, Write};
+
+pub mod migrate;
+
+pub use migrate::SchemaStatus;
+
+/// Observes duration of a write into `db_write_seconds` when dropped
+fn write_timer(table: &str) -> HistogramTimer {
+    metrics()
+        .db_write_seconds
+        .with_label_values(&[table])
+        .start_timer()
+}
+
+/// Rows per multi-row INSERT, keeps bound parameters well below sqlite's limit
+const ROWS_PER_STATEMENT: usize = 500;
+
+/// Insert `rows` with as few statements as possible, `head` is `INSERT INTO table (columns) `
+async fn insert_rows<T>(
+    tx: &mut Transaction<'_, Sqlite>,
+    head: &'static str,
+    rows: Vec<T>,
+    mut push: impl FnMut(Separated<'_, 'static, Sqlite, &'static str>, T),
+) -> anyhow::Result<()> {
+    let mut rows = rows.into_iter().peekable();
+    while rows.peek().is_some() {
+        let mut builder = QueryBuilder::new(head);
+        builder.push_values(rows.by_ref().take(ROWS_PER_STATEMENT), &mut push);
+        builder
+            .build()
+            .execute(&mut *tx)
+            .await
+            .context(format!("Failed to execute: {head}"))?;
+    }
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct Database {
     conn: sqlite::SqlitePool,
@@ -11,21 +61,175 @@ impl Database {
     pub fn new(conn: sqlite::SqlitePool) -> Self {
         Self { conn }
     }
-    pub async fn add_to_links(&self, urls: Vec<Url>) -> anyhow::Result<()> {
-        let mut tx = self.conn.begin().await?;
-        for url in urls {
-            // TODO: please make this performant (benchmark!!)
-            // see: https://github.com/launchbadge/sqlx/issues/294
-            let url_string = url.to_string();
-            let query = sqlx::query!("INSERT INTO links (url) VALUES (?)", url_string);
-            query.execute(&mut tx).await?;
+
+    async fn open(
+        path: &Path,
+        sqlite_options: SqliteConnectOptions,
+    ) -> anyhow::Result<sqlite::SqlitePool> {
+        let sqlite_options = sqlite_options.filename(path);
+        SqlitePoolOptions::new()
+            .max_connections(3)
+            .connect_with(sqlite_options)
+            .await
+            .context("Can't open sqlite file")
+    }
+
+    /// Open (or create) sqlite file at `path` and upgrade its schema if it's from an older waper
+    pub async fn connect(path: &Path) -> anyhow::Result<Self> {
+        let sqlite_options = SqliteConnectOptions::new()
+            .create_if_missing(true)
+            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal);
+        let conn = Self::open(path, sqlite_options).await?;
+        migrate::upgrade(&conn, path).await?;
+        Ok(Self::new(conn))
+    }
+
+    /// Schema version of an existing file, without changing it
+    pub async fn check(path: &Path) -> anyhow::Result<SchemaStatus> {
+        let sqlite_options = SqliteConnectOptions::new().read_only(true);
+        let conn = Self::open(path, sqlite_options).await?;
+        migrate::status(&conn).await
+    }
+
+    /// Upgrade schema of an existing file, returns path of the backup if it was upgraded
+    pub async fn migrate(path: &Path) -> anyhow::Result<Option<PathBuf>> {
+        let conn = Self::open(path, SqliteConnectOptions::new()).await?;
+        migrate::upgrade(&conn, path).await
+    }
+
+    /// (url, simhash) in order of scraping
+    pub async fn get_fingerprints(&self) -> anyhow::Result<Vec<(String, u64)>> {
+        let results = sqlx::query!("SELECT url, simhash FROM fingerprints ORDER BY time")
+            .fetch_all(&self.conn)
+            .await
+            .context("Failed to fetch fingerprints from sqlite db")?;
+        Ok(results
+            .into_iter()
+            .map(|x| (x.url, x.simhash as u64))
+            .collect())
+    }
+
+    pub async fn get_metadata(&self) -> anyhow::Result<Vec<(String, String, PageMetadata)>> {
+        let results = sqlx::query!(
+            "SELECT url, time, json_ld, opengraph, microdata FROM metadata ORDER BY time"
+        )
+        .fetch_all(&self.conn)
+        .await
+        .context("Failed to fetch metadata from sqlite db")?;
+
+        let mut rv = vec![];
+        for result in results {
+            let metadata = PageMetadata {
+                json_ld: serde_json::from_str(&result.json_ld).context("Invalid json_ld in DB")?,
+                opengraph: serde_json::from_str(&result.opengraph)
+                    .context("Invalid opengraph in DB")?,
+                microdata: serde_json::from_str(&result.microdata)
+                    .context("Invalid microdata in DB")?,
+            };
+            rv.push((result.url, result.time, metadata));
         }
-        tx.commit().await?;
-        Ok(())
+        Ok(rv)
+    }
+
+    pub async fn get_result_urls(&self) -> anyhow::Result<Vec<String>> {
+        let results = sqlx::query!("SELECT url FROM results")
+            .fetch_all(&self.conn)
+            .await
+            .context("Failed to fetch results from sqlite db")?;
+        Ok(results.into_iter().map(|x| x.url).collect())
+    }
+
+    /// (url, time) of pages whose url starts with `prefix`, most recent first
+    pub async fn get_pages(
+        &self,
+        prefix: &str,
+        limit: i64,
+        offset: i64,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        // not LIKE, urls are full of `_` and `%`
+        let results = sqlx::query!(
+            r#"SELECT url as "url!", time as "time!" FROM results WHERE substr(url, 1, length(?1)) = ?1
+             ORDER BY time DESC, url LIMIT ?2 OFFSET ?3"#,
+            prefix,
+            limit,
+            offset
+        )
+        .fetch_all(&self.conn)
+        .await
+        .context("Failed to fetch results from sqlite db")?;
+        Ok(results.into_iter().map(|x| (x.url, x.time)).collect())
+    }
+
+    /// (html, time) of the page at `url`
+    pub async fn get_page(&self, url: &str) -> anyhow::Result<Option<(String, String)>> {
+        let result = sqlx::query!("SELECT content, time FROM results WHERE url = ?", url)
+            .fetch_optional(&self.conn)
+            .await
+            .context("Failed to fetch result from sqlite db")?;
+        Ok(result.map(|x| (x.content, x.time)))
+    }
+
+    pub async fn get_asset_urls(&self) -> anyhow::Result<Vec<String>> {
+        let results = sqlx::query!("SELECT url FROM assets")
+            .fetch_all(&self.conn)
+            .await
+            .context("Failed to fetch assets from sqlite db")?;
+        Ok(results.into_iter().map(|x| x.url).collect())
+    }
+
+    /// (url, html) of all results, without loading everything in memory
+    pub fn stream_results(&self) -> BoxStream<'_, anyhow::Result<(String, String)>> {
+        sqlx::query!("SELECT url, content FROM results")
+            .fetch(&self.conn)
+            .map(|x| {
+                x.map(|x| (x.url, x.content))
+                    .context("Failed to fetch results from sqlite db")
+            })
+            .boxed()
+    }
+
+    /// (url, kind, content) of all assets, without loading everything in memory
+    pub fn stream_assets(&self) -> BoxStream<'_, anyhow::Result<(String, String, Vec<u8>)>> {
+        sqlx::query!("SELECT url, kind, content FROM assets")
+            .fetch(&self.conn)
+            .map(|x| {
+                x.map(|x| (x.url, x.kind, x.content))
+                    .context("Failed to fetch assets from sqlite db")
+            })
+            .boxed()
+    }
+}
+
+#[async_trait]
+impl Storage for Database {
+    async fn add_to_links(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        self.write_batch(urls.into_iter().map(Write::Link).collect())
+            .await
+    }
+
+    async fn add_to_aliases(&self, aliases: Vec<(Url, Url)>) -> anyhow::Result<()> {
+        self.write_batch(
+            aliases
+                .into_iter()
+                .map(|(original, url)| Write::Alias(original, url))
+                .collect(),
+        )
+        .await
+    }
+
+    async fn add_to_skipped(&self, skipped: Vec<(Url, String)>) -> anyhow::Result<()> {
+        self.write_batch(
+            skipped
+                .into_iter()
+                .map(|(url, reason)| Write::Skipped(url, reason))
+                .collect(),
+        )
+        .await
     }
 
-    pub async fn add_to_results(&self, url: Url, html: String) -> anyhow::Result<()> {
+    async fn add_to_results(&self, url: Url, html: String) -> anyhow::Result<()> {
         let url_string = url.to_string();
+        let _timer = write_timer("results");
         sqlx::query!(
             "INSERT INTO results (url, content) VALUES (?, ?)",
             url_string,
@@ -37,8 +241,9 @@ impl Database {
         Ok(())
     }
 
-    pub async fn add_to_errors(&self, url: Url, msg: String) -> anyhow::Result<()> {
+    async fn add_to_errors(&self, url: Url, msg: String) -> anyhow::Result<()> {
         let url_string = url.to_string();
+        let _timer = write_timer("errors");
         sqlx::query!(
             "INSERT INTO errors (url, msg) VALUES (?, ?)",
             url_string,
@@ -52,13 +257,385 @@ impl Database {
         Ok(())
     }
 
-    pub async fn get_unprocessed_links(&self) -> anyhow::Result<Vec<Url>> {
+    async fn add_to_metadata(&self, url: Url, metadata: &PageMetadata) -> anyhow::Result<()> {
+        let url_string = url.to_string();
+        let json_ld = serde_json::to_string(&metadata.json_ld)?;
+        let opengraph = serde_json::to_string(&metadata.opengraph)?;
+        let microdata = serde_json::to_string(&metadata.microdata)?;
+        let _timer = write_timer("metadata");
+        sqlx::query!(
+            "INSERT INTO metadata (url, json_ld, opengraph, microdata) VALUES (?, ?, ?, ?)",
+            url_string,
+            json_ld,
+            opengraph,
+            microdata
+        )
+        .execute(&self.conn)
+        .await
+        .context(format!(
+            "Failed to insert metadata in sqlite db for uri: {url}"
+        ))?;
+        Ok(())
+    }
+
+    async fn add_to_assets(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let url_string = url.to_string();
+        let kind = kind.as_str();
+        let _timer = write_timer("assets");
+        sqlx::query!(
+            "INSERT INTO assets (url, kind, content_type, content) VALUES (?, ?, ?, ?)",
+            url_string,
+            kind,
+            content_type,
+            content
+        )
+        .execute(&self.conn)
+        .await
+        .context(format!(
+            "Failed to insert asset in sqlite db for uri: {url}"
+        ))?;
+        Ok(())
+    }
+
+    async fn add_to_asset_errors(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        msg: String,
+    ) -> anyhow::Result<()> {
+        let url_string = url.to_string();
+        let kind = kind.as_str();
+        let _timer = write_timer("asset_errors");
+        sqlx::query!(
+            "INSERT INTO asset_errors (url, kind, msg) VALUES (?, ?, ?)",
+            url_string,
+            kind,
+            msg
+        )
+        .execute(&self.conn)
+        .await
+        .context(format!(
+            "Failed to insert asset error in sqlite db for uri: {url}"
+        ))?;
+        Ok(())
+    }
+
+    async fn add_to_fingerprints(
+        &self,
+        url: Url,
+        simhash: u64,
+        near_duplicate: bool,
+    ) -> anyhow::Result<()> {
+        let url_string = url.to_string();
+        // sqlite only has signed integers, bits are kept as is
+        let simhash = simhash as i64;
+        let _timer = write_timer("fingerprints");
+        sqlx::query!(
+            "INSERT INTO fingerprints (url, simhash, near_duplicate) VALUES (?, ?, ?)",
+            url_string,
+            simhash,
+            near_duplicate
+        )
+        .execute(&self.conn)
+        .await
+        .context(format!(
+            "Failed to insert fingerprint in sqlite db for uri: {url}"
+        ))?;
+        Ok(())
+    }
+
+    async fn add_to_rendered(&self, url: Url, html: String) -> anyhow::Result<()> {
+        let url_string = url.to_string();
+        let _timer = write_timer("rendered");
+        sqlx::query!(
+            "INSERT INTO rendered (url, content) VALUES (?, ?)",
+            url_string,
+            html
+        )
+        .execute(&self.conn)
+        .await
+        .context(format!(
+            "Failed to insert rendered page in sqlite db for uri: {url}"
+        ))?;
+        Ok(())
+    }
+
+    async fn add_to_truncated(&self, url: Url) -> anyhow::Result<()> {
+        let url_string = url.to_string();
+        let _timer = write_timer("truncated");
+        sqlx::query!("INSERT INTO truncated (url) VALUES (?)", url_string)
+            .execute(&self.conn)
+            .await
+            .context(format!(
+                "Failed to insert truncated url in sqlite db for uri: {url}"
+            ))?;
+        Ok(())
+    }
+
+    async fn add_to_proxies(&self, url: Url, proxy: String) -> anyhow::Result<()> {
+        let url_string = url.to_string();
+        let _timer = write_timer("proxies");
+        sqlx::query!(
+            "INSERT INTO proxies (url, proxy) VALUES (?, ?)",
+            url_string,
+            proxy
+        )
+        .execute(&self.conn)
+        .await
+        .context(format!(
+            "Failed to insert proxy in sqlite db for uri: {url}"
+        ))?;
+        Ok(())
+    }
+
+    async fn add_to_page_data(&self, url: Url, data: &Map<String, Value>) -> anyhow::Result<()> {
+        let url_string = url.to_string();
+        let data = serde_json::to_string(data)?;
+        let _timer = write_timer("page_data");
+        sqlx::query!(
+            "INSERT INTO page_data (url, data) VALUES (?, ?)",
+            url_string,
+            data
+        )
+        .execute(&self.conn)
+        .await
+        .context(format!(
+            "Failed to insert page data in sqlite db for uri: {url}"
+        ))?;
+        Ok(())
+    }
+
+    async fn add_to_sessions(&self, session: &Session) -> anyhow::Result<()> {
+        let started = unix_time(session.started) as i64;
+        let ended = unix_time(session.ended) as i64;
+        let exit_reason = session.exit_reason.as_str();
+        let pages = session.pages as i64;
+        let assets = session.assets as i64;
+        let errors = session.errors as i64;
+        let bytes = session.bytes as i64;
+        let frontier = session.frontier as i64;
+        let _timer = write_timer("sessions");
+        sqlx::query!(
+            "INSERT INTO sessions (started, ended, exit_reason, pages, assets, errors, bytes, frontier)
+            VALUES (datetime(?, 'unixepoch'), datetime(?, 'unixepoch'), ?, ?, ?, ?, ?, ?)",
+            started,
+            ended,
+            exit_reason,
+            pages,
+            assets,
+            errors,
+            bytes,
+            frontier
+        )
+        .execute(&self.conn)
+        .await
+        .context("Failed to insert session in sqlite db")?;
+        Ok(())
+    }
+
+    /// Single transaction with one multi-row INSERT per table
+    async fn write_batch(&self, batch: Vec<Write>) -> anyhow::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut links = vec![];
+        let mut results = vec![];
+        let mut errors = vec![];
+        let mut metadata = vec![];
+        let mut assets = vec![];
+        let mut asset_errors = vec![];
+        let mut aliases = vec![];
+        let mut skipped = vec![];
+        let mut fingerprints = vec![];
+        let mut rendered = vec![];
+        let mut truncated = vec![];
+        let mut proxies = vec![];
+        let mut page_data = vec![];
+        let mut sessions = vec![];
+        for write in batch {
+            match write {
+                Write::Link(url) => links.push(url.to_string()),
+                Write::Result(url, html) => results.push((url.to_string(), html)),
+                Write::Error(url, msg) => errors.push((url.to_string(), msg)),
+                Write::Metadata(url, x) => metadata.push((
+                    url.to_string(),
+                    serde_json::to_string(&x.json_ld)?,
+                    serde_json::to_string(&x.opengraph)?,
+                    serde_json::to_string(&x.microdata)?,
+                )),
+                Write::Asset {
+                    url,
+                    kind,
+                    content_type,
+                    content,
+                } => assets.push((url.to_string(), kind.as_str(), content_type, content)),
+                Write::AssetError(url, kind, msg) => {
+                    asset_errors.push((url.to_string(), kind.as_str(), msg))
+                }
+                Write::Alias(original, url) => {
+                    aliases.push((original.to_string(), url.to_string()))
+                }
+                Write::Skipped(url, reason) => skipped.push((url.to_string(), reason)),
+                Write::Fingerprint {
+                    url,
+                    simhash,
+                    near_duplicate,
+                } => fingerprints.push((url.to_string(), simhash as i64, near_duplicate)),
+                Write::Rendered(url, html) => rendered.push((url.to_string(), html)),
+                Write::Truncated(url) => truncated.push(url.to_string()),
+                Write::Proxy(url, proxy) => proxies.push((url.to_string(), proxy)),
+                Write::PageData(url, data) => {
+                    page_data.push((url.to_string(), serde_json::to_string(&data)?))
+                }
+                // once per run, not worth batching
+                Write::Session(x) => sessions.push(x),
+            }
+        }
+
+        let _timer = write_timer("batch");
+        let mut tx = self.conn.begin().await?;
+        insert_rows(&mut tx, "INSERT INTO links (url) ", links, |mut b, url| {
+            b.push_bind(url);
+        })
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO results (url, content) ",
+            results,
+            |mut b, (url, html)| {
+                b.push_bind(url).push_bind(html);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO errors (url, msg) ",
+            errors,
+            |mut b, (url, msg)| {
+                b.push_bind(url).push_bind(msg);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO metadata (url, json_ld, opengraph, microdata) ",
+            metadata,
+            |mut b, (url, json_ld, opengraph, microdata)| {
+                b.push_bind(url)
+                    .push_bind(json_ld)
+                    .push_bind(opengraph)
+                    .push_bind(microdata);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO assets (url, kind, content_type, content) ",
+            assets,
+            |mut b, (url, kind, content_type, content)| {
+                b.push_bind(url)
+                    .push_bind(kind)
+                    .push_bind(content_type)
+                    .push_bind(content);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO asset_errors (url, kind, msg) ",
+            asset_errors,
+            |mut b, (url, kind, msg)| {
+                b.push_bind(url).push_bind(kind).push_bind(msg);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO aliases (original_url, url) ",
+            aliases,
+            |mut b, (original, url)| {
+                b.push_bind(original).push_bind(url);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO skipped (url, reason) ",
+            skipped,
+            |mut b, (url, reason)| {
+                b.push_bind(url).push_bind(reason);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO fingerprints (url, simhash, near_duplicate) ",
+            fingerprints,
+            |mut b, (url, simhash, near_duplicate)| {
+                b.push_bind(url)
+                    .push_bind(simhash)
+                    .push_bind(near_duplicate);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO rendered (url, content) ",
+            rendered,
+            |mut b, (url, html)| {
+                b.push_bind(url).push_bind(html);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO truncated (url) ",
+            truncated,
+            |mut b, url| {
+                b.push_bind(url);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO proxies (url, proxy) ",
+            proxies,
+            |mut b, (url, proxy)| {
+                b.push_bind(url).push_bind(proxy);
+            },
+        )
+        .await?;
+        insert_rows(
+            &mut tx,
+            "INSERT INTO page_data (url, data) ",
+            page_data,
+            |mut b, (url, data)| {
+                b.push_bind(url).push_bind(data);
+            },
+        )
+        .await?;
+        tx.commit().await?;
+
+        for session in sessions {
+            self.add_to_sessions(&session).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_unprocessed_links(&self) -> anyhow::Result<Vec<Url>> {
         let results = sqlx::query!(
             "
 			SELECT url FROM links
 			WHERE
 				url NOT IN (SELECT url FROM results) AND
-				url NOT IN (SELECT url FROM errors)
+				url NOT IN (SELECT url FROM errors) AND
+				url NOT IN (SELECT url FROM skipped)
 			ORDER BY time",
         )
         .fetch_all(&self.conn)
@@ -0,0 +1,186 @@
+//! Versioned schema of sqlite output files, so files written by older waper stay usable.
+//!
+//! Applied versions are recorded in `schema_version` table. Files from before versioning
+//! have no such table and are treated as version 1, which is why the first migrations
+//! use `IF NOT EXISTS`. New migrations are only ever appended to [`MIGRATIONS`], along with
+//! one for postgres in `PostgresStorage::MIGRATIONS`.
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use sqlx::sqlite::SqlitePool;
+
+use crate::prelude::*;
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub(crate) sql: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "results, errors and links",
+        sql: include_str!("../../sqls/migrations/0001_baseline.sql"),
+    },
+    Migration {
+        version: 2,
+        description:
+            "metadata, assets, aliases, skipped, fingerprints, truncated, page_data and sessions",
+        sql: include_str!("../../sqls/migrations/0002_crawl_data.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "rendered",
+        sql: include_str!("../../sqls/migrations/0003_rendered.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "proxies",
+        sql: include_str!("../../sqls/migrations/0004_proxies.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "asset_errors",
+        sql: include_str!("../../sqls/migrations/0005_asset_errors.sql"),
+    },
+];
+
+/// Version this waper writes
+pub const LATEST_VERSION: i64 = MIGRATIONS.len() as i64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaStatus {
+    UpToDate(i64),
+    /// Upgraded automatically on open
+    Outdated {
+        version: i64,
+    },
+    /// Written by a newer waper, refused
+    Newer {
+        version: i64,
+    },
+}
+
+impl SchemaStatus {
+    pub(crate) fn new(version: i64) -> Self {
+        match version.cmp(&LATEST_VERSION) {
+            std::cmp::Ordering::Equal => SchemaStatus::UpToDate(version),
+            std::cmp::Ordering::Less => SchemaStatus::Outdated { version },
+            std::cmp::Ordering::Greater => SchemaStatus::Newer { version },
+        }
+    }
+}
+
+impl fmt::Display for SchemaStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaStatus::UpToDate(x) => write!(f, "schema version {x}, up to date"),
+            SchemaStatus::Outdated { version } => write!(
+                f,
+                "schema version {version}, needs migration to {LATEST_VERSION}"
+            ),
+            SchemaStatus::Newer { version } => write!(
+                f,
+                "schema version {version} is newer than {LATEST_VERSION} supported by this waper, upgrade waper"
+            ),
+        }
+    }
+}
+
+/// 0 for an empty file
+pub async fn version(conn: &SqlitePool) -> anyhow::Result<i64> {
+    let tables: Vec<(String,)> = sqlx::query_as(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name IN ('schema_version', 'results')",
+    )
+    .fetch_all(conn)
+    .await
+    .context("Failed to read sqlite file tables")?;
+
+    if tables.iter().any(|(name,)| name == "schema_version") {
+        let (version,): (i64,) =
+            sqlx::query_as("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+                .fetch_one(conn)
+                .await
+                .context("Failed to read schema version")?;
+        Ok(version)
+    } else if tables.is_empty() {
+        Ok(0)
+    } else {
+        // written before versioning
+        Ok(1)
+    }
+}
+
+pub async fn status(conn: &SqlitePool) -> anyhow::Result<SchemaStatus> {
+    Ok(SchemaStatus::new(version(conn).await?))
+}
+
+/// Apply pending migrations to the file at `path`. Existing data is first copied to
+/// `<path>.v<version>.bak`, the path of the copy is returned.
+pub async fn upgrade(conn: &SqlitePool, path: &Path) -> anyhow::Result<Option<PathBuf>> {
+    let status = status(conn).await?;
+    let current = match status {
+        SchemaStatus::UpToDate(_) => return Ok(None),
+        SchemaStatus::Newer { .. } => anyhow::bail!("Can't open {}: {}", path.display(), status),
+        SchemaStatus::Outdated { version } => version,
+    };
+
+    let backup = if current > 0 {
+        Some(backup(conn, path, current).await?)
+    } else {
+        None
+    };
+
+    for migration in MIGRATIONS.iter().filter(|x| x.version > current) {
+        let mut tx = conn.begin().await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS schema_version (
+              version INTEGER NOT NULL PRIMARY KEY,
+              time TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+        )
+        .execute(&mut tx)
+        .await?;
+        sqlx::query(migration.sql)
+            .execute(&mut tx)
+            .await
+            .with_context(|| format!("Failed to apply schema migration {}", migration.version))?;
+        sqlx::query("INSERT INTO schema_version (version) VALUES (?)")
+            .bind(migration.version)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        if current > 0 {
+            info!(
+                "Migrated {} to schema version {}: {}",
+                path.display(),
+                migration.version,
+                migration.description
+            );
+        }
+    }
+    Ok(backup)
+}
+
+async fn backup(conn: &SqlitePool, path: &Path, version: i64) -> anyhow::Result<PathBuf> {
+    let mut backup = PathBuf::from(format!("{}.v{version}.bak", path.display()));
+    let mut n = 1;
+    while backup.exists() {
+        backup = PathBuf::from(format!("{}.v{version}.{n}.bak", path.display()));
+        n += 1;
+    }
+    let target = backup
+        .to_str()
+        .context("Backup path is not valid unicode")?
+        .to_string();
+    // consistent copy even with WAL journal
+    sqlx::query("VACUUM INTO ?")
+        .bind(target)
+        .execute(conn)
+        .await
+        .with_context(|| format!("Failed to back up sqlite file to {}", backup.display()))?;
+    info!("Backed up {} to {}", path.display(), backup.display());
+    Ok(backup)
+}
@@ -0,0 +1,203 @@
+//! Reading responses without trusting the server: bodies are streamed and size limited,
+//! pages which are not html are not downloaded at all.
+use std::fmt;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::assets::{self, AssetKind, AssetResult};
+use crate::scraper::{self, ScrapingResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Oversize {
+    /// Stop reading and record the url in `skipped` table
+    Abort,
+    /// Keep the first `max_body_size` bytes and record the url in `truncated` table
+    Truncate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchConfig {
+    /// Bodies larger than this are handled according to `oversize`, `None` reads everything
+    pub max_body_size: Option<usize>,
+    pub oversize: Oversize,
+    /// Max wait for a connection to be established. Only read on `Orchestrator` creation.
+    pub connect_timeout: Duration,
+    /// Max wait between two chunks of body
+    pub read_timeout: Duration,
+    /// Max duration of whole request, including body. Only read on `Orchestrator` creation.
+    pub total_timeout: Duration,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            max_body_size: Some(10 * 1024 * 1024),
+            oversize: Oversize::Abort,
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(10),
+            total_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl FetchConfig {
+    pub fn client(&self) -> reqwest::Result<reqwest::Client> {
+        self.client_builder().build()
+    }
+
+    /// Client sending all requests through `proxy` (`http://`, `https://` or `socks5://`)
+    pub fn proxied_client(&self, proxy: &Url) -> reqwest::Result<reqwest::Client> {
+        self.client_builder()
+            .proxy(reqwest::Proxy::all(proxy.as_str())?)
+            .build()
+    }
+
+    fn client_builder(&self) -> reqwest::ClientBuilder {
+        reqwest::ClientBuilder::new()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.total_timeout)
+    }
+}
+
+/// Makes the requests of an [`crate::Orchestrator`]. A [`reqwest::Client`] fetches them itself,
+/// [`crate::proxy::ProxyPool`] through one of its proxies and [`crate::cluster::Coordinator`]
+/// hands them to workers on other machines.
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    /// Fails with [`Rejected`] if the page is not html or is too large
+    async fn page(&self, url: &Url, config: &FetchConfig) -> anyhow::Result<ScrapingResult>;
+
+    async fn asset(
+        &self,
+        url: &Url,
+        kind: AssetKind,
+        discover_js_urls: bool,
+        config: &FetchConfig,
+    ) -> anyhow::Result<AssetResult>;
+}
+
+#[async_trait]
+impl Fetcher for reqwest::Client {
+    async fn page(&self, url: &Url, config: &FetchConfig) -> anyhow::Result<ScrapingResult> {
+        scraper::scrap_links(url, self.clone(), config).await
+    }
+
+    async fn asset(
+        &self,
+        url: &Url,
+        kind: AssetKind,
+        discover_js_urls: bool,
+        config: &FetchConfig,
+    ) -> anyhow::Result<AssetResult> {
+        assets::fetch_asset(url, kind, self.clone(), discover_js_urls, config).await
+    }
+}
+
+/// Response was deliberately not read. Not an error of the server,
+/// so it's recorded in `skipped` table instead of `errors`.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Rejected {
+    ContentType(String),
+    TooLarge(usize),
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejected::ContentType(x) => write!(f, "content type {x} is not html"),
+            Rejected::TooLarge(x) => write!(f, "body larger than {x} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Server stopped sending the body for longer than [`FetchConfig::read_timeout`]
+#[derive(Debug)]
+pub struct ReadTimeout(pub Duration);
+
+impl fmt::Display for ReadTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No data received for {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ReadTimeout {}
+
+pub struct Body {
+    pub content: Vec<u8>,
+    /// Only set with [`Oversize::Truncate`]
+    pub truncated: bool,
+}
+
+pub fn content_type(headers: &HeaderMap) -> Option<&str> {
+    headers.get(CONTENT_TYPE).and_then(|x| x.to_str().ok())
+}
+
+/// Pages without a content type are assumed to be html
+pub fn check_html(headers: &HeaderMap) -> Result<(), Rejected> {
+    let Some(content_type) = content_type(headers) else {
+        return Ok(());
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if essence == "text/html" || essence == "application/xhtml+xml" {
+        Ok(())
+    } else {
+        Err(Rejected::ContentType(essence))
+    }
+}
+
+/// Stream the body chunk by chunk, so an endless or huge response can't exhaust memory
+pub async fn read_body(
+    mut response: reqwest::Response,
+    config: &FetchConfig,
+) -> anyhow::Result<Body> {
+    if let (Some(max), Some(length)) = (config.max_body_size, response.content_length()) {
+        if length > max as u64 && config.oversize == Oversize::Abort {
+            return Err(Rejected::TooLarge(max).into());
+        }
+    }
+
+    let mut content = vec![];
+    let mut truncated = false;
+    while let Some(chunk) = tokio::time::timeout(config.read_timeout, response.chunk())
+        .await
+        .map_err(|_| ReadTimeout(config.read_timeout))??
+    {
+        content.extend_from_slice(&chunk);
+        if let Some(max) = config.max_body_size.filter(|x| content.len() > *x) {
+            match config.oversize {
+                Oversize::Abort => return Err(Rejected::TooLarge(max).into()),
+                Oversize::Truncate => {
+                    content.truncate(max);
+                    truncated = true;
+                    break;
+                }
+            }
+        }
+    }
+    Ok(Body { content, truncated })
+}
+
+/// Decode using charset from content type, utf-8 if it's missing or unknown
+pub fn decode_text(content: &[u8], headers: &HeaderMap) -> String {
+    let encoding = content_type(headers)
+        .and_then(|x| {
+            x.split(';')
+                .filter_map(|param| param.trim().split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        })
+        .and_then(|(_, value)| encoding_rs::Encoding::for_label(value.trim_matches('"').as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    let (text, _, _) = encoding.decode(content);
+    text.into_owned()
+}
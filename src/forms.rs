@@ -0,0 +1,298 @@
+//! Following GET forms (search, filters) without a browser. A [`FormRule`] picks forms on some pages
+//! and the values to submit, each combination of values becomes a url which is queued like a link.
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+use regex::Regex;
+use select::document::Document;
+use select::node::Node;
+use select::predicate::{Name, Predicate};
+use url::Url;
+
+/// Compound css selector: `tag`, `#id`, `.class`, `[attr]` and `[attr=value]` in any combination,
+/// e.g. `form#search` or `form.filters[action="/list"]`. Combinators (`div form`) are not supported.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attrs: Vec<(String, Option<String>)>,
+}
+
+impl Selector {
+    pub fn matches(&self, node: &Node) -> bool {
+        let Some(name) = node.name() else {
+            return false;
+        };
+        if self
+            .tag
+            .as_ref()
+            .is_some_and(|x| !x.eq_ignore_ascii_case(name))
+        {
+            return false;
+        }
+        if self.id.is_some() && node.attr("id") != self.id.as_deref() {
+            return false;
+        }
+        let classes = node.attr("class").unwrap_or_default();
+        if !self
+            .classes
+            .iter()
+            .all(|x| classes.split_whitespace().any(|y| y == x))
+        {
+            return false;
+        }
+        self.attrs
+            .iter()
+            .all(|(name, value)| match (node.attr(name), value) {
+                (Some(_), None) => true,
+                (Some(x), Some(value)) => x == value,
+                (None, _) => false,
+            })
+    }
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// Leading identifier of `s` and the rest
+fn ident(s: &str) -> (&str, &str) {
+    s.split_at(s.find(|c| !is_ident(c)).unwrap_or(s.len()))
+}
+
+impl FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unsupported = || {
+            anyhow::anyhow!(
+                "Unsupported selector {s:?}, only tag, #id, .class and [attr=value] are supported"
+            )
+        };
+        let mut selector = Selector::default();
+        let (tag, mut rest) = match s.trim().strip_prefix('*') {
+            Some(rest) => ("", rest),
+            None => ident(s.trim()),
+        };
+        if !tag.is_empty() {
+            selector.tag = Some(tag.to_string());
+        }
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '#' | '.' => {
+                    let (name, after) = ident(rest);
+                    if name.is_empty() {
+                        return Err(unsupported());
+                    }
+                    if c == '#' {
+                        selector.id = Some(name.to_string());
+                    } else {
+                        selector.classes.push(name.to_string());
+                    }
+                    rest = after;
+                }
+                '[' => {
+                    let (inner, after) = rest.split_once(']').ok_or_else(unsupported)?;
+                    let attr = match inner.split_once('=') {
+                        Some((name, value)) => {
+                            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+                            (name.trim(), Some(value.to_string()))
+                        }
+                        None => (inner.trim(), None),
+                    };
+                    if attr.0.is_empty() || !attr.0.chars().all(is_ident) {
+                        return Err(unsupported());
+                    }
+                    selector.attrs.push((attr.0.to_string(), attr.1));
+                    rest = after;
+                }
+                _ => return Err(unsupported()),
+            }
+        }
+        Ok(selector)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FormRule {
+    /// Only forms on pages whose url matches
+    pub url: Regex,
+    pub selector: Selector,
+    /// Values submitted for each field, every combination is tried.
+    /// Other fields of the form keep their value from html.
+    pub fields: Vec<(String, Vec<String>)>,
+    /// Max urls made from one form, combinations after it are dropped
+    pub max_urls: usize,
+}
+
+impl FormRule {
+    /// `<url regex> <selector> <field>=<values>...` where values are comma separated
+    /// or `@file` to read one value per line, e.g. `"https://example.com/.* form#search q=@words.txt sort=new,old"`
+    pub fn parse(spec: &str, max_urls: usize) -> anyhow::Result<Self> {
+        let parts = shlex::split(spec).filter(|x| x.len() >= 2).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Invalid form rule: {spec}, expected <url regex> <selector> <field>=<values>..."
+            )
+        })?;
+        let url = Regex::new(&parts[0])?;
+        let selector = parts[1].parse()?;
+        let fields = parts[2..]
+            .iter()
+            .map(|field| {
+                let (name, values) = field.split_once('=').ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Invalid field {field:?} in form rule, expected <field>=<values>"
+                    )
+                })?;
+                let values = match values.strip_prefix('@') {
+                    Some(path) => read_wordlist(path.as_ref())?,
+                    None => values.split(',').map(|x| x.to_string()).collect(),
+                };
+                Ok((name.to_string(), values))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            url,
+            selector,
+            fields,
+            max_urls,
+        })
+    }
+
+    /// Urls of submitting matching forms of the page at `url`, with every combination of values
+    fn urls(&self, url: &Url, document: &Document, urls: &mut Vec<Url>) {
+        if self.fields.iter().any(|(_, values)| values.is_empty()) {
+            // no combination at all
+            return;
+        }
+        let forms = document
+            .find(Name("form"))
+            .filter(|x| self.selector.matches(x))
+            // POST forms change things, or at least aren't addressable by url
+            .filter(|x| {
+                x.attr("method")
+                    .is_none_or(|x| x.trim().eq_ignore_ascii_case("get"))
+            });
+        for form in forms {
+            let Ok(mut action) = url.join(form.attr("action").unwrap_or_default()) else {
+                continue;
+            };
+            action.set_fragment(None);
+            let defaults = form_fields(&form);
+            let mut indices = vec![0; self.fields.len()];
+            let mut made = 0;
+            while made < self.max_urls {
+                let mut fields = defaults.clone();
+                for ((name, values), i) in self.fields.iter().zip(&indices) {
+                    set_field(&mut fields, name, &values[*i]);
+                }
+                let mut submitted = action.clone();
+                submitted.query_pairs_mut().clear().extend_pairs(fields);
+                urls.push(submitted);
+                made += 1;
+
+                // next combination, last field changing fastest
+                let Some(i) = (0..indices.len())
+                    .rev()
+                    .find(|&i| indices[i] + 1 < self.fields[i].1.len())
+                else {
+                    break;
+                };
+                indices[i] += 1;
+                indices[i + 1..].iter_mut().for_each(|x| *x = 0);
+            }
+        }
+    }
+}
+
+fn read_wordlist(path: &Path) -> anyhow::Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read wordlist {}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect())
+}
+
+/// (name, value) a browser would submit without touching the form, except for submit buttons
+fn form_fields(form: &Node) -> Vec<(String, String)> {
+    let mut fields = vec![];
+    for field in form.find(Name("input").or(Name("select")).or(Name("textarea"))) {
+        let Some(name) = field.attr("name").filter(|x| !x.is_empty()) else {
+            continue;
+        };
+        if field.attr("disabled").is_some() {
+            continue;
+        }
+        let value = match field.name() {
+            Some("select") => {
+                let mut options = field.find(Name("option"));
+                let selected = field
+                    .find(Name("option"))
+                    .find(|x| x.attr("selected").is_some())
+                    .or_else(|| options.next());
+                match selected {
+                    Some(option) => option
+                        .attr("value")
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| option.text().trim().to_string()),
+                    None => continue,
+                }
+            }
+            Some("textarea") => field.text(),
+            _ => {
+                let kind = field.attr("type").unwrap_or("text").to_ascii_lowercase();
+                match kind.as_str() {
+                    "submit" | "button" | "image" | "reset" | "file" => continue,
+                    "checkbox" | "radio" if field.attr("checked").is_none() => continue,
+                    "checkbox" | "radio" => field.attr("value").unwrap_or("on").to_string(),
+                    _ => field.attr("value").unwrap_or_default().to_string(),
+                }
+            }
+        };
+        fields.push((name.to_string(), value));
+    }
+    fields
+}
+
+/// Replace first field called `name` with `value`, or add it if the form has none
+fn set_field(fields: &mut Vec<(String, String)>, name: &str, value: &str) {
+    let mut found = false;
+    fields.retain_mut(|(field, current)| {
+        if field != name {
+            return true;
+        }
+        if found {
+            return false;
+        }
+        found = true;
+        *current = value.to_string();
+        true
+    });
+    if !found {
+        fields.push((name.to_string(), value.to_string()));
+    }
+}
+
+/// Urls of submitting forms of the page at `url` according to `rules`, html is only parsed
+/// if a rule applies to the page
+pub fn form_urls(rules: &[FormRule], url: &Url, html: &str) -> Vec<Url> {
+    let mut rules = rules
+        .iter()
+        .filter(|x| x.url.is_match(url.as_str()))
+        .peekable();
+    if rules.peek().is_none() {
+        return vec![];
+    }
+    let document = Document::from(html);
+    let mut urls = vec![];
+    for rule in rules {
+        rule.urls(url, &document, &mut urls);
+    }
+    urls
+}
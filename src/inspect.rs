@@ -0,0 +1,53 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::cli::{InspectArgs, InspectCommand};
+use waper::db::Database;
+use waper::simhash::SimHashIndex;
+
+pub async fn inspect(args: InspectArgs) -> anyhow::Result<()> {
+    let db = Database::connect(&args.input_file).await?;
+    match args.command {
+        InspectCommand::Duplicates { distance } => duplicates(&db, distance).await,
+    }
+}
+
+/// Prints each page followed by the later pages which are near-duplicates of it
+async fn duplicates(db: &Database, distance: u32) -> anyhow::Result<()> {
+    let fingerprints = db.get_fingerprints().await?;
+    let mut index = SimHashIndex::new(distance);
+    // first page of the group => [(duplicate page, distance)]
+    let mut groups: BTreeMap<usize, Vec<(usize, u32)>> = BTreeMap::new();
+    for (idx, (_, fingerprint)) in fingerprints.iter().enumerate() {
+        match index.find(*fingerprint) {
+            Some((original, d)) => groups.entry(*original).or_default().push((idx, d)),
+            None => index.insert(*fingerprint, idx),
+        }
+    }
+
+    let mut stdout = io::stdout().lock();
+    let mut total = 0;
+    for (original, duplicates) in &groups {
+        writeln!(
+            stdout,
+            "{} ({} near-duplicates)",
+            fingerprints[*original].0,
+            duplicates.len()
+        )?;
+        for (duplicate, d) in duplicates {
+            writeln!(
+                stdout,
+                "    {} (distance {})",
+                fingerprints[*duplicate].0, d
+            )?;
+        }
+        total += duplicates.len();
+    }
+    writeln!(
+        stdout,
+        "{} of {} pages are near-duplicates of another page",
+        total,
+        fingerprints.len()
+    )?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+//! Running scripts of a page in an embedded QuickJS, to discover urls which are only built in JavaScript
+//! (`location.href = ...`, `fetch(...)`, links added with `innerHTML`). Much cheaper than [`crate::render`],
+//! but the DOM is a shim: scripts don't see the page's elements and responses to their requests never arrive.
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use rquickjs::{Context, Ctx, Runtime};
+use select::predicate::Name;
+use serde::Deserialize;
+use url::Url;
+
+use crate::fetch::{self, FetchConfig};
+use crate::pipeline::{Outcome, Page, PageProcessor};
+use crate::prelude::*;
+use crate::scraper;
+
+const SHIM: &str = include_str!("js/shim.js");
+
+/// Promise callbacks run after each script
+const MAX_JOBS: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct JsConfig {
+    /// Max time for running all scripts of a page
+    pub timeout: Duration,
+    /// Memory of the engine, per page
+    pub memory_limit: usize,
+    /// Max same-origin `<script src>` fetched per page
+    pub max_scripts: usize,
+    /// Max timer and load event callbacks run after the scripts
+    pub max_callbacks: usize,
+    /// Used for fetching scripts
+    pub fetch: FetchConfig,
+}
+
+impl Default for JsConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            memory_limit: 32 * 1024 * 1024,
+            max_scripts: 10,
+            max_callbacks: 100,
+            fetch: FetchConfig::default(),
+        }
+    }
+}
+
+/// [`PageProcessor`] adding urls found by running the page's scripts to its links
+pub struct JsEvaluator {
+    config: JsConfig,
+    client: reqwest::Client,
+}
+
+enum Script {
+    Inline(String),
+    External(Url),
+}
+
+#[derive(Deserialize)]
+struct Found {
+    urls: Vec<String>,
+    /// Assigned to `innerHTML` or passed to `document.write`
+    html: Vec<String>,
+}
+
+impl JsEvaluator {
+    /// `client` is used for same-origin external scripts, they are not subject to the rate limit
+    pub fn new(config: JsConfig, client: reqwest::Client) -> Self {
+        Self { config, client }
+    }
+
+    /// Classic scripts in document order, external ones only from the page's origin
+    fn scripts(&self, page: &Page) -> Vec<Script> {
+        let document = select::document::Document::from(page.html.as_str());
+        let mut external = 0;
+        document
+            .find(Name("script"))
+            .filter(|n| {
+                n.attr("type").is_none_or(|x| {
+                    let x = x.to_ascii_lowercase();
+                    x.is_empty() || x.contains("javascript") || x.contains("ecmascript")
+                })
+            })
+            .filter_map(|n| match n.attr("src") {
+                Some(src) => {
+                    let url = page.url.join(src).ok()?;
+                    if url.origin() != page.url.origin() || external >= self.config.max_scripts {
+                        return None;
+                    }
+                    external += 1;
+                    Some(Script::External(url))
+                }
+                None => Some(Script::Inline(n.text())),
+            })
+            .collect()
+    }
+
+    async fn fetch_script(&self, url: &Url) -> anyhow::Result<String> {
+        let response = self
+            .client
+            .get(url.as_str())
+            .send()
+            .await?
+            .error_for_status()?;
+        let headers = response.headers().clone();
+        let body = fetch::read_body(response, &self.config.fetch).await?;
+        anyhow::ensure!(!body.truncated, "script is larger than max body size");
+        Ok(fetch::decode_text(&body.content, &headers))
+    }
+}
+
+#[async_trait]
+impl PageProcessor for JsEvaluator {
+    fn name(&self) -> &str {
+        "js"
+    }
+
+    async fn process(&self, page: &mut Page) -> anyhow::Result<Outcome> {
+        let mut sources = vec![];
+        for script in self.scripts(page) {
+            match script {
+                Script::Inline(x) => sources.push(x),
+                Script::External(url) => match self.fetch_script(&url).await {
+                    Ok(x) => sources.push(x),
+                    Err(e) => debug!("Failed to fetch script {}: {:?}", url, e),
+                },
+            }
+        }
+        if sources.iter().all(|x| x.trim().is_empty()) {
+            return Ok(Outcome::Continue);
+        }
+
+        let url = page.url.clone();
+        let config = self.config.clone();
+        // engine is not Send and scripts can keep a thread busy until the timeout
+        let found =
+            tokio::task::spawn_blocking(move || evaluate(&url, &sources, &config)).await??;
+        debug!("Found {} urls in scripts of {}", found.len(), page.url);
+        page.links.extend(found);
+        Ok(Outcome::Continue)
+    }
+}
+
+/// Run `scripts` of the page at `url` in order and return urls they navigated to, requested or wrote links to
+pub fn evaluate(url: &Url, scripts: &[String], config: &JsConfig) -> anyhow::Result<Vec<Url>> {
+    let runtime = Runtime::new()?;
+    runtime.set_memory_limit(config.memory_limit);
+    runtime.set_max_stack_size(256 * 1024);
+    let deadline = Rc::new(Cell::new(Instant::now() + config.timeout));
+    runtime.set_interrupt_handler(Some(Box::new({
+        let deadline = deadline.clone();
+        move || Instant::now() > deadline.get()
+    })));
+    let context = Context::full(&runtime)?;
+
+    context.with(|ctx| -> anyhow::Result<()> {
+        ctx.eval::<(), _>(format!("globalThis.__waper_page = {};", page_json(url)))?;
+        ctx.eval::<(), _>(SHIM)
+            .map_err(|e| anyhow::anyhow!("{}", exception(&ctx, e)))?;
+        Ok(())
+    })?;
+    for script in scripts {
+        context.with(|ctx| {
+            if let Err(e) = ctx.eval::<(), _>(script.as_str()) {
+                debug!("Script of {} failed: {}", url, exception(&ctx, e));
+            }
+        });
+        for _ in 0..MAX_JOBS {
+            match runtime.execute_pending_job() {
+                Ok(true) | Err(_) => {}
+                Ok(false) => break,
+            }
+        }
+    }
+
+    let found = context.with(|ctx| -> anyhow::Result<String> {
+        let finish = format!("__waper_finish({})", config.max_callbacks);
+        if let Err(e) = ctx.eval::<(), _>(finish.as_str()) {
+            debug!("Callbacks of {} failed: {}", url, exception(&ctx, e));
+        }
+        // whatever was found until the timeout
+        deadline.set(Instant::now() + config.timeout);
+        ctx.eval::<String, _>("__waper_found()")
+            .map_err(|e| anyhow::anyhow!("{}", exception(&ctx, e)))
+    })?;
+    let found: Found = serde_json::from_str(&found)?;
+
+    let mut urls: Vec<Url> = found
+        .urls
+        .iter()
+        .filter_map(|x| url.join(x.trim()).ok())
+        .filter(|x| matches!(x.scheme(), "http" | "https"))
+        .map(|mut x| {
+            x.set_fragment(None);
+            x
+        })
+        .collect();
+    for html in found.html {
+        urls.extend(scraper::parse_page(url, &html).links);
+    }
+    urls.sort();
+    urls.dedup();
+    Ok(urls)
+}
+
+fn page_json(url: &Url) -> serde_json::Value {
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    serde_json::json!({
+        "href": url.as_str(),
+        "origin": url.origin().ascii_serialization(),
+        "protocol": format!("{}:", url.scheme()),
+        "host": host,
+        "hostname": url.host_str().unwrap_or_default(),
+        "port": url.port().map(|x| x.to_string()).unwrap_or_default(),
+        "pathname": url.path(),
+        "search": url.query().map(|x| format!("?{x}")).unwrap_or_default(),
+        "hash": url.fragment().map(|x| format!("#{x}")).unwrap_or_default(),
+    })
+}
+
+fn exception(ctx: &Ctx<'_>, error: rquickjs::Error) -> String {
+    if !error.is_exception() {
+        return error.to_string();
+    }
+    let value = ctx.catch();
+    value
+        .as_exception()
+        .and_then(|x| x.message())
+        .unwrap_or_else(|| format!("{value:?}"))
+}
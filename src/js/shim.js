@@ -0,0 +1,200 @@
+// Just enough of a browser for scripts to run and reveal the urls they build, see `src/js.rs`.
+// `__waper_page` (the page url, parsed) is defined before this runs.
+(function (global) {
+  const urls = [];
+  const html = [];
+  const listeners = [];
+  const timers = [];
+
+  const record = (url) => {
+    if (url === undefined || url === null) return;
+    urls.push(typeof url === "object" && "url" in url ? String(url.url) : String(url));
+  };
+
+  // Absorbs any property access or call, so scripts using apis we don't have keep going
+  const stub = new Proxy(function () {}, {
+    get: (_, key) => {
+      if (key === Symbol.toPrimitive) return () => "";
+      if (key === "then" || typeof key === "symbol") return undefined;
+      return stub;
+    },
+    set: () => true,
+    apply: () => stub,
+    construct: () => stub,
+  });
+  const lenient = (target) =>
+    new Proxy(target, {
+      get: (t, key) => (key in t || typeof key === "symbol" ? t[key] : stub),
+      set: (t, key, value) => {
+        t[key] = value;
+        return true;
+      },
+    });
+
+  const URL_ATTRIBUTES = /^(href|src|action|formaction|data-url|data-href)$/i;
+
+  function element(tag) {
+    const el = {
+      tagName: String(tag || "div").toUpperCase(),
+      style: {},
+      dataset: {},
+      attributes: {},
+      children: [],
+      childNodes: [],
+      setAttribute(name, value) {
+        if (URL_ATTRIBUTES.test(name)) record(value);
+        this.attributes[name] = String(value);
+      },
+      getAttribute(name) {
+        return name in this.attributes ? this.attributes[name] : null;
+      },
+      appendChild: (x) => x,
+      insertBefore: (x) => x,
+      removeChild: (x) => x,
+      append() {},
+      prepend() {},
+      remove() {},
+      addEventListener() {},
+      removeEventListener() {},
+      click() {},
+      submit() {},
+      querySelector: () => element("div"),
+      querySelectorAll: () => [],
+      getElementsByTagName: () => [],
+      getElementsByClassName: () => [],
+    };
+    for (const name of ["href", "src", "action"]) {
+      Object.defineProperty(el, name, {
+        get: () => el.attributes[name] || "",
+        set: (value) => el.setAttribute(name, value),
+      });
+    }
+    for (const name of ["innerHTML", "outerHTML"]) {
+      Object.defineProperty(el, name, {
+        get: () => "",
+        set: (value) => html.push(String(value)),
+      });
+    }
+    el.insertAdjacentHTML = (_, value) => html.push(String(value));
+    return lenient(el);
+  }
+
+  const page = global.__waper_page;
+  const location = lenient({
+    ...page,
+    assign: record,
+    replace: record,
+    reload() {},
+    toString: () => page.href,
+  });
+  Object.defineProperty(location, "href", { get: () => page.href, set: record });
+
+  const addEventListener = (type, listener) => {
+    if (typeof listener === "function" && /^(DOMContentLoaded|load|readystatechange)$/.test(type)) {
+      listeners.push(listener);
+    }
+  };
+
+  const body = element("body");
+  const document = lenient({
+    readyState: "loading",
+    cookie: "",
+    title: "",
+    referrer: "",
+    body,
+    head: element("head"),
+    documentElement: element("html"),
+    createElement: element,
+    createElementNS: (_, tag) => element(tag),
+    createTextNode: () => element("#text"),
+    createDocumentFragment: () => element("#fragment"),
+    getElementById: () => element("div"),
+    querySelector: () => element("div"),
+    querySelectorAll: () => [],
+    getElementsByTagName: () => [],
+    getElementsByClassName: () => [],
+    getElementsByName: () => [],
+    write: (...parts) => html.push(parts.join("")),
+    writeln: (...parts) => html.push(parts.join("")),
+    addEventListener,
+    removeEventListener() {},
+  });
+  Object.defineProperty(document, "location", { get: () => location, set: record });
+
+  const storage = () =>
+    lenient({
+      getItem: () => null,
+      setItem() {},
+      removeItem() {},
+      clear() {},
+    });
+
+  const schedule = (fn) => {
+    if (typeof fn === "function") timers.push(fn);
+    return timers.length;
+  };
+
+  class XMLHttpRequest {
+    open(_, url) {
+      record(url);
+    }
+    send() {}
+    abort() {}
+    setRequestHeader() {}
+    addEventListener() {}
+  }
+
+  Object.defineProperty(global, "location", { get: () => location, set: record });
+  Object.assign(global, {
+    window: global,
+    self: global,
+    top: global,
+    parent: global,
+    document,
+    navigator: lenient({ userAgent: "Mozilla/5.0 (compatible; waper)", language: "en", languages: ["en"] }),
+    history: lenient({
+      pushState: (_, __, url) => record(url),
+      replaceState: (_, __, url) => record(url),
+      back() {},
+      forward() {},
+    }),
+    console: lenient({ log() {}, info() {}, warn() {}, error() {}, debug() {} }),
+    localStorage: storage(),
+    sessionStorage: storage(),
+    fetch: (url) => {
+      record(url);
+      // responses never arrive, code waiting for them doesn't run
+      return new Promise(() => {});
+    },
+    XMLHttpRequest,
+    Image: function () {
+      return element("img");
+    },
+    open: (url) => record(url),
+    addEventListener,
+    removeEventListener() {},
+    setTimeout: schedule,
+    setInterval: schedule,
+    requestAnimationFrame: schedule,
+    requestIdleCallback: schedule,
+    clearTimeout() {},
+    clearInterval() {},
+    cancelAnimationFrame() {},
+    matchMedia: () => lenient({ matches: false, addListener() {}, addEventListener() {} }),
+    getComputedStyle: () => lenient({}),
+  });
+
+  // Run after all scripts: load handlers, then timers (which may schedule more, up to a limit)
+  global.__waper_finish = (maxCallbacks) => {
+    document.readyState = "complete";
+    const run = (fn) => {
+      try {
+        fn();
+      } catch (e) {}
+    };
+    listeners.forEach(run);
+    let n = 0;
+    while (timers.length && n++ < maxCallbacks) run(timers.shift());
+  };
+  global.__waper_found = () => JSON.stringify({ urls, html });
+})(globalThis);
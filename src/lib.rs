@@ -0,0 +1,61 @@
+//! Waper as a library: scrape html websites from your own tokio services.
+//!
+//! ```no_run
+//! use waper::{Database, Orchestrator, RuntimeConfig};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let db = Database::connect("waper_out.sqlite".as_ref()).await?;
+//! let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+//! let mut orchestrator = Orchestrator::builder(db)
+//!     .seeds(["https://example.com/".parse()?])
+//!     .config(RuntimeConfig::default().with_whitelist(["https://example.com/.*"])?)
+//!     .events(events_tx)
+//!     .build()?;
+//!
+//! tokio::spawn(async move {
+//!     while let Some(event) = events.recv().await {
+//!         println!("{event:?}");
+//!     }
+//! });
+//! orchestrator.start(false).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Everything scraped is also stored in a [`Storage`], [`Database`] (sqlite) is the default.
+//! See `sqls/migrations` for the schema, older files are upgraded on open (see [`db::migrate`]).
+
+pub mod assets;
+pub mod cluster;
+pub mod db;
+pub mod fetch;
+pub mod forms;
+#[cfg(feature = "js")]
+pub mod js;
+pub mod metadata;
+pub mod metrics;
+pub mod mirror;
+pub mod normalize;
+pub mod orchestrator;
+pub mod outage;
+pub mod pipeline;
+mod prelude;
+pub mod proxy;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod scraper;
+pub mod seen;
+pub mod server;
+pub mod session;
+pub mod simhash;
+pub mod storage;
+pub mod trap;
+
+pub use db::Database;
+pub use orchestrator::{
+    CrawlControl, CrawlEvent, Filter, Orchestrator, OrchestratorBuilder, RateLimit, Resource,
+    RuntimeConfig,
+};
+pub use scraper::{scrap_links, ScrapingResult};
+pub use session::{ExitReason, Session, Stats, StopHandle};
+pub use storage::Storage;
@@ -1,14 +1,47 @@
-use tracing::Level;
-use tracing_subscriber::FmtSubscriber;
-
-pub fn init_logging(level: Level) {
-    // a builder for `FmtSubscriber`.
-    let subscriber = FmtSubscriber::builder()
-        // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
-        // will be written to stdout.
-        .with_max_level(level)
-        // completes the builder.
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+use std::fs::OpenOptions;
+use std::sync::Arc;
+
+use anyhow::Context;
+use tracing_subscriber::fmt::time::ChronoUtc;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
+
+use crate::cli::{LogArgs, LogFormat};
+
+pub fn init_logging(args: &LogArgs) -> anyhow::Result<()> {
+    let filter = match &args.log_filter {
+        Some(x) => EnvFilter::try_new(x).context("Invalid log filter")?,
+        None if args.verbose => EnvFilter::new("debug"),
+        None => EnvFilter::new("info"),
+    };
+    // logs are written to stderr, stdout is kept for data (`--output -`).
+    let writer = match &args.log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?;
+            BoxMakeWriter::new(Arc::new(file))
+        }
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
+    let builder = FmtSubscriber::builder()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        // no escape codes in files
+        .with_ansi(args.log_file.is_none());
+
+    match args.log_format {
+        LogFormat::Text => tracing::subscriber::set_global_default(builder.finish()),
+        LogFormat::Json => tracing::subscriber::set_global_default(
+            builder
+                .with_timer(ChronoUtc::rfc3339())
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .finish(),
+        ),
+    }
+    .context("setting default subscriber failed")
 }
@@ -1,74 +1,423 @@
 #![doc = include_str!("../README.md")]
 
 mod cli;
-mod db;
+mod inspect;
 mod log;
-mod orchestrator;
-mod prelude;
-mod scraper;
 
 use clap::{CommandFactory, Parser};
-use cli::{Args, Command};
-use db::Database;
-use parking_lot::Mutex;
+use cli::{
+    Args, Command, ConfigArgs, DbArgs, DbCommand, ExportArgs, ExportKind, LogArgs, MirrorArgs,
+    ProxyArgs, ScrapeArgs, SeenKind, ServeArgs, StorageKind, StreamFormat, WorkerArgs,
+};
 use regex::RegexSet;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
-use std::{io, sync::Arc};
-use tracing::{debug, Level};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::signal::unix::SignalKind;
 
-use crate::orchestrator::RuntimeConfig;
+use waper::assets::{AssetConfig, AssetKind};
+use waper::cluster::{Coordinator, Worker, WorkerConfig};
+use waper::db::SchemaStatus;
+use waper::fetch::FetchConfig;
+use waper::forms::FormRule;
+use waper::metrics;
+use waper::normalize::Normalizer;
+use waper::orchestrator::NearDuplicateConfig;
+use waper::orchestrator::OrchestratorBuilder;
+use waper::outage::OutageConfig;
+use waper::pipeline::{CommandProcessor, TextExtractor};
+use waper::proxy::{ProxyConfig, ProxyPool};
+use waper::seen::{BloomSeenSet, SqliteSeenSet};
+use waper::server::Server;
+use waper::session::Limits;
+use waper::storage::{Batched, JsonLinesStorage, Tee};
+use waper::trap::TrapConfig;
+use waper::{Database, ExitReason, RuntimeConfig, Storage};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    if let Some(Command::Completion(shell)) = args.command {
-        let mut cmd = Args::command();
-        let name = cmd.get_name().to_string();
-        clap_complete::generate(shell.shell, &mut cmd, name, &mut io::stdout());
-        return Ok(());
-    }
-
     // If no command is provided or the `scrape` command is provided
     // we want to scrape
-
-    let args = args.scrape_args;
-    if args.verbose {
-        log::init_logging(Level::DEBUG);
-    } else {
-        log::init_logging(Level::INFO);
+    let args = match args.command {
+        Some(Command::Completion(shell)) => {
+            let mut cmd = Args::command();
+            let name = cmd.get_name().to_string();
+            clap_complete::generate(shell.shell, &mut cmd, name, &mut io::stdout());
+            return Ok(());
+        }
+        Some(Command::Export(args)) => return export(args).await,
+        Some(Command::Mirror(args)) => return mirror(args).await,
+        Some(Command::Inspect(args)) => return inspect::inspect(args).await,
+        Some(Command::Db(args)) => return db(args).await,
+        Some(Command::Serve(args)) => return serve(*args).await,
+        Some(Command::Worker(args)) => return worker(*args).await,
+        Some(Command::Scrape(args)) => *args,
+        None => args.scrape_args,
+    };
+    log::init_logging(&args.log)?;
+    let proxy = proxy_config(&args.proxy)?;
+    if proxy.is_some() && args.coordinate.is_some() {
+        anyhow::bail!("With --coordinate, pass --proxy to `waper worker`s instead");
     }
+    let whitelist = RegexSet::new(&args.whitelist).expect("invalid whitelist regexes");
+    let blacklist = RegexSet::new(&args.blacklist).expect("invalid blacklist regexes");
+    let config = runtime_config(
+        RuntimeConfig::new(args.max_parallel_requests.into(), whitelist, blacklist),
+        &args.assets,
+        &args.config,
+    )?;
+    if let Some(addr) = args.metrics_addr {
+        metrics::spawn_server(addr)?;
+    }
+    let db = Arc::new(Batched::new(
+        open_storage(&args).await?,
+        args.write_batch_size,
+        args.write_queue_size,
+    ));
     let src: Vec<_> = args
         .seed_links
         .into_iter()
         .map(|x| x.parse::<url::Url>().expect("Invalid seed url"))
         .collect();
 
-    let whitelist = RegexSet::new(args.whitelist).expect("invalid whitelist regexes");
-    let blacklist = RegexSet::new(args.blacklist).expect("invalid blacklist regexes");
+    let fetch_config = config.fetch.clone();
+    let mut builder = OrchestratorBuilder::new(db)
+        .seeds(src)
+        .config(config)
+        .shutdown_timeout(Duration::from_secs(args.shutdown_timeout));
+    match args.seen_set {
+        SeenKind::Memory => {}
+        SeenKind::Sqlite => {
+            let path = args
+                .seen_file
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("--seen-file is required"))?;
+            builder = builder.seen_set(SqliteSeenSet::open(path)?);
+        }
+        SeenKind::Bloom => {
+            builder = builder.seen_set(BloomSeenSet::new(args.seen_fp_rate, args.seen_capacity)?);
+        }
+    }
+    if args.eval_js {
+        builder = with_js(builder, &fetch_config, args.js_timeout, args.js_max_scripts)?;
+    }
+    if args.extract_text {
+        builder = builder.processor(TextExtractor);
+    }
+    for command in &args.process_command {
+        let processor = CommandProcessor::from_shell(command)?
+            .timeout(Duration::from_secs(args.process_timeout));
+        builder = builder.processor(processor);
+    }
+    if let Some(proxy) = proxy {
+        builder = builder.fetcher(ProxyPool::new(proxy, &fetch_config)?);
+    }
+    let coordinator = match args.coordinate {
+        Some(addr) => {
+            let mut coordinator = Coordinator::new(Duration::from_secs(args.lease_timeout));
+            if let Some(token) = &args.cluster_token {
+                coordinator = coordinator.token(token);
+            }
+            coordinator.spawn_server(addr)?;
+            builder = builder.fetcher(coordinator.clone());
+            Some(coordinator)
+        }
+        None => None,
+    };
+    if !args.render.is_empty() {
+        builder = with_renderer(
+            builder,
+            &args.render,
+            args.chrome_path,
+            args.chrome_arg,
+            args.render_timeout,
+            args.render_tabs,
+        )?;
+    }
+    let mut orchestrator = builder.build()?;
+
+    let stop = orchestrator.stop_handle();
+    tokio::spawn(async move {
+        if shutdown_signal().await.is_err() {
+            return;
+        }
+        tracing::info!("Stopping, press Ctrl+C again to exit immediately");
+        stop.stop(ExitReason::Interrupted);
+        if shutdown_signal().await.is_ok() {
+            tracing::warn!("Exiting without waiting for in-flight requests");
+            std::process::exit(130);
+        }
+    });
+
+    let operation = orchestrator.start(args.include_db_links);
+    let session = operation.await?;
+    tracing::info!("{}", session);
+    if let Some(coordinator) = coordinator {
+        coordinator.finish().await;
+    }
+    Ok(())
+}
+
+/// Ctrl+C, or SIGTERM on unix
+async fn shutdown_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(SignalKind::terminate())?;
+        tokio::select! {
+            x = tokio::signal::ctrl_c() => x,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
 
-    let sqlite_options = SqliteConnectOptions::new()
-        .filename(&args.output_file)
-        .create_if_missing(true)
-        .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal);
+#[cfg(feature = "js")]
+fn with_js(
+    builder: OrchestratorBuilder,
+    fetch: &FetchConfig,
+    timeout: u64,
+    max_scripts: usize,
+) -> anyhow::Result<OrchestratorBuilder> {
+    use waper::js::{JsConfig, JsEvaluator};
+    let config = JsConfig {
+        timeout: Duration::from_secs(timeout),
+        max_scripts,
+        fetch: fetch.clone(),
+        ..Default::default()
+    };
+    Ok(builder.processor(JsEvaluator::new(config, fetch.client()?)))
+}
 
-    let conn = SqlitePoolOptions::new()
-        .max_connections(3)
-        .connect_with(sqlite_options)
-        .await
-        .expect("Can't open sqlite file");
+#[cfg(not(feature = "js"))]
+fn with_js(
+    _builder: OrchestratorBuilder,
+    _fetch: &FetchConfig,
+    _timeout: u64,
+    _max_scripts: usize,
+) -> anyhow::Result<OrchestratorBuilder> {
+    anyhow::bail!("waper was built without support for --eval-js, install with `--features js`")
+}
 
-    sqlx::query(include_str!("../sqls/INIT.sql"))
-        .execute(&conn)
-        .await
-        .expect("Failed to initialize sqlite file schema");
+#[cfg(feature = "render")]
+fn with_renderer(
+    builder: OrchestratorBuilder,
+    patterns: &[String],
+    chrome_path: Option<PathBuf>,
+    chrome_args: Vec<String>,
+    timeout: u64,
+    tabs: usize,
+) -> anyhow::Result<OrchestratorBuilder> {
+    use anyhow::Context;
+    use waper::render::{RenderConfig, Renderer};
+    let config = RenderConfig {
+        patterns: RegexSet::new(patterns).context("invalid render regexes")?,
+        executable: chrome_path,
+        args: chrome_args,
+        timeout: Duration::from_secs(timeout),
+        tabs,
+    };
+    Ok(builder.renderer(Renderer::new(config)))
+}
 
-    let db = Database::new(conn);
-    let config = RuntimeConfig::new(args.max_parallel_requests.into(), whitelist, blacklist);
-    let config = Arc::new(Mutex::new(config));
+#[cfg(not(feature = "render"))]
+fn with_renderer(
+    _builder: OrchestratorBuilder,
+    _patterns: &[String],
+    _chrome_path: Option<PathBuf>,
+    _chrome_args: Vec<String>,
+    _timeout: u64,
+    _tabs: usize,
+) -> anyhow::Result<OrchestratorBuilder> {
+    anyhow::bail!("waper was built without support for --render, install with `--features render`")
+}
 
-    let mut orchestrator = orchestrator::Orchestrator::new(src, config.clone(), db);
-    let operation = orchestrator.start(args.include_db_links);
-    operation.await?;
+async fn open_storage(args: &ScrapeArgs) -> anyhow::Result<Arc<dyn Storage>> {
+    if args.output_file.as_os_str() == "-" {
+        // stdout replaces the storage, so another one was probably not meant to be ignored
+        if !matches!(args.storage, StorageKind::Sqlite) || args.postgres_url.is_some() {
+            anyhow::bail!(
+                "--output-file - writes to stdout instead of --storage, use --stream jsonl to write to both"
+            );
+        }
+        return Ok(Arc::new(JsonLinesStorage::stdout()));
+    }
+    let storage = open_output(args).await?;
+    Ok(match args.stream {
+        Some(StreamFormat::Jsonl) => Arc::new(Tee(storage, JsonLinesStorage::stdout())),
+        None => storage,
+    })
+}
+
+async fn open_output(args: &ScrapeArgs) -> anyhow::Result<Arc<dyn Storage>> {
+    Ok(match args.storage {
+        StorageKind::Sqlite => Arc::new(Database::connect(&args.output_file).await?),
+        #[cfg(feature = "fs-storage")]
+        StorageKind::Fs => Arc::new(waper::storage::FsStorage::open(&args.output_file).await?),
+        #[cfg(feature = "postgres")]
+        StorageKind::Postgres => {
+            let url = args
+                .postgres_url
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("--postgres-url is required"))?;
+            Arc::new(waper::storage::PostgresStorage::connect(url).await?)
+        }
+        #[allow(unreachable_patterns)]
+        kind => anyhow::bail!("waper was built without support for {kind:?} storage"),
+    })
+}
+
+async fn export(args: ExportArgs) -> anyhow::Result<()> {
+    let db = Database::connect(&args.input_file).await?;
+    let mut stdout = io::stdout().lock();
+    match args.kind {
+        ExportKind::Metadata => {
+            for (url, time, metadata) in db.get_metadata().await? {
+                let line = serde_json::json!({
+                    "url": url,
+                    "time": time,
+                    "json_ld": metadata.json_ld,
+                    "opengraph": metadata.opengraph,
+                    "microdata": metadata.microdata,
+                });
+                writeln!(stdout, "{line}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn mirror(args: MirrorArgs) -> anyhow::Result<()> {
+    log::init_logging(&LogArgs::default())?;
+    let db = Database::connect(&args.input_file).await?;
+    let written = waper::mirror::mirror(&db, &args.out).await?;
+    tracing::info!("Wrote {} files to {:?}", written, args.out);
+    Ok(())
+}
+
+async fn db(args: DbArgs) -> anyhow::Result<()> {
+    log::init_logging(&LogArgs::default())?;
+    match args.command {
+        DbCommand::Migrate => match Database::migrate(&args.input_file).await? {
+            Some(backup) => tracing::info!("Previous version kept in {:?}", backup),
+            None => tracing::info!("{:?} is already up to date", args.input_file),
+        },
+        DbCommand::Check => {
+            let status = Database::check(&args.input_file).await?;
+            println!("{}: {}", args.input_file.display(), status);
+            if !matches!(status, SchemaStatus::UpToDate(_)) {
+                std::process::exit(1);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Settings `scrape` and `serve` share on top of `config`
+fn runtime_config(
+    mut config: RuntimeConfig,
+    assets: &[AssetKind],
+    args: &ConfigArgs,
+) -> anyhow::Result<RuntimeConfig> {
+    let forms = args
+        .form
+        .iter()
+        .map(|x| {
+            FormRule::parse(x, args.form_max_urls)
+                .map_err(|e| e.context(format!("Invalid --form {x:?}")))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    config.assets = AssetConfig {
+        kinds: assets.to_vec(),
+        discover_js_urls: args.discover_js_urls,
+    };
+    config.normalizer = Normalizer {
+        strip_params: args
+            .strip_params
+            .iter()
+            .filter(|x| !x.is_empty())
+            .cloned()
+            .collect(),
+        sort_query: !args.keep_query_order,
+        fold_trailing_slash: args.fold_trailing_slash,
+        honour_canonical: !args.ignore_canonical,
+    };
+    config.near_duplicates = NearDuplicateConfig {
+        skip: args.skip_near_duplicates,
+        max_distance: args.near_duplicate_distance,
+    };
+    config.traps = TrapConfig {
+        max_url_length: Some(args.max_url_length).filter(|x| *x > 0),
+        max_repeated_segments: args.max_repeated_segments,
+        max_query_variants: args.max_query_variants,
+        max_pages_per_host: args.max_pages_per_host,
+    };
+    config.fetch = FetchConfig {
+        max_body_size: Some(args.max_body_size).filter(|x| *x > 0),
+        oversize: args.oversize,
+        connect_timeout: Duration::from_secs(args.connect_timeout),
+        read_timeout: Duration::from_secs(args.read_timeout),
+        total_timeout: Duration::from_secs(args.timeout),
+    };
+    config.outage = OutageConfig {
+        max_errors: args.outage_errors,
+        probe_interval: Duration::from_secs(args.probe_interval),
+        probe_url: args.probe_url.clone(),
+    };
+    config.forms = forms.into();
+    config.limits = Limits {
+        max_duration: args.max_duration.map(Duration::from_secs),
+        max_pages: args.max_pages,
+        max_bytes: args.max_bytes,
+        max_errors: args.max_errors,
+        max_error_rate: args.max_error_rate,
+    };
+    Ok(config)
+}
+
+async fn serve(args: ServeArgs) -> anyhow::Result<()> {
+    log::init_logging(&args.log)?;
+    let db = Database::connect(&args.output_file).await?;
+    let config = runtime_config(
+        RuntimeConfig {
+            rate_limit: args.max_parallel_requests.into(),
+            ..Default::default()
+        },
+        &args.assets,
+        &args.config,
+    )?;
+    Server::new(db, config).serve(args.addr).await
+}
+
+async fn worker(args: WorkerArgs) -> anyhow::Result<()> {
+    log::init_logging(&args.log)?;
+    let mut config = WorkerConfig::new(args.coordinator);
+    if let Some(name) = args.name {
+        config.name = name;
+    }
+    config.max_parallel_requests = args.max_parallel_requests;
+    config.retry_for = Duration::from_secs(args.retry_for);
+    config.proxy = proxy_config(&args.proxy)?;
+    config.fetch.connect_timeout = Duration::from_secs(args.connect_timeout);
+    config.fetch.total_timeout = Duration::from_secs(args.timeout);
+    config.token = args.cluster_token;
+    Worker::new(config)?.run().await?;
     Ok(())
 }
+
+/// `None` without `--proxy`
+fn proxy_config(args: &ProxyArgs) -> anyhow::Result<Option<ProxyConfig>> {
+    if args.proxy.is_empty() {
+        return Ok(None);
+    }
+    let mut config = ProxyConfig::new(args.proxy.clone())?;
+    config.rotation = args.proxy_rotation;
+    config.max_failures = args.proxy_max_failures;
+    config.backoff = Duration::from_secs(args.proxy_backoff);
+    config.max_backoff = Duration::from_secs(args.proxy_max_backoff);
+    Ok(Some(config))
+}
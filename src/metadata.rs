@@ -0,0 +1,139 @@
+//! Structured metadata embedded in html pages.
+//! Supports JSON-LD (`<script type="application/ld+json">`),
+//! OpenGraph style `<meta property>` tags and microdata (`itemscope`/`itemprop`).
+use std::collections::BTreeMap;
+
+use select::document::Document;
+use select::node::Node;
+use select::predicate::{Attr, Name, Predicate};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PageMetadata {
+    /// Every parsable JSON-LD block in document order.
+    pub json_ld: Vec<Value>,
+    /// `<meta property="og:title" content="...">` => {"og:title": ["..."]}
+    /// Values are lists as properties like `og:image` can be repeated.
+    pub opengraph: BTreeMap<String, Vec<String>>,
+    /// Top level microdata items
+    pub microdata: Vec<MicrodataItem>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct MicrodataItem {
+    #[serde(rename = "type")]
+    pub item_type: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub properties: BTreeMap<String, Vec<Value>>,
+}
+
+impl PageMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.json_ld.is_empty() && self.opengraph.is_empty() && self.microdata.is_empty()
+    }
+
+    pub fn extract(document: &Document) -> Self {
+        Self {
+            json_ld: extract_json_ld(document),
+            opengraph: extract_opengraph(document),
+            microdata: extract_microdata(document),
+        }
+    }
+}
+
+fn extract_json_ld(document: &Document) -> Vec<Value> {
+    document
+        .find(Name("script").and(Attr("type", "application/ld+json")))
+        .filter_map(|n| {
+            // Broken JSON-LD is common in the wild, we just skip those blocks
+            serde_json::from_str::<Value>(n.text().trim()).ok()
+        })
+        .collect()
+}
+
+fn extract_opengraph(document: &Document) -> BTreeMap<String, Vec<String>> {
+    let mut rv: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for node in document.find(Name("meta")) {
+        // Some sites use `name` instead of `property` for og tags
+        let property = match node.attr("property").or_else(|| {
+            node.attr("name")
+                .filter(|x| x.starts_with("og:") || x.starts_with("twitter:"))
+        }) {
+            Some(x) => x.trim(),
+            None => continue,
+        };
+        let content = match node.attr("content") {
+            Some(x) => x.trim(),
+            None => continue,
+        };
+        rv.entry(property.to_string())
+            .or_default()
+            .push(content.to_string());
+    }
+    rv
+}
+
+fn extract_microdata(document: &Document) -> Vec<MicrodataItem> {
+    document
+        .find(Attr("itemscope", ()))
+        // Items with `itemprop` are properties of their parent item
+        .filter(|n| n.attr("itemprop").is_none())
+        .map(microdata_item)
+        .collect()
+}
+
+fn microdata_item(scope: Node) -> MicrodataItem {
+    let mut properties: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    collect_properties(scope, &mut properties);
+    MicrodataItem {
+        item_type: scope
+            .attr("itemtype")
+            .map(|x| x.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default(),
+        id: scope.attr("itemid").map(str::to_string),
+        properties,
+    }
+}
+
+/// Walk children of `node` and collect `itemprop`s belonging to the current item.
+/// Does not descend into nested items, those are collected as their own value.
+fn collect_properties(node: Node, properties: &mut BTreeMap<String, Vec<Value>>) {
+    for child in node.children() {
+        let is_scope = child.attr("itemscope").is_some();
+        if let Some(names) = child.attr("itemprop") {
+            let value = if is_scope {
+                serde_json::to_value(microdata_item(child)).unwrap_or(Value::Null)
+            } else {
+                Value::String(property_value(child))
+            };
+            for name in names.split_whitespace() {
+                properties
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(value.clone());
+            }
+        }
+        if !is_scope {
+            collect_properties(child, properties);
+        }
+    }
+}
+
+/// See: https://html.spec.whatwg.org/multipage/microdata.html#values
+fn property_value(node: Node) -> String {
+    let attr = match node.name() {
+        Some("meta") => "content",
+        Some("audio" | "embed" | "iframe" | "img" | "source" | "track" | "video") => "src",
+        Some("a" | "area" | "link") => "href",
+        Some("object") => "data",
+        Some("data" | "meter") => "value",
+        Some("time") => "datetime",
+        _ => return node.text().trim().to_string(),
+    };
+    match node.attr(attr) {
+        Some(x) => x.trim().to_string(),
+        None => node.text().trim().to_string(),
+    }
+}
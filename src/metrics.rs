@@ -0,0 +1,182 @@
+//! Prometheus metrics of everything running in the process, served by `--metrics-addr`
+//! and on `/metrics` of `waper serve`. Crawls running side by side add up.
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::cluster::RemoteError;
+use crate::fetch::{ReadTimeout, Rejected};
+use crate::prelude::*;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+pub struct Metrics {
+    registry: Registry,
+    /// By `kind` (page, asset), `status` class (2xx, 4xx, ...) and `host`.
+    /// `error` if there was no response and `skipped` if it was rejected.
+    pub requests: IntCounterVec,
+    /// Body sizes by `kind`
+    pub bytes: IntCounterVec,
+    /// Fetching, reading and parsing a response by `kind`
+    pub response_seconds: HistogramVec,
+    /// By `class`, see [`error_class`]
+    pub errors: IntCounterVec,
+    pub queued: IntGauge,
+    pub in_flight: IntGauge,
+    pub seen: IntGauge,
+    pub seen_bytes: IntGauge,
+    /// Crawls whose connection is lost, see [`crate::outage`]
+    pub offline: IntGauge,
+    /// Connection failures by `proxy`, see [`crate::proxy`]
+    pub proxy_failures: IntCounterVec,
+    /// By `table`, `batch` for multi-row writes (everything written through `Batched`)
+    pub db_write_seconds: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("waper".to_string()), None)?;
+        let rv = Self {
+            requests: IntCounterVec::new(
+                Opts::new("requests_total", "Requests made, by response status class"),
+                &["kind", "status", "host"],
+            )?,
+            bytes: IntCounterVec::new(
+                Opts::new("fetched_bytes_total", "Size of fetched bodies"),
+                &["kind"],
+            )?,
+            response_seconds: HistogramVec::new(
+                HistogramOpts::new("response_seconds", "Time to fetch and parse a response")
+                    .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+                &["kind"],
+            )?,
+            errors: IntCounterVec::new(
+                Opts::new("errors_total", "Failed requests, by cause"),
+                &["class"],
+            )?,
+            queued: IntGauge::new("queued", "Urls waiting for a free request slot")?,
+            in_flight: IntGauge::new("in_flight", "Requests being made or processed")?,
+            seen: IntGauge::new("seen_urls", "Urls in the seen sets of crawls")?,
+            seen_bytes: IntGauge::new("seen_bytes", "Approximate memory taken by seen sets")?,
+            offline: IntGauge::new("offline", "Crawls paused till their connection is back")?,
+            proxy_failures: IntCounterVec::new(
+                Opts::new("proxy_failures_total", "Failed connections through proxies"),
+                &["proxy"],
+            )?,
+            db_write_seconds: HistogramVec::new(
+                HistogramOpts::new("db_write_seconds", "Time taken by sqlite writes")
+                    .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]),
+                &["table"],
+            )?,
+            registry,
+        };
+        rv.registry.register(Box::new(rv.requests.clone()))?;
+        rv.registry.register(Box::new(rv.bytes.clone()))?;
+        rv.registry
+            .register(Box::new(rv.response_seconds.clone()))?;
+        rv.registry.register(Box::new(rv.errors.clone()))?;
+        rv.registry.register(Box::new(rv.queued.clone()))?;
+        rv.registry.register(Box::new(rv.in_flight.clone()))?;
+        rv.registry.register(Box::new(rv.seen.clone()))?;
+        rv.registry.register(Box::new(rv.seen_bytes.clone()))?;
+        rv.registry.register(Box::new(rv.offline.clone()))?;
+        rv.registry.register(Box::new(rv.proxy_failures.clone()))?;
+        rv.registry
+            .register(Box::new(rv.db_write_seconds.clone()))?;
+        Ok(rv)
+    }
+
+    /// Request to `host` which got a response with `status`, or failed with `error`.
+    /// Every `host` is a series of its own, so it should come from a small set (seed hosts).
+    pub(crate) fn request(
+        &self,
+        kind: &str,
+        host: &str,
+        elapsed: Duration,
+        result: Result<u16, &anyhow::Error>,
+    ) {
+        let status = match result {
+            Ok(status) => format!("{}xx", status / 100),
+            Err(e) if e.is::<Rejected>() => "skipped".to_string(),
+            Err(e) => match e.downcast_ref::<reqwest::Error>().and_then(|x| x.status()) {
+                Some(status) => format!("{}xx", status.as_u16() / 100),
+                None => "error".to_string(),
+            },
+        };
+        self.requests
+            .with_label_values(&[kind, &status, host])
+            .inc();
+        self.response_seconds
+            .with_label_values(&[kind])
+            .observe(elapsed.as_secs_f64());
+        match result {
+            Err(e) if !e.is::<Rejected>() => {
+                self.errors.with_label_values(&[error_class(e)]).inc();
+            }
+            _ => {}
+        }
+    }
+
+    /// Prometheus text format
+    pub fn encode(&self) -> String {
+        let mut rv = vec![];
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut rv) {
+            error!("Failed to encode metrics: {:?}", e);
+        }
+        String::from_utf8_lossy(&rv).into_owned()
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("metric definitions should be valid"))
+}
+
+/// `timeout`, `connect`, `status`, `body`, `redirect` or `other`
+pub fn error_class(error: &anyhow::Error) -> &'static str {
+    if error.is::<ReadTimeout>() {
+        return "timeout";
+    }
+    if let Some(e) = error.downcast_ref::<RemoteError>() {
+        return ["timeout", "connect", "status", "body", "redirect"]
+            .into_iter()
+            .find(|x| *x == e.class)
+            .unwrap_or("other");
+    }
+    let Some(e) = error.downcast_ref::<reqwest::Error>() else {
+        return "other";
+    };
+    if e.is_timeout() {
+        "timeout"
+    } else if e.is_connect() {
+        "connect"
+    } else if e.is_status() {
+        "status"
+    } else if e.is_body() || e.is_decode() {
+        "body"
+    } else if e.is_redirect() {
+        "redirect"
+    } else {
+        "other"
+    }
+}
+
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(|| async { metrics().encode() }))
+}
+
+/// Serve `/metrics` on `addr` from a background task, returns the bound address
+pub fn spawn_server(addr: SocketAddr) -> anyhow::Result<SocketAddr> {
+    let server = axum::Server::try_bind(&addr)?.serve(router().into_make_service());
+    let addr = server.local_addr();
+    info!("Serving metrics on http://{}/metrics", addr);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Metrics server failed: {:?}", e);
+        }
+    });
+    Ok(addr)
+}
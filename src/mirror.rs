@@ -0,0 +1,496 @@
+//! Write scraped pages and assets to a directory tree which can be browsed offline.
+//! Links between mirrored files are rewritten to relative paths.
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use futures::StreamExt;
+use regex::{Captures, Regex};
+use url::Url;
+
+use crate::db::Database;
+use crate::prelude::*;
+
+static CSS_URL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(url\(\s*)(["']?)([^"')]+?)(["']?\s*\))"#).unwrap());
+static CSS_IMPORT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(@import\s+)(["'])([^"']+)(["'])"#).unwrap());
+
+/// Elements whose content is not html, `style` content is rewritten as css
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+
+/// Maps urls present in db to paths relative to the mirror root
+struct PathMap {
+    paths: HashMap<String, PathBuf>,
+}
+
+impl PathMap {
+    fn new(pages: Vec<Url>, assets: Vec<Url>) -> Self {
+        let mut candidates: Vec<(String, PathBuf)> = pages
+            .into_iter()
+            .map(|x| (x.to_string(), local_path(&x, true)))
+            .chain(
+                assets
+                    .into_iter()
+                    .map(|x| (x.to_string(), local_path(&x, false))),
+            )
+            .collect();
+        // Deterministic output independent of db order
+        candidates.sort();
+
+        // A file can't exist where a directory is required by another path.
+        // e.g. asset `/a` and `/a/b` => `/a` is written to `a/index`
+        let dirs: HashSet<PathBuf> = candidates
+            .iter()
+            .flat_map(|(_, path)| path.ancestors().skip(1).map(Path::to_path_buf))
+            .collect();
+
+        let mut used: HashSet<PathBuf> = HashSet::new();
+        let mut paths = HashMap::new();
+        for (url, mut path) in candidates {
+            if dirs.contains(&path) {
+                path = path.join("index");
+            }
+            let mut unique = path.clone();
+            let mut counter = 1;
+            while used.contains(&unique) {
+                unique = with_suffix(&path, &format!("-{counter}"));
+                counter += 1;
+            }
+            used.insert(unique.clone());
+            paths.insert(url, unique);
+        }
+        Self { paths }
+    }
+
+    fn get(&self, url: &Url) -> Option<&PathBuf> {
+        self.paths.get(url.as_str())
+    }
+
+    /// Replace `value` (found in file at `from`) by a relative link if it is mirrored,
+    /// otherwise by an absolute url so it still works when browsing offline.
+    fn rewrite(&self, base: &Url, from: &Path, value: &str) -> Option<String> {
+        let value = value.trim().replace("&amp;", "&");
+        if value.is_empty() || value.starts_with('#') {
+            return None;
+        }
+        let mut target = base.join(&value).ok()?;
+        if !matches!(target.scheme(), "http" | "https") {
+            return None;
+        }
+        let fragment = target.fragment().map(str::to_string);
+        target.set_fragment(None);
+        let mut rv = match self.get(&target) {
+            Some(path) => relative_link(from, path),
+            None => target.to_string(),
+        };
+        if let Some(fragment) = fragment {
+            rv.push('#');
+            rv.push_str(&fragment);
+        }
+        Some(rv)
+    }
+}
+
+/// `https://example.com:8080/a/b?x=1` => `example.com_8080/a/b/index@x=1.html` for pages
+fn local_path(url: &Url, is_page: bool) -> PathBuf {
+    let mut rv = PathBuf::new();
+    let host = url.host_str().unwrap_or("unknown");
+    match url.port() {
+        Some(port) => rv.push(format!("{host}_{port}")),
+        None => rv.push(host),
+    }
+
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|x| {
+            x.filter(|x| !x.is_empty() && *x != "." && *x != "..")
+                .collect()
+        })
+        .unwrap_or_default();
+    let ends_with_slash = url.path().ends_with('/');
+    let (dirs, file) = match segments.split_last() {
+        Some((last, rest)) if !ends_with_slash => (rest, Some(*last)),
+        _ => (&segments[..], None),
+    };
+    for dir in dirs {
+        rv.push(dir);
+    }
+
+    let file = match (file, is_page) {
+        (None, true) => "index.html".to_string(),
+        (None, false) => "index".to_string(),
+        // Pages without html extension become directories, so `/a` and `/a/b` can coexist
+        (Some(x), true) if !has_html_extension(x) => {
+            rv.push(x);
+            "index.html".to_string()
+        }
+        (Some(x), _) => x.to_string(),
+    };
+    let file = match url.query() {
+        Some(query) => insert_before_extension(&file, &format!("@{}", sanitize(query))),
+        None => file,
+    };
+    rv.push(file);
+    rv
+}
+
+fn has_html_extension(segment: &str) -> bool {
+    let lower = segment.to_ascii_lowercase();
+    lower.ends_with(".html") || lower.ends_with(".htm")
+}
+
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "=&-_.,+".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn insert_before_extension(file: &str, suffix: &str) -> String {
+    match file.rfind('.') {
+        Some(idx) if idx > 0 => format!("{}{}{}", &file[..idx], suffix, &file[idx..]),
+        _ => format!("{file}{suffix}"),
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let file = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(insert_before_extension(&file, suffix))
+}
+
+/// Relative link from file `from` to file `to`, both relative to mirror root
+fn relative_link(from: &Path, to: &Path) -> String {
+    let from_dir: Vec<_> = from
+        .parent()
+        .map(|x| x.components().collect())
+        .unwrap_or_default();
+    let to: Vec<_> = to.components().collect();
+    let common = from_dir
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts: Vec<String> = vec!["..".to_string(); from_dir.len() - common];
+    parts.extend(
+        to[common..]
+            .iter()
+            .map(|x| x.as_os_str().to_string_lossy().to_string()),
+    );
+    // File names are kept percent-encoded as they appear in urls
+    parts.join("/").replace('%', "%25")
+}
+
+/// Pieces of an html document, which put back together give the document unchanged
+enum Token<'a> {
+    /// Text, comments, doctype and end tags
+    Other(&'a str),
+    StartTag(Tag<'a>),
+    /// Content of a [`RAW_TEXT`] element, by element name
+    RawText(&'static str, &'a str),
+}
+
+struct Tag<'a> {
+    raw: &'a str,
+    /// Lowercase
+    name: String,
+    /// Name and range of the value in `raw`, without quotes. Quote is `None` for unquoted values.
+    attrs: Vec<(&'a str, Range<usize>, Option<char>)>,
+}
+
+impl<'a> Tag<'a> {
+    /// Start tag at the beginning of `html`
+    fn parse(html: &'a str) -> Option<Self> {
+        let bytes = html.as_bytes();
+        if bytes.len() < 2 || bytes[0] != b'<' || !bytes[1].is_ascii_alphabetic() {
+            return None;
+        }
+        let len = bytes.len();
+        let name_end = |i: usize| {
+            i >= len || bytes[i].is_ascii_whitespace() || matches!(bytes[i], b'>' | b'/' | b'=')
+        };
+        let mut i = 1;
+        while !name_end(i) {
+            i += 1;
+        }
+        let name = html[1..i].to_ascii_lowercase();
+        let mut attrs = vec![];
+        loop {
+            while i < len && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+                i += 1;
+            }
+            if i >= len {
+                break;
+            }
+            if bytes[i] == b'>' {
+                i += 1;
+                break;
+            }
+            let start = i;
+            // a stray `=` is part of the name
+            i += 1;
+            while !name_end(i) {
+                i += 1;
+            }
+            let attr = &html[start..i];
+            while i < len && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if i >= len || bytes[i] != b'=' {
+                continue;
+            }
+            i += 1;
+            while i < len && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            match bytes.get(i) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    let end = html[i + 1..].find(quote as char).map_or(len, |x| i + 1 + x);
+                    attrs.push((attr, i + 1..end, Some(quote as char)));
+                    i = (end + 1).min(len);
+                }
+                _ => {
+                    let start = i;
+                    while i < len && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                        i += 1;
+                    }
+                    attrs.push((attr, start..i, None));
+                }
+            }
+        }
+        Some(Self {
+            raw: &html[..i],
+            name,
+            attrs,
+        })
+    }
+
+    fn attr(&self, name: &str) -> Option<&'a str> {
+        self.attrs
+            .iter()
+            .find(|(attr, ..)| attr.eq_ignore_ascii_case(name))
+            .map(|(_, value, _)| &self.raw[value.clone()])
+    }
+}
+
+struct Tokens<'a> {
+    html: &'a str,
+    pos: usize,
+    raw_text: Option<&'static str>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(html: &'a str) -> Self {
+        Self {
+            html,
+            pos: 0,
+            raw_text: None,
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let rest = &self.html[self.pos..];
+        if rest.is_empty() {
+            return None;
+        }
+        if let Some(name) = self.raw_text.take() {
+            let end = rest
+                .to_ascii_lowercase()
+                .find(&format!("</{name}"))
+                .unwrap_or(rest.len());
+            self.pos += end;
+            return Some(Token::RawText(name, &rest[..end]));
+        }
+        if let Some(tag) = Tag::parse(rest) {
+            self.raw_text = RAW_TEXT.iter().find(|x| **x == tag.name).copied();
+            self.pos += tag.raw.len();
+            return Some(Token::StartTag(tag));
+        }
+        let len = if let Some(comment) = rest.strip_prefix("<!--") {
+            comment.find("-->").map_or(rest.len(), |x| x + 7)
+        } else if rest.starts_with("</") || rest.starts_with("<!") || rest.starts_with("<?") {
+            rest.find('>').map_or(rest.len(), |x| x + 1)
+        } else {
+            // text, or a `<` which doesn't start a tag
+            let skip = usize::from(rest.starts_with('<'));
+            rest[skip..].find('<').map_or(rest.len(), |x| x + skip)
+        };
+        self.pos += len;
+        Some(Token::Other(&rest[..len]))
+    }
+}
+
+fn rewrite_html(map: &PathMap, url: &Url, from: &Path, html: &str) -> String {
+    // links are resolved against `<base href>`, which is dropped as they become relative to the file
+    let is_base = |tag: &Tag| tag.name == "base" && tag.attr("href").is_some();
+    let base = Tokens::new(html)
+        .find_map(|token| match token {
+            Token::StartTag(tag) if is_base(&tag) => Some(tag.attr("href")?.trim().to_string()),
+            _ => None,
+        })
+        .and_then(|href| url.join(&href.replace("&amp;", "&")).ok())
+        .unwrap_or_else(|| url.clone());
+
+    let mut rv = String::with_capacity(html.len());
+    for token in Tokens::new(html) {
+        match token {
+            Token::RawText("style", css) => rv.push_str(&rewrite_css(map, &base, from, css)),
+            Token::Other(text) | Token::RawText(_, text) => rv.push_str(text),
+            Token::StartTag(tag) if is_base(&tag) => {}
+            Token::StartTag(tag) => rewrite_tag(map, &base, from, &tag, &mut rv),
+        }
+    }
+    rv
+}
+
+/// Push `tag` to `out` with `href`, `src`, `srcset` and `style` attributes rewritten
+fn rewrite_tag(map: &PathMap, base: &Url, from: &Path, tag: &Tag, out: &mut String) {
+    let mut last = 0;
+    for (attr, range, quote) in &tag.attrs {
+        let value = &tag.raw[range.clone()];
+        let rewritten = match attr.to_ascii_lowercase().as_str() {
+            "href" | "src" => map.rewrite(base, from, value),
+            "srcset" => Some(rewrite_srcset(map, base, from, value)),
+            "style" => Some(rewrite_css(map, base, from, value)),
+            _ => None,
+        };
+        let Some(rewritten) = rewritten else { continue };
+        out.push_str(&tag.raw[last..range.start]);
+        match quote {
+            Some(quote) => {
+                out.push_str(&rewritten.replace(*quote, &format!("%{:X}", *quote as u8)))
+            }
+            None => {
+                out.push('"');
+                out.push_str(&rewritten.replace('"', "%22"));
+                out.push('"');
+            }
+        }
+        last = range.end;
+    }
+    out.push_str(&tag.raw[last..]);
+}
+
+/// `a.png 1x, b.png 2x`, urls are separated from descriptors by whitespace
+/// and can contain commas themselves (`data:` urls)
+fn rewrite_srcset(map: &PathMap, base: &Url, from: &Path, srcset: &str) -> String {
+    let mut candidates = vec![];
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let (url, after) = rest.split_at(end);
+        let (url, descriptor, after) = match url.strip_suffix(',') {
+            Some(url) => (url.trim_end_matches(','), "", after),
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (url, after[..end].trim(), &after[end..])
+            }
+        };
+        let url = match map.rewrite(base, from, url) {
+            // a comma would end the url
+            Some(x) => x.replace(',', "%2C"),
+            None => url.to_string(),
+        };
+        candidates.push(match descriptor {
+            "" => url,
+            descriptor => format!("{url} {descriptor}"),
+        });
+        rest = after;
+    }
+    candidates.join(", ")
+}
+
+fn rewrite_css(map: &PathMap, url: &Url, from: &Path, css: &str) -> String {
+    let replace = |caps: &Captures| {
+        if caps[3].trim().starts_with("data:") {
+            return caps[0].to_string();
+        }
+        match map.rewrite(url, from, &caps[3]) {
+            Some(x) => format!("{}{}{}{}", &caps[1], &caps[2], x, &caps[4]),
+            None => caps[0].to_string(),
+        }
+    };
+    let css = CSS_URL_RE.replace_all(css, replace);
+    CSS_IMPORT_RE.replace_all(&css, replace).to_string()
+}
+
+fn write_file(root: &Path, path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let path = root.join(path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, content)?;
+    Ok(())
+}
+
+/// Write all results and assets from `db` under `out`. Returns number of files written.
+pub async fn mirror(db: &Database, out: &Path) -> anyhow::Result<usize> {
+    let pages = parse_urls(db.get_result_urls().await?);
+    let assets = parse_urls(db.get_asset_urls().await?);
+    let map = PathMap::new(pages, assets);
+    let mut written = 0;
+
+    let mut results = db.stream_results();
+    while let Some(row) = results.next().await {
+        let (url, html) = row?;
+        let Ok(url) = url.parse::<Url>() else {
+            continue;
+        };
+        let Some(path) = map.get(&url) else { continue };
+        let html = rewrite_html(&map, &url, path, &html);
+        write_file(out, path, html.as_bytes())?;
+        debug!("Wrote {} to {:?}", url, path);
+        written += 1;
+    }
+    drop(results);
+
+    let mut assets = db.stream_assets();
+    while let Some(row) = assets.next().await {
+        let (url, kind, content) = row?;
+        let Ok(url) = url.parse::<Url>() else {
+            continue;
+        };
+        let Some(path) = map.get(&url) else { continue };
+        if kind == crate::assets::AssetKind::Stylesheet.as_str() {
+            let css = rewrite_css(&map, &url, path, &String::from_utf8_lossy(&content));
+            write_file(out, path, css.as_bytes())?;
+        } else {
+            write_file(out, path, &content)?;
+        }
+        debug!("Wrote {} to {:?}", url, path);
+        written += 1;
+    }
+    Ok(written)
+}
+
+fn parse_urls(urls: Vec<String>) -> Vec<Url> {
+    urls.into_iter()
+        .filter_map(|x| match x.parse::<Url>() {
+            Ok(x) => Some(x),
+            Err(e) => {
+                warn!("Skipping invalid url in db {}: {}", x, e);
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,79 @@
+//! Url normalization applied before filtering and deduplication,
+//! so trivial variations of a url are not scraped multiple times.
+use url::Url;
+
+/// Query params stripped by default, `*` at the end matches any suffix
+pub const DEFAULT_STRIP_PARAMS: &[&str] = &[
+    "utm_*", "gclid", "fbclid", "msclkid", "dclid", "mc_cid", "mc_eid", "_ga", "_hsenc", "_hsmi",
+];
+
+#[derive(Debug, Clone)]
+pub struct Normalizer {
+    /// Query params to remove, `*` at the end matches any suffix (e.g. `utm_*`)
+    pub strip_params: Vec<String>,
+    /// Sort query params by name, `?b=1&a=2` => `?a=2&b=1`
+    pub sort_query: bool,
+    /// Remove trailing slash from path, `/a/` => `/a`
+    pub fold_trailing_slash: bool,
+    /// Treat page as duplicate of url in `<link rel=canonical>`
+    pub honour_canonical: bool,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Self {
+            strip_params: DEFAULT_STRIP_PARAMS.iter().map(|x| x.to_string()).collect(),
+            sort_query: true,
+            fold_trailing_slash: false,
+            honour_canonical: true,
+        }
+    }
+}
+
+impl Normalizer {
+    /// Scheme and host are lowercased and default ports are removed by `Url` parser itself,
+    /// rest is handled here.
+    pub fn normalize(&self, url: &Url) -> Url {
+        let mut rv = url.clone();
+        rv.set_fragment(None);
+
+        if let Some(query) = rv.query() {
+            // Work on raw `key=value` pairs so encoding of values is kept as is,
+            // re-encoding could change meaning for some servers.
+            let mut pairs: Vec<&str> = query
+                .split('&')
+                .filter(|x| !x.is_empty())
+                .filter(|x| !self.is_stripped(param_name(x)))
+                .collect();
+            if self.sort_query {
+                // stable sort, so repeated params keep their relative order
+                pairs.sort_by_key(|x| param_name(x));
+            }
+            let query = pairs.join("&");
+            if query.is_empty() {
+                rv.set_query(None);
+            } else {
+                rv.set_query(Some(&query));
+            }
+        }
+
+        if self.fold_trailing_slash && rv.path().len() > 1 && rv.path().ends_with('/') {
+            let path = rv.path().trim_end_matches('/').to_string();
+            rv.set_path(&path);
+        }
+        rv
+    }
+
+    fn is_stripped(&self, name: &str) -> bool {
+        self.strip_params
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            })
+    }
+}
+
+fn param_name(pair: &str) -> &str {
+    pair.split('=').next().unwrap_or(pair)
+}
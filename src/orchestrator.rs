@@ -1,19 +1,39 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::Context;
 
 use futures::future::BoxFuture;
 use futures::{FutureExt, StreamExt};
-use patricia_tree::PatriciaSet;
 use regex::RegexSet;
+use serde_json::{Map, Value};
+use tracing::Instrument;
 use url::Url;
 
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
-use crate::db::Database;
+use crate::assets::{AssetConfig, AssetKind};
+use crate::fetch::{FetchConfig, Fetcher, Rejected};
+use crate::forms::{self, FormRule};
+use crate::metadata::PageMetadata;
+use crate::metrics::metrics;
+use crate::normalize::Normalizer;
+use crate::outage::{is_connectivity_error, OutageConfig, OutageDetector, Verdict};
+use crate::pipeline::{Page, PageProcessor, Pipeline};
 use crate::prelude::*;
+#[cfg(feature = "render")]
+use crate::render::Renderer;
+#[cfg(feature = "render")]
 use crate::scraper;
+use crate::seen::{MemorySeenSet, SeenSet, SharedSeenSet};
+use crate::session::{unix_time, Counters, ExitReason, Limits, Session, Stats, StopHandle};
+use crate::simhash::SimHashIndex;
+use crate::storage::Storage;
+use crate::trap::{TrapConfig, TrapDetector};
 
 /// Used to run and controll the craping
 /// let runner = Orchestrator::new(config)
@@ -43,19 +63,165 @@ pub struct Orchestrator {
     seed_urls: Vec<Url>,
     config: Arc<Mutex<RuntimeConfig>>,
 
-    queue_rx: mpsc::UnboundedReceiver<Url>,
-    queue_tx: mpsc::UnboundedSender<Url>,
+    // Hosts of seeds, other hosts are counted together in metrics
+    seed_hosts: Arc<Mutex<HashSet<String>>>,
 
-    request_client: reqwest::Client,
+    queue_rx: mpsc::UnboundedReceiver<Resource>,
+    queue_tx: mpsc::UnboundedSender<Resource>,
+
+    fetcher: Arc<dyn Fetcher>,
 
     // URIs which have already been added to queue_rx
     // So do not need to be added again.
-    noticed_uris: Arc<Mutex<PatriciaSet>>,
+    noticed_uris: Arc<SharedSeenSet>,
+
+    // Fingerprints of scraped pages, to detect near-duplicates
+    fingerprints: Arc<Mutex<SimHashIndex<()>>>,
+
+    // State for crawler trap heuristics
+    traps: Arc<Mutex<TrapDetector>>,
+
+    // Urls which failed to connect, and whether the connection is lost
+    outage: Arc<Mutex<OutageDetector>>,
+    lost_rx: watch::Receiver<bool>,
 
     // tasks: futures::stream::FuturesUnordered<BoxFuture<'static, ()>>,
     tasks: futures::stream::FuturesUnordered<BoxFuture<'static, anyhow::Result<()>>>,
 
-    db: Database,
+    db: Arc<dyn Storage>,
+
+    events: Option<mpsc::UnboundedSender<CrawlEvent>>,
+
+    pipeline: Arc<Pipeline>,
+
+    #[cfg(feature = "render")]
+    renderer: Option<Arc<Renderer>>,
+
+    stop: StopHandle,
+    stop_rx: watch::Receiver<Option<ExitReason>>,
+    paused: Arc<watch::Sender<bool>>,
+    paused_rx: watch::Receiver<bool>,
+    // Seeds added with `CrawlControl::add_seeds` while running
+    seeds_tx: mpsc::UnboundedSender<Url>,
+    seeds_rx: mpsc::UnboundedReceiver<Url>,
+    // How long in-flight requests get to finish after a stop
+    shutdown_timeout: Duration,
+
+    counters: Arc<Counters>,
+}
+
+/// Controls a running [`Orchestrator`] from other tasks, see [`Orchestrator::control`].
+/// Cheap to clone, everything takes effect once the orchestrator's loop wakes up.
+#[derive(Clone)]
+pub struct CrawlControl {
+    config: Arc<Mutex<RuntimeConfig>>,
+    stop: StopHandle,
+    paused: Arc<watch::Sender<bool>>,
+    lost: watch::Receiver<bool>,
+    seeds: mpsc::UnboundedSender<Url>,
+    counters: Arc<Counters>,
+}
+
+impl CrawlControl {
+    /// Config used by the running crawl, changes apply to newly found urls
+    pub fn config(&self) -> &Arc<Mutex<RuntimeConfig>> {
+        &self.config
+    }
+
+    /// Scrape `urls` even if they do not pass the filters, like the initial seeds.
+    /// Ignored once the crawl has ended.
+    pub fn add_seeds(&self, urls: impl IntoIterator<Item = Url>) {
+        for url in urls {
+            // error drop: receiver is only gone once the orchestrator is dropped
+            let _ = self.seeds.send(url);
+        }
+    }
+
+    /// Stop making new requests, in-flight ones still finish.
+    /// Limits keep being checked, time spent paused counts towards `max_duration`.
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Connection is lost, nothing is scheduled till it's back (see [`crate::outage`])
+    pub fn is_offline(&self) -> bool {
+        *self.lost.borrow()
+    }
+
+    pub fn stop(&self) {
+        self.stop.stop(ExitReason::Interrupted);
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.counters.stats()
+    }
+}
+
+/// Something to be fetched
+#[derive(Debug, Clone)]
+pub enum Resource {
+    /// Html page, it's links will be followed
+    Page(Url),
+    /// Asset referenced by some page
+    Asset(AssetKind, Url),
+}
+
+impl Resource {
+    pub fn url(&self) -> &Url {
+        match self {
+            Resource::Page(x) => x,
+            Resource::Asset(_, x) => x,
+        }
+    }
+
+    /// Key in the seen set. Pages and assets are seen apart, so a url referenced by an
+    /// `<img>` can still be scraped as a page. Pages keep the bare url so seen sets
+    /// persisted by earlier runs still apply.
+    pub fn seen_key(&self) -> Cow<'_, str> {
+        match self {
+            Resource::Page(x) => Cow::Borrowed(x.as_str()),
+            Resource::Asset(_, x) => Cow::Owned(format!("asset {x}")),
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Page(x) => write!(f, "{x}"),
+            Resource::Asset(kind, x) => write!(f, "{x} ({kind})"),
+        }
+    }
+}
+
+/// Sent to the channel registered with [`OrchestratorBuilder::events`] as resources are processed
+#[derive(Debug, Clone)]
+pub enum CrawlEvent {
+    Page {
+        url: Url,
+        html: String,
+        metadata: PageMetadata,
+        /// Added by [`crate::pipeline::PageProcessor`]s
+        data: Map<String, Value>,
+    },
+    Asset {
+        url: Url,
+        kind: AssetKind,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    },
+    Error {
+        url: Url,
+        message: String,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -63,6 +229,22 @@ pub struct RateLimit {
     max_parallel_requests: u64,
 }
 
+impl RateLimit {
+    pub fn new(max_parallel_requests: u64) -> Self {
+        Self {
+            max_parallel_requests,
+        }
+    }
+
+    pub fn set_max_parallel_requests(&mut self, max_parallel_requests: u64) {
+        self.max_parallel_requests = max_parallel_requests;
+    }
+
+    pub fn max_parallel_requests(&self) -> u64 {
+        self.max_parallel_requests
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Filter {
     pub blacklist_re: RegexSet,
@@ -83,11 +265,45 @@ impl Filter {
             true
         }
     }
+
+    /// Assets are often served from other domains (CDNs),
+    /// so only the blacklist applies to them.
+    pub fn is_blacklisted(&self, value: &str) -> bool {
+        self.blacklist_re.is_match(value)
+    }
 }
+#[derive(Debug, Clone)]
+pub struct NearDuplicateConfig {
+    /// Do not follow links of pages which are near-duplicate of an already scraped page
+    pub skip: bool,
+    /// Max differing bits in SimHash for pages to be considered near-duplicate.
+    /// Only read on `Orchestrator` creation.
+    pub max_distance: u32,
+}
+
+impl Default for NearDuplicateConfig {
+    fn default() -> Self {
+        Self {
+            skip: false,
+            max_distance: 3,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RuntimeConfig {
     pub rate_limit: RateLimit,
     pub filter: Filter,
+    pub assets: AssetConfig,
+    pub normalizer: Normalizer,
+    pub near_duplicates: NearDuplicateConfig,
+    pub traps: TrapConfig,
+    pub limits: Limits,
+    pub fetch: FetchConfig,
+    pub outage: OutageConfig,
+    /// Forms submitted for more links, see [`crate::forms`].
+    /// Shared as wordlists can be large and the rules are read for every page.
+    pub forms: Arc<[FormRule]>,
 }
 impl RuntimeConfig {
     pub fn new(limit: RateLimit, whitelist_re: RegexSet, blacklist_re: RegexSet) -> Self {
@@ -97,31 +313,272 @@ impl RuntimeConfig {
                 whitelist_re,
                 blacklist_re,
             },
+            assets: AssetConfig::default(),
+            normalizer: Normalizer::default(),
+            near_duplicates: NearDuplicateConfig::default(),
+            traps: TrapConfig::default(),
+            limits: Limits::default(),
+            fetch: FetchConfig::default(),
+            outage: OutageConfig::default(),
+            forms: Arc::new([]),
+        }
+    }
+
+    /// Replace whitelist regexes, only urls matching one of these will be scraped (other than seeds)
+    pub fn with_whitelist<I, S>(mut self, whitelist: I) -> Result<Self, regex::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter.whitelist_re = RegexSet::new(whitelist)?;
+        Ok(self)
+    }
+
+    /// Replace blacklist regexes, urls matching any of these will never be scraped
+    pub fn with_blacklist<I, S>(mut self, blacklist: I) -> Result<Self, regex::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.filter.blacklist_re = RegexSet::new(blacklist)?;
+        Ok(self)
+    }
+}
+
+impl Default for RuntimeConfig {
+    /// Everything is whitelisted, nothing is blacklisted
+    fn default() -> Self {
+        Self::new(
+            RateLimit::new(5),
+            RegexSet::new([".*"]).unwrap(),
+            RegexSet::empty(),
+        )
+    }
+}
+
+/// Builds an [`Orchestrator`], only the storage is required.
+/// ```no_run
+/// # async fn run(db: waper::Database) -> anyhow::Result<()> {
+/// let mut orchestrator = waper::Orchestrator::builder(db)
+///     .seeds(["https://example.com/".parse()?])
+///     .build()?;
+/// orchestrator.start(false).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct OrchestratorBuilder {
+    db: Arc<dyn Storage>,
+    seeds: Vec<Url>,
+    config: Option<Arc<Mutex<RuntimeConfig>>>,
+    fetcher: Option<Arc<dyn Fetcher>>,
+    seen_set: Option<Box<dyn SeenSet>>,
+    events: Option<mpsc::UnboundedSender<CrawlEvent>>,
+    pipeline: Pipeline,
+    #[cfg(feature = "render")]
+    renderer: Option<Renderer>,
+    shutdown_timeout: Option<Duration>,
+}
+
+impl OrchestratorBuilder {
+    pub fn new(db: Arc<dyn Storage>) -> Self {
+        Self {
+            db,
+            seeds: vec![],
+            config: None,
+            fetcher: None,
+            seen_set: None,
+            events: None,
+            pipeline: Pipeline::new(),
+            #[cfg(feature = "render")]
+            renderer: None,
+            shutdown_timeout: None,
+        }
+    }
+
+    /// Urls to start with, these are scraped even if they do not pass the filters
+    pub fn seeds(mut self, seeds: impl IntoIterator<Item = Url>) -> Self {
+        self.seeds.extend(seeds);
+        self
+    }
+
+    /// Defaults to [`RuntimeConfig::default`]
+    pub fn config(self, config: RuntimeConfig) -> Self {
+        self.shared_config(Arc::new(Mutex::new(config)))
+    }
+
+    /// Config which can be modified while scraping is in progress
+    pub fn shared_config(mut self, config: Arc<Mutex<RuntimeConfig>>) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Client used for all requests, defaults to a client with timeouts from [`RuntimeConfig::fetch`]
+    pub fn request_client(self, client: reqwest::Client) -> Self {
+        self.fetcher(client)
+    }
+
+    /// Makes all requests instead of a local client, e.g. [`crate::cluster::Coordinator`]
+    pub fn fetcher(mut self, fetcher: impl Fetcher + 'static) -> Self {
+        self.fetcher = Some(Arc::new(fetcher));
+        self
+    }
+
+    /// Remembers urls already queued, defaults to [`MemorySeenSet`]
+    pub fn seen_set(mut self, seen_set: impl SeenSet + 'static) -> Self {
+        self.seen_set = Some(Box::new(seen_set));
+        self
+    }
+
+    /// Receive a [`CrawlEvent`] for every processed resource
+    pub fn events(mut self, events: mpsc::UnboundedSender<CrawlEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Run `processor` on every scraped page, in the order they are added
+    pub fn processor(mut self, processor: impl PageProcessor + 'static) -> Self {
+        self.pipeline.add(processor);
+        self
+    }
+
+    /// Render pages matching [`crate::render::RenderConfig::patterns`] in a browser
+    #[cfg(feature = "render")]
+    pub fn renderer(mut self, renderer: Renderer) -> Self {
+        self.renderer = Some(renderer);
+        self
+    }
+
+    /// How long in-flight requests get to finish once stopped, defaults to 30 seconds.
+    /// Unfinished ones are left in the frontier.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Fails if no [`Self::fetcher`] was given and the http client can't be created
+    pub fn build(self) -> anyhow::Result<Orchestrator> {
+        let config = self
+            .config
+            .unwrap_or_else(|| Arc::new(Mutex::new(RuntimeConfig::default())));
+        let mut rv = match self.fetcher {
+            Some(fetcher) => Orchestrator::with_fetcher(self.seeds, config, self.db, fetcher),
+            None => Orchestrator::new(self.seeds, config, self.db)?,
+        };
+        if let Some(seen_set) = self.seen_set {
+            rv.noticed_uris = Arc::new(SharedSeenSet::new(seen_set));
+        }
+        rv.events = self.events;
+        rv.pipeline = Arc::new(self.pipeline);
+        #[cfg(feature = "render")]
+        {
+            rv.renderer = self.renderer.map(Arc::new);
+        }
+        if let Some(timeout) = self.shutdown_timeout {
+            rv.shutdown_timeout = timeout;
         }
+        Ok(rv)
     }
 }
 
 impl Orchestrator {
-    pub fn new(seed_urls: Vec<Url>, config: Arc<Mutex<RuntimeConfig>>, db: Database) -> Self {
+    pub fn builder(db: impl Storage + 'static) -> OrchestratorBuilder {
+        OrchestratorBuilder::new(Arc::new(db))
+    }
+
+    /// Fetches with a [`reqwest::Client`] made from [`RuntimeConfig::fetch`]
+    pub fn new(
+        seed_urls: Vec<Url>,
+        config: Arc<Mutex<RuntimeConfig>>,
+        db: Arc<dyn Storage>,
+    ) -> anyhow::Result<Self> {
+        let client = config
+            .lock()
+            .fetch
+            .client()
+            .context("Failed to create http client")?;
+        Ok(Self::with_fetcher(seed_urls, config, db, Arc::new(client)))
+    }
+
+    fn with_fetcher(
+        seed_urls: Vec<Url>,
+        config: Arc<Mutex<RuntimeConfig>>,
+        db: Arc<dyn Storage>,
+        fetcher: Arc<dyn Fetcher>,
+    ) -> Self {
         let (queue_tx, queue_rx) = mpsc::unbounded_channel();
+        let max_distance = config.lock().near_duplicates.max_distance;
+        let (stop, stop_rx) = StopHandle::new();
+        let (paused, paused_rx) = watch::channel(false);
+        let (seeds_tx, seeds_rx) = mpsc::unbounded_channel();
+        let (outage, lost_rx) = OutageDetector::new();
+        let seed_hosts = seed_urls
+            .iter()
+            .filter_map(|x| x.host_str())
+            .map(String::from)
+            .collect();
         Self {
             seed_urls,
             config,
+            seed_hosts: Arc::new(Mutex::new(seed_hosts)),
             queue_rx,
             queue_tx,
-            request_client: reqwest::ClientBuilder::new()
-                .timeout(Duration::from_secs(10))
-                .build()
-                .unwrap(),
-            noticed_uris: Arc::new(Mutex::new(PatriciaSet::new())),
+            fetcher,
+            noticed_uris: Arc::new(SharedSeenSet::new(Box::new(MemorySeenSet::new()))),
+            fingerprints: Arc::new(Mutex::new(SimHashIndex::new(max_distance))),
+            traps: Arc::new(Mutex::new(TrapDetector::new())),
+            outage: Arc::new(Mutex::new(outage)),
+            lost_rx,
             tasks: futures::stream::FuturesUnordered::new(),
             db,
+            events: None,
+            pipeline: Default::default(),
+            #[cfg(feature = "render")]
+            renderer: None,
+            stop,
+            stop_rx,
+            paused: Arc::new(paused),
+            paused_rx,
+            seeds_tx,
+            seeds_rx,
+            shutdown_timeout: Duration::from_secs(30),
+            counters: Default::default(),
         }
     }
 
-    pub async fn start(&mut self, include_unprocessed_from_db: bool) -> anyhow::Result<()> {
+    /// Handle to stop [`Self::start`] from another task, e.g. on Ctrl+C
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Handle to pause, feed and observe [`Self::start`] from another task
+    pub fn control(&self) -> CrawlControl {
+        CrawlControl {
+            config: self.config.clone(),
+            stop: self.stop.clone(),
+            paused: self.paused.clone(),
+            lost: self.lost_rx.clone(),
+            seeds: self.seeds_tx.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+
+    /// Runs till there is nothing left to scrape or it's stopped with [`Self::stop_handle`].
+    /// Session is also stored in `sessions` table.
+    ///
+    /// Logs are in a `crawl` span with `session` field (unix time it started, same as in `sessions`),
+    /// each url in a `scrape` span with `url` and `host` fields.
+    #[tracing::instrument(name = "crawl", skip_all, fields(session))]
+    pub async fn start(&mut self, include_unprocessed_from_db: bool) -> anyhow::Result<Session> {
+        let started = SystemTime::now();
+        tracing::Span::current().record("session", unix_time(started));
         debug!("Starting orchestrator");
-        let mut seed_links = self.seed_urls.clone();
+        let started_at = tokio::time::Instant::now();
+        let normalizer = self.config.lock().normalizer.clone();
+        let mut seed_links: Vec<Url> = self
+            .seed_urls
+            .iter()
+            .map(|x| normalizer.normalize(x))
+            .collect();
         if include_unprocessed_from_db {
             seed_links.append(&mut self.db.get_unprocessed_links().await?);
         }
@@ -131,90 +588,653 @@ impl Orchestrator {
         // Schedule seed links
         for link in &seed_links[..split_point] {
             info!("Scheduling {}", link);
-            self.tasks
-                .push(Self::scrape_link(self.create_context(), link.clone()).boxed());
+            self.tasks.push(self.task(Resource::Page(link.clone())));
         }
         for link in &seed_links[split_point..] {
-            self.queue_tx.send(link.clone())?;
+            self.queue_tx.send(Resource::Page(link.clone()))?;
+            self.counters.enqueue();
         }
-        self.noticed_uris
-            .lock()
-            .extend(seed_links.iter().map(|x| x.to_string()));
+        self.noticed_uris.with(|noticed_uris| {
+            for link in &seed_links {
+                noticed_uris.insert(link.as_str())?;
+            }
+            self.counters.seen(noticed_uris);
+            anyhow::Ok(())
+        })?;
 
-        self.db.add_to_links(self.seed_urls.clone()).await?;
+        self.db
+            .add_to_links(seed_links[..self.seed_urls.len()].to_vec())
+            .await?;
 
-        while let Some(task) = self.tasks.next().await {
+        let exit_reason = loop {
+            if let Some(reason) = *self.stop_rx.borrow() {
+                break reason;
+            }
+            while let Ok(url) = self.seeds_rx.try_recv() {
+                self.add_seed(url).await?;
+            }
+            // a lost connection pauses too, till the task probing it sees it's back
+            let paused = *self.paused_rx.borrow() || *self.lost_rx.borrow();
+            if !paused {
+                while self.tasks.len()
+                    < self.config.lock().rate_limit.max_parallel_requests as usize
+                {
+                    // error drop: The error can never be `TryRecvError::Disconnected`
+                    // as we always have a reference to queue_tx in `Self`
+                    if let Ok(x) = self.queue_rx.try_recv() {
+                        self.counters.dequeue();
+                        info!("Scheduling {}", x);
+                        self.tasks.push(self.task(x));
+                    } else {
+                        break;
+                    }
+                }
+            }
+            self.counters.set_in_flight(self.tasks.len() as u64);
+            // a paused crawl waits for resume even with nothing in flight
+            if self.tasks.is_empty() && !paused {
+                break ExitReason::Completed;
+            }
+
+            let max_duration = self.config.lock().limits.max_duration;
+            let task = tokio::select! {
+                Some(task) = self.tasks.next() => task,
+                // only wake up the loop, state is read above
+                _ = self.stop_rx.changed() => continue,
+                _ = self.paused_rx.changed() => continue,
+                _ = self.lost_rx.changed() => continue,
+                Some(url) = self.seeds_rx.recv() => {
+                    self.add_seed(url).await?;
+                    continue;
+                }
+                _ = tokio::time::sleep_until(started_at + max_duration.unwrap_or_default()),
+                    if max_duration.is_some() =>
+                {
+                    self.stop.stop(ExitReason::MaxDuration);
+                    continue;
+                }
+            };
             if let Err(e) = task {
                 error!("Error: {:?}", e);
                 // continue even if error as task completion might have freed the `RateLimit` pool
             }
-            while self.tasks.len() < self.config.lock().rate_limit.max_parallel_requests as usize {
-                // error drop: The error can never be `TryRecvError::Disconnected`
-                // as we always have a reference to queue_tx in `Self`
-                if let Ok(x) = self.queue_rx.try_recv() {
-                    info!("Scheduling {}", x);
-                    self.tasks
-                        .push(Self::scrape_link(self.create_context(), x).boxed());
-                } else {
-                    break;
+            let exceeded = self
+                .config
+                .lock()
+                .limits
+                .exceeded(started_at.elapsed(), &self.counters);
+            if let Some(reason) = exceeded {
+                info!("Limit reached: {}", reason);
+                self.stop.stop(reason);
+            }
+        };
+        self.counters.set_in_flight(0);
+
+        let frontier = if exit_reason == ExitReason::Completed {
+            // nothing responded after them, but nothing else failed either
+            let context = self.create_context();
+            let suspects = self.outage.lock().restored();
+            for (resource, message) in suspects {
+                Self::record_error(&context, &resource, message).await?;
+            }
+            0
+        } else {
+            self.shutdown().await?
+        };
+        let stats = self.counters.stats();
+        info!(
+            "Seen {} urls, seen set takes about {} MiB of memory",
+            stats.seen,
+            stats.seen_bytes / (1024 * 1024)
+        );
+        let session = Session {
+            started,
+            ended: SystemTime::now(),
+            exit_reason,
+            pages: self.counters.pages.load(Ordering::Relaxed),
+            assets: self.counters.assets.load(Ordering::Relaxed),
+            errors: self.counters.errors.load(Ordering::Relaxed),
+            bytes: self.counters.bytes.load(Ordering::Relaxed),
+            frontier,
+        };
+        self.db.add_to_sessions(&session).await?;
+        self.db.flush().await?;
+        Ok(session)
+    }
+
+    /// Give in-flight tasks `shutdown_timeout` to finish, then write everything still queued to links.
+    /// Returns number of pages left unscraped.
+    async fn shutdown(&mut self) -> anyhow::Result<u64> {
+        info!(
+            "Stopping, waiting up to {:?} for {} in-flight requests",
+            self.shutdown_timeout,
+            self.tasks.len()
+        );
+        let tasks = &mut self.tasks;
+        let drained = tokio::time::timeout(self.shutdown_timeout, async {
+            while let Some(task) = tasks.next().await {
+                if let Err(e) = task {
+                    error!("Error: {:?}", e);
                 }
             }
+        })
+        .await;
+        // Their urls are already in links, so they are still part of the frontier
+        let cancelled = self.tasks.len() as u64;
+        if drained.is_err() {
+            warn!(
+                "Cancelling {} requests which did not finish in time",
+                cancelled
+            );
+            self.tasks.clear();
         }
+
+        // Most of these are already in links, but a task could have been cancelled between
+        // queueing a link and writing it. Assets are not part of the frontier.
+        let mut frontier = vec![];
+        // failed to connect, maybe because the connection was lost, so they are tried again
+        let suspects = self.outage.lock().restored();
+        for (resource, _) in suspects {
+            if let Resource::Page(url) = resource {
+                frontier.push(url);
+            }
+        }
+        while let Ok(resource) = self.queue_rx.try_recv() {
+            if let Resource::Page(url) = resource {
+                frontier.push(url);
+            }
+        }
+        let queued = frontier.len() as u64;
+        self.counters.clear_queue();
+        self.db.add_to_links(frontier).await?;
+        Ok(queued + cancelled)
+    }
+
+    /// Seeds are scraped even if they do not pass the filters, unless already seen
+    async fn add_seed(&mut self, url: Url) -> anyhow::Result<()> {
+        let url = self.config.lock().normalizer.normalize(&url);
+        let inserted = self.noticed_uris.with(|noticed_uris| {
+            let inserted = noticed_uris.insert(url.as_str())?;
+            self.counters.seen(noticed_uris);
+            anyhow::Ok(inserted)
+        })?;
+        if let Some(host) = url.host_str() {
+            self.seed_hosts.lock().insert(host.to_string());
+        }
+        if !inserted {
+            debug!("Seed {} was already seen", url);
+            return Ok(());
+        }
+        self.db.add_to_links(vec![url.clone()]).await?;
+        self.queue_tx.send(Resource::Page(url))?;
+        self.counters.enqueue();
         Ok(())
     }
 
+    fn task(&self, resource: Resource) -> BoxFuture<'static, anyhow::Result<()>> {
+        let url = resource.url();
+        let span =
+            tracing::info_span!("scrape", url = %url, host = url.host_str().unwrap_or_default());
+        Self::process(self.create_context(), resource)
+            .instrument(span)
+            .boxed()
+    }
+
+    async fn process(context: ScraperContext, resource: Resource) -> anyhow::Result<()> {
+        match resource {
+            Resource::Page(url) => Self::scrape_link(context, url).await,
+            Resource::Asset(kind, url) => Self::scrape_asset(context, kind, url).await,
+        }
+    }
+
     async fn scrape_link(context: ScraperContext, url: Url) -> anyhow::Result<()> {
-        let scrape_result = scraper::scrap_links(&url, context.request_client).await;
+        let fetch_config = context.config.lock().fetch.clone();
+        let started = Instant::now();
+        let scrape_result = context.fetcher.page(&url, &fetch_config).await;
+        metrics().request(
+            "page",
+            &context.metrics_host(&url),
+            started.elapsed(),
+            scrape_result.as_ref().map(|x| x.status),
+        );
 
         debug!("Visited {}", url);
 
         let scrape_result = match scrape_result {
-            Ok(r) => {
+            Ok(r) => r,
+            Err(e) if e.is::<Rejected>() => {
+                Self::response(&context, &url).await?;
+                return Self::reject(&context, url, e).await;
+            }
+            Err(e) => {
+                let resource = Resource::Page(url.clone());
+                Self::fail(&context, resource, &e).await?;
+                Err(e).context(format!("Failed to fetch webpage for uri: {url}"))?;
+                unreachable!();
+            }
+        };
+        Self::response(&context, &url).await?;
+
+        context.counters.page(scrape_result.html.len());
+        if scrape_result.truncated {
+            context.db.add_to_truncated(url.clone()).await?;
+        }
+        if let Some(proxy) = &scrape_result.proxy {
+            context
+                .db
+                .add_to_proxies(url.clone(), proxy.clone())
+                .await?;
+        }
+        #[cfg(feature = "render")]
+        let (scrape_result, rendered) = Self::render(&context, &url, scrape_result).await;
+        #[cfg(not(feature = "render"))]
+        let rendered = None;
+        let mut page = Page {
+            url: url.clone(),
+            status: scrape_result.status,
+            headers: scrape_result.headers,
+            html: scrape_result.html,
+            rendered,
+            links: scrape_result.links,
+            data: Default::default(),
+        };
+        let forms = context.config.lock().forms.clone();
+        let html = page.rendered.as_deref().unwrap_or(&page.html);
+        let submitted = forms::form_urls(&forms, &url, html);
+        page.links.extend(submitted);
+        if let Some(processor) = context.pipeline.run(&mut page).await {
+            // recorded so the page is not picked up again as unprocessed
+            let reason = format!("dropped by processor {processor}");
+            return context.db.add_to_skipped(vec![(url, reason)]).await;
+        }
+
+        context
+            .db
+            .add_to_results(url.clone(), page.html.clone())
+            .await?;
+        if let Some(rendered) = &page.rendered {
+            context
+                .db
+                .add_to_rendered(url.clone(), rendered.clone())
+                .await?;
+        }
+        if !scrape_result.metadata.is_empty() {
+            context
+                .db
+                .add_to_metadata(url.clone(), &scrape_result.metadata)
+                .await?;
+        }
+        if !page.data.is_empty() {
+            context.db.add_to_page_data(url.clone(), &page.data).await?;
+        }
+        context.emit(|| CrawlEvent::Page {
+            url: url.clone(),
+            html: page.html.clone(),
+            metadata: scrape_result.metadata.clone(),
+            data: page.data.clone(),
+        });
+
+        if let Some(canonical) = scrape_result.canonical {
+            Self::notice_canonical(&context, &url, canonical).await?;
+        }
+
+        // pages with too little text are never near-duplicates
+        if let Some(fingerprint) = scrape_result.fingerprint {
+            let near_duplicate = {
+                let mut fingerprints = context.fingerprints.lock();
+                let near_duplicate = fingerprints.find(fingerprint).is_some();
+                if !near_duplicate {
+                    fingerprints.insert(fingerprint, ());
+                }
+                near_duplicate
+            };
+            context
+                .db
+                .add_to_fingerprints(url.clone(), fingerprint, near_duplicate)
+                .await?;
+            if near_duplicate && context.config.lock().near_duplicates.skip {
+                debug!("Near-duplicate, not following links: {}", url);
+                return Ok(());
+            }
+        }
+
+        Self::notice(&context, page.links, scrape_result.assets).await
+    }
+
+    /// Render the page if it matches, what's found in the rendered DOM is added to `result`.
+    /// A page which fails to render is still stored as fetched.
+    #[cfg(feature = "render")]
+    async fn render(
+        context: &ScraperContext,
+        url: &Url,
+        mut result: scraper::ScrapingResult,
+    ) -> (scraper::ScrapingResult, Option<String>) {
+        let Some(renderer) = context.renderer.as_ref().filter(|x| x.matches(url)) else {
+            return (result, None);
+        };
+        let html = match renderer.render(url).await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to render {}: {:?}", url, e);
+                return (result, None);
+            }
+        };
+        debug!("Rendered {}", url);
+        let parsed = scraper::parse_page(url, &html);
+        result.links.extend(parsed.links);
+        result.assets.extend(parsed.assets);
+        if !parsed.metadata.is_empty() {
+            result.metadata = parsed.metadata;
+        }
+        result.canonical = result.canonical.take().or(parsed.canonical);
+        // shells of single page apps all look alike, compare what is displayed instead
+        result.fingerprint = parsed.fingerprint;
+        (result, Some(html))
+    }
+
+    /// Request got no response or a broken one. Urls failing to connect are held back
+    /// to find out whether the connection is lost, see [`crate::outage`].
+    async fn fail(
+        context: &ScraperContext,
+        resource: Resource,
+        error: &anyhow::Error,
+    ) -> anyhow::Result<()> {
+        let message = format!("{error:?}");
+        if !is_connectivity_error(error) {
+            return Self::record_error(context, &resource, message).await;
+        }
+        let config = context.config.lock().outage.clone();
+        let verdict =
+            context
+                .outage
+                .lock()
+                .connectivity_error(resource.clone(), message.clone(), &config);
+        match verdict {
+            Verdict::Error => Self::record_error(context, &resource, message).await,
+            Verdict::Hold => Ok(()),
+            Verdict::Probe => Self::check_connection(context, &config).await,
+        }
+    }
+
+    /// Asset failures go to their own table, they are not part of the frontier
+    async fn record_error(
+        context: &ScraperContext,
+        resource: &Resource,
+        message: String,
+    ) -> anyhow::Result<()> {
+        context.counters.error();
+        let url = resource.url();
+        match resource {
+            Resource::Page(_) => {
                 context
                     .db
-                    .add_to_results(url.clone(), r.html.clone())
-                    .await?;
-                r
+                    .add_to_errors(url.clone(), message.clone())
+                    .await?
             }
-            Err(e) => {
+            Resource::Asset(kind, _) => {
                 context
                     .db
-                    .add_to_errors(url.clone(), format!("{e:?}"))
-                    .await?;
-                Err(e).context(format!("Failed to fetch webpage for uri: {url}"))?;
+                    .add_to_asset_errors(url.clone(), *kind, message.clone())
+                    .await?
+            }
+        }
+        context.emit(|| CrawlEvent::Error {
+            url: url.clone(),
+            message,
+        });
+        Ok(())
+    }
+
+    /// Something responded, so urls which failed to connect before failed on their own
+    async fn response(context: &ScraperContext, url: &Url) -> anyhow::Result<()> {
+        let suspects = context.outage.lock().response(url);
+        for (resource, message) in suspects {
+            Self::record_error(context, &resource, message).await?;
+        }
+        Ok(())
+    }
+
+    /// Too many urls failed to connect in a row. If the probe doesn't respond either, the connection
+    /// is lost: scheduling pauses and they are queued again once the probe responds.
+    async fn check_connection(
+        context: &ScraperContext,
+        config: &OutageConfig,
+    ) -> anyhow::Result<()> {
+        let probe = context.outage.lock().probe_url(config);
+        let Some(probe) = probe else {
+            return Ok(());
+        };
+        debug!("Too many connection errors in a row, probing {}", probe);
+        if Self::probe(context, &probe).await {
+            let suspects = context.outage.lock().restored();
+            for (resource, message) in suspects {
+                Self::record_error(context, &resource, message).await?;
+            }
+            return Ok(());
+        }
+
+        warn!(
+            "Connection seems lost, {} does not respond either. Pausing till it does",
+            probe
+        );
+        context.outage.lock().lost();
+        metrics().offline.inc();
+        let started = Instant::now();
+        let mut stop = context.stop_rx.clone();
+        let restored = loop {
+            tokio::select! {
+                _ = tokio::time::sleep(config.probe_interval) => {}
+                // held back urls are written to links by the shutdown
+                _ = stop.wait_for(|x| x.is_some()) => break false,
+            }
+            if Self::probe(context, &probe).await {
+                break true;
+            }
+            debug!("{} does not respond yet", probe);
+        };
+        metrics().offline.dec();
+        if !restored {
+            return Ok(());
+        }
+        let suspects = context.outage.lock().restored();
+        info!(
+            "Connection is back after {:?}, retrying {} urls",
+            started.elapsed(),
+            suspects.len()
+        );
+        for (resource, _) in suspects {
+            context.queue_tx.send(resource)?;
+            context.counters.enqueue();
+        }
+        Ok(())
+    }
+
+    /// Whether anything answered, even an error status or a page which isn't html
+    async fn probe(context: &ScraperContext, url: &Url) -> bool {
+        let fetch_config = context.config.lock().fetch.clone();
+        match context.fetcher.page(url, &fetch_config).await {
+            Ok(_) => true,
+            Err(e) => !is_connectivity_error(&e),
+        }
+    }
+
+    /// Page response was not read on purpose (not html, too large), so it's skipped rather than an error
+    async fn reject(
+        context: &ScraperContext,
+        url: Url,
+        rejected: anyhow::Error,
+    ) -> anyhow::Result<()> {
+        debug!("Skipping {}: {}", url, rejected);
+        context
+            .db
+            .add_to_skipped(vec![(url, rejected.to_string())])
+            .await
+    }
+
+    /// Page at `url` is a copy of `canonical`, so `canonical` doesn't need to be scraped again
+    async fn notice_canonical(
+        context: &ScraperContext,
+        url: &Url,
+        canonical: Url,
+    ) -> anyhow::Result<()> {
+        let canonical = {
+            let config = context.config.lock();
+            if !config.normalizer.honour_canonical {
+                return Ok(());
+            }
+            config.normalizer.normalize(&canonical)
+        };
+        if &canonical == url {
+            return Ok(());
+        }
+        debug!("Canonical for {} is {}", url, canonical);
+        context.noticed_uris.with(|noticed_uris| {
+            noticed_uris.insert(canonical.as_str())?;
+            context.counters.seen(noticed_uris);
+            anyhow::Ok(())
+        })?;
+        context
+            .db
+            .add_to_aliases(vec![(url.clone(), canonical)])
+            .await
+    }
+
+    async fn scrape_asset(
+        context: ScraperContext,
+        kind: AssetKind,
+        url: Url,
+    ) -> anyhow::Result<()> {
+        let (discover_js_urls, fetch_config) = {
+            let config = context.config.lock();
+            (config.assets.discover_js_urls, config.fetch.clone())
+        };
+        let started = Instant::now();
+        let result = context
+            .fetcher
+            .asset(&url, kind, discover_js_urls, &fetch_config)
+            .await;
+        metrics().request(
+            "asset",
+            &context.metrics_host(&url),
+            started.elapsed(),
+            result.as_ref().map(|x| x.status),
+        );
+
+        debug!("Fetched {} asset {}", kind, url);
+
+        let result = match result {
+            Ok(r) => r,
+            Err(e) if e.is::<Rejected>() => {
+                Self::response(&context, &url).await?;
+                debug!("Skipping {}: {}", url, e);
+                return context
+                    .db
+                    .add_to_asset_errors(url, kind, e.to_string())
+                    .await;
+            }
+            Err(e) => {
+                let resource = Resource::Asset(kind, url.clone());
+                Self::fail(&context, resource, &e).await?;
+                Err(e).context(format!("Failed to fetch asset for uri: {url}"))?;
                 unreachable!();
             }
         };
+        Self::response(&context, &url).await?;
+        context.counters.asset(result.content.len());
+        if result.truncated {
+            context.db.add_to_truncated(url.clone()).await?;
+        }
+        if let Some(proxy) = &result.proxy {
+            context
+                .db
+                .add_to_proxies(url.clone(), proxy.clone())
+                .await?;
+        }
+        context.emit(|| CrawlEvent::Asset {
+            url: url.clone(),
+            kind,
+            content_type: result.content_type.clone(),
+            content: result.content.clone(),
+        });
+        context
+            .db
+            .add_to_assets(url, kind, result.content_type, result.content)
+            .await?;
+
+        Self::notice(&context, result.links, result.assets).await
+    }
 
+    /// Queue links and assets which pass the filters and haven't been seen before
+    async fn notice(
+        context: &ScraperContext,
+        links: Vec<Url>,
+        assets: Vec<(AssetKind, Url)>,
+    ) -> anyhow::Result<()> {
         let mut links_to_add = vec![];
-        {
-            // No async/heavy operation after this,
-            // safe to take the lock
-            let mut noticed_uris_lock = context.noticed_uris.lock();
-            for link in scrape_result.links {
+        let mut aliases = vec![];
+        let mut skipped = vec![];
+        let resources: Vec<_> = {
+            let config = context.config.lock();
+            let mut normalize = |link: Url| {
+                let normalized = config.normalizer.normalize(&link);
+                if normalized != link {
+                    aliases.push((link, normalized.clone()));
+                }
+                normalized
+            };
+            let links: Vec<_> = links.into_iter().map(&mut normalize).collect();
+            let assets: Vec<_> = assets
+                .into_iter()
+                .map(|(kind, link)| (kind, normalize(link)))
+                .collect();
+            let links = links.into_iter().filter_map(|link| {
                 // TODO: too many to_string operations
                 // benchmark and move to passing strings around instead if required.
-                if !context.config.lock().filter.is_match(link.as_str()) {
+                if !config.filter.is_match(link.as_str()) {
                     debug!("Does not match filter: {}", link);
-                    continue;
+                    return None;
                 }
-
-                if noticed_uris_lock.contains(&link.to_string()) {
-                    debug!("Already Noticed: {}", link);
+                Some(Resource::Page(link))
+            });
+            let assets = assets.into_iter().filter_map(|(kind, link)| {
+                if !config.assets.wants(kind) || config.filter.is_blacklisted(link.as_str()) {
+                    return None;
+                }
+                Some(Resource::Asset(kind, link))
+            });
+            links.chain(assets).collect()
+        };
+        // No async operation in here, safe to hold the locks
+        context.noticed_uris.with(|noticed_uris| {
+            let mut traps = context.traps.lock();
+            let config = context.config.lock();
+            for resource in resources {
+                if !noticed_uris.insert(&resource.seen_key())? {
+                    debug!("Already Noticed: {}", resource);
                     continue;
                 }
-                debug!("Found: {}", link);
+                debug!("Found: {}", resource);
 
-                noticed_uris_lock.insert(&link.to_string());
-                links_to_add.push(link.clone());
-                // Self::add_to_links(vec![link.clone()], context.outfile.clone()).await?;
+                if let Resource::Page(link) = &resource {
+                    if let Err(reason) = traps.check(&config.traps, link) {
+                        debug!("Skipping {}: {}", link, reason);
+                        skipped.push((link.clone(), reason.to_string()));
+                        continue;
+                    }
+                    links_to_add.push(link.clone());
+                }
                 context
                     .queue_tx
-                    .send(link)
+                    .send(resource)
                     .expect("reciever should never be dropped as long as scrapes are running");
+                context.counters.enqueue();
             }
-        }
+            context.counters.seen(noticed_uris);
+            anyhow::Ok(())
+        })?;
         context.db.add_to_links(links_to_add).await?;
+        context.db.add_to_aliases(aliases).await?;
+        context.db.add_to_skipped(skipped).await?;
         Ok(())
     }
 
@@ -222,10 +1242,20 @@ impl Orchestrator {
         let queue_tx = self.queue_tx.clone();
         ScraperContext {
             config: self.config.clone(),
+            seed_hosts: self.seed_hosts.clone(),
             noticed_uris: self.noticed_uris.clone(),
-            request_client: self.request_client.clone(),
+            fingerprints: self.fingerprints.clone(),
+            traps: self.traps.clone(),
+            outage: self.outage.clone(),
+            stop_rx: self.stop_rx.clone(),
+            fetcher: self.fetcher.clone(),
             db: self.db.clone(),
             queue_tx,
+            events: self.events.clone(),
+            pipeline: self.pipeline.clone(),
+            #[cfg(feature = "render")]
+            renderer: self.renderer.clone(),
+            counters: self.counters.clone(),
         }
     }
 }
@@ -240,8 +1270,37 @@ impl From<u64> for RateLimit {
 
 struct ScraperContext {
     config: Arc<Mutex<RuntimeConfig>>,
-    noticed_uris: Arc<Mutex<PatriciaSet>>,
-    request_client: reqwest::Client,
-    queue_tx: mpsc::UnboundedSender<Url>,
-    db: Database,
+    seed_hosts: Arc<Mutex<HashSet<String>>>,
+    noticed_uris: Arc<SharedSeenSet>,
+    fingerprints: Arc<Mutex<SimHashIndex<()>>>,
+    traps: Arc<Mutex<TrapDetector>>,
+    outage: Arc<Mutex<OutageDetector>>,
+    stop_rx: watch::Receiver<Option<ExitReason>>,
+    fetcher: Arc<dyn Fetcher>,
+    queue_tx: mpsc::UnboundedSender<Resource>,
+    db: Arc<dyn Storage>,
+    events: Option<mpsc::UnboundedSender<CrawlEvent>>,
+    pipeline: Arc<Pipeline>,
+    #[cfg(feature = "render")]
+    renderer: Option<Arc<Renderer>>,
+    counters: Arc<Counters>,
+}
+
+impl ScraperContext {
+    fn emit(&self, event: impl FnOnce() -> CrawlEvent) {
+        if let Some(events) = &self.events {
+            // receiver not listening anymore is not our concern
+            let _ = events.send(event());
+        }
+    }
+
+    /// `host` label of request metrics, `other` for hosts which are not seeds
+    fn metrics_host(&self, url: &Url) -> String {
+        let host = url.host_str().unwrap_or_default();
+        if self.seed_hosts.lock().contains(host) {
+            host.to_string()
+        } else {
+            "other".to_string()
+        }
+    }
 }
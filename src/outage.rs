@@ -0,0 +1,122 @@
+//! Telling a lost connection apart from urls which fail on their own. Urls failing to connect
+//! are held back until something responds; when too many fail in a row the connection is probed,
+//! and if it's lost, scheduling pauses and the held urls are retried once it's back.
+use std::time::Duration;
+
+use tokio::sync::watch;
+use url::Url;
+
+use crate::metrics::error_class;
+use crate::orchestrator::Resource;
+
+#[derive(Debug, Clone)]
+pub struct OutageConfig {
+    /// Connectivity errors in a row (nothing responding in between) after which the connection
+    /// is probed, `0` records every error right away
+    pub max_errors: usize,
+    /// Wait between probes while the connection is lost
+    pub probe_interval: Duration,
+    /// Probed to check the connection, defaults to the last url which got a response
+    pub probe_url: Option<Url>,
+}
+
+impl Default for OutageConfig {
+    fn default() -> Self {
+        Self {
+            max_errors: 10,
+            probe_interval: Duration::from_secs(10),
+            probe_url: None,
+        }
+    }
+}
+
+/// No response at all, as opposed to an error status or a broken body
+pub fn is_connectivity_error(error: &anyhow::Error) -> bool {
+    matches!(error_class(error), "connect" | "timeout")
+}
+
+/// What to do with a url which failed with a connectivity error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Record it in `errors` now
+    Error,
+    /// Held back, see [`OutageDetector::response`]
+    Hold,
+    /// Held back, and it's the one too many: probe the connection
+    Probe,
+}
+
+/// (resource, error message) of a url which failed to connect
+pub type Suspect = (Resource, String);
+
+/// Keeps the state required by [`OutageConfig`]
+pub struct OutageDetector {
+    /// Failed to connect since the last response
+    suspects: Vec<Suspect>,
+    last_response: Option<Url>,
+    probing: bool,
+    lost: watch::Sender<bool>,
+}
+
+impl OutageDetector {
+    /// Receiver is true while the connection is lost
+    pub fn new() -> (Self, watch::Receiver<bool>) {
+        let (lost, lost_rx) = watch::channel(false);
+        let detector = Self {
+            suspects: vec![],
+            last_response: None,
+            probing: false,
+            lost,
+        };
+        (detector, lost_rx)
+    }
+
+    /// Something responded to a request to `url`, whatever the status.
+    /// Returns urls held back since the last response, they failed on their own.
+    pub fn response(&mut self, url: &Url) -> Vec<Suspect> {
+        self.last_response = Some(url.clone());
+        if self.probing {
+            // the probe decides what happens to them
+            return vec![];
+        }
+        std::mem::take(&mut self.suspects)
+    }
+
+    pub fn connectivity_error(
+        &mut self,
+        resource: Resource,
+        message: String,
+        config: &OutageConfig,
+    ) -> Verdict {
+        if config.max_errors == 0 {
+            return Verdict::Error;
+        }
+        self.suspects.push((resource, message));
+        if self.probing || self.suspects.len() < config.max_errors {
+            return Verdict::Hold;
+        }
+        self.probing = true;
+        Verdict::Probe
+    }
+
+    /// Configured probe, else the last url which responded, else one which failed
+    pub fn probe_url(&self, config: &OutageConfig) -> Option<Url> {
+        config
+            .probe_url
+            .clone()
+            .or_else(|| self.last_response.clone())
+            .or_else(|| self.suspects.first().map(|(x, _)| x.url().clone()))
+    }
+
+    /// Probe didn't respond either
+    pub fn lost(&mut self) {
+        self.lost.send_replace(true);
+    }
+
+    /// Probe responded, returns the urls held back
+    pub fn restored(&mut self) -> Vec<Suspect> {
+        self.probing = false;
+        self.lost.send_replace(false);
+        std::mem::take(&mut self.suspects)
+    }
+}
@@ -0,0 +1,241 @@
+//! Processors invoked for every scraped page, before it is stored and its links are followed.
+//! They can enrich the page with extra data, remove links, add urls or drop the page entirely.
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::prelude::*;
+use crate::simhash;
+
+/// Scraped page as seen by processors
+pub struct Page {
+    pub url: Url,
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub html: String,
+    /// DOM after running scripts, for pages rendered in a browser
+    pub rendered: Option<String>,
+    /// Links which will be followed (still subject to filters)
+    pub links: Vec<Url>,
+    /// Extra data added by processors, stored in `page_data` table
+    pub data: Map<String, Value>,
+}
+
+impl Page {
+    /// Parsed html, rendered DOM if there is one. Not `Send`, so don't hold it across `.await`
+    pub fn document(&self) -> select::document::Document {
+        select::document::Document::from(self.rendered.as_deref().unwrap_or(&self.html))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Continue,
+    /// Page is not stored and it's links are not followed, remaining processors are skipped
+    Drop,
+}
+
+#[async_trait]
+pub trait PageProcessor: Send + Sync {
+    /// Used in logs and as reason when page is dropped
+    fn name(&self) -> &str;
+
+    async fn process(&self, page: &mut Page) -> anyhow::Result<Outcome>;
+}
+
+/// Processors run in order they are added
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    processors: Vec<Arc<dyn PageProcessor>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, processor: impl PageProcessor + 'static) {
+        self.processors.push(Arc::new(processor));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    /// Returns name of the processor which dropped the page, if any.
+    /// A failing processor is logged and skipped, it doesn't fail the page.
+    pub async fn run(&self, page: &mut Page) -> Option<String> {
+        for processor in &self.processors {
+            match processor.process(page).await {
+                Ok(Outcome::Continue) => {}
+                Ok(Outcome::Drop) => {
+                    debug!("{} dropped by processor {}", page.url, processor.name());
+                    return Some(processor.name().to_string());
+                }
+                Err(e) => warn!(
+                    "Processor {} failed for {}: {:?}",
+                    processor.name(),
+                    page.url,
+                    e
+                ),
+            }
+        }
+        None
+    }
+}
+
+/// Adds visible text of the page as `text`
+pub struct TextExtractor;
+
+#[async_trait]
+impl PageProcessor for TextExtractor {
+    fn name(&self) -> &str {
+        "text"
+    }
+
+    async fn process(&self, page: &mut Page) -> anyhow::Result<Outcome> {
+        let text = simhash::document_text(&page.document());
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        page.data.insert("text".into(), Value::String(text));
+        Ok(Outcome::Continue)
+    }
+}
+
+/// Runs an external command for each page.
+///
+/// Command receives a json object on stdin:
+/// `{"url": "...", "status": 200, "headers": {"content-type": "..."}, "html": "...", "rendered": null, "links": ["..."], "data": {}}`
+///
+/// And can optionally print a json object on stdout, all fields are optional:
+/// `{"drop": false, "data": {"class": "article"}, "links": ["..."], "urls": ["..."]}`
+/// - `drop`: drop the page
+/// - `data`: merged into page data
+/// - `links`: replaces links to be followed
+/// - `urls`: extra urls to be followed
+///
+/// Command is killed if it doesn't exit within [`CommandProcessor::timeout`], 30 seconds by default.
+pub struct CommandProcessor {
+    name: String,
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+#[derive(Deserialize, Default)]
+struct CommandOutput {
+    #[serde(default)]
+    drop: bool,
+    #[serde(default)]
+    data: Map<String, Value>,
+    links: Option<Vec<String>>,
+    #[serde(default)]
+    urls: Vec<String>,
+}
+
+impl CommandProcessor {
+    pub fn new(program: impl Into<String>, args: Vec<String>) -> Self {
+        let program = program.into();
+        Self {
+            name: format!("command:{program}"),
+            program,
+            args,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Time the command has to exit for each page, it is killed after
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Split shell like `command` (`"classify.py --model x"`) into program and args
+    pub fn from_shell(command: &str) -> anyhow::Result<Self> {
+        let mut parts = shlex::split(command)
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Invalid command: {command}"))?;
+        let program = parts.remove(0);
+        Ok(Self::new(program, parts))
+    }
+}
+
+#[async_trait]
+impl PageProcessor for CommandProcessor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn process(&self, page: &mut Page) -> anyhow::Result<Outcome> {
+        let headers: Map<String, Value> = page
+            .headers
+            .iter()
+            .filter_map(|(k, v)| Some((k.to_string(), Value::String(v.to_str().ok()?.into()))))
+            .collect();
+        let input = serde_json::json!({
+            "url": page.url.as_str(),
+            "status": page.status,
+            "headers": headers,
+            "html": page.html,
+            "rendered": page.rendered,
+            "links": page.links.iter().map(Url::as_str).collect::<Vec<_>>(),
+            "data": page.data,
+        });
+
+        let mut child = tokio::process::Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = serde_json::to_vec(&input)?;
+        // write concurrently with reading output, else a command printing a lot can deadlock
+        let write = async move {
+            stdin.write_all(&input).await?;
+            stdin.shutdown().await
+        };
+        // dropping the child on timeout kills it
+        let (write, output) = tokio::time::timeout(self.timeout, async {
+            tokio::join!(write, child.wait_with_output())
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("{} timed out after {:?}", self.program, self.timeout))?;
+        let output = output?;
+        if !output.status.success() {
+            anyhow::bail!("{} exited with {}", self.program, output.status);
+        }
+        // command might not read stdin at all, that's fine
+        if let Err(e) = write {
+            debug!("Failed to write page to {}: {}", self.program, e);
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.trim().is_empty() {
+            return Ok(Outcome::Continue);
+        }
+        let output: CommandOutput = serde_json::from_str(&stdout)?;
+        page.data.extend(output.data);
+        if let Some(links) = output.links {
+            page.links = parse_urls(&page.url, links);
+        }
+        page.links.extend(parse_urls(&page.url, output.urls));
+        Ok(if output.drop {
+            Outcome::Drop
+        } else {
+            Outcome::Continue
+        })
+    }
+}
+
+fn parse_urls(base: &Url, urls: Vec<String>) -> Vec<Url> {
+    urls.into_iter()
+        .filter_map(|x| base.join(&x).ok())
+        .collect()
+}
@@ -0,0 +1,104 @@
+//! Rendering pages in headless Chromium, for sites which build their content with JavaScript.
+//! Chromium has to be installed locally, it's started on first use and driven over the DevTools protocol.
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use chromiumoxide::{Browser, BrowserConfig};
+use futures::StreamExt;
+use regex::RegexSet;
+use tokio::sync::{OnceCell, Semaphore};
+use url::Url;
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct RenderConfig {
+    /// Pages whose url matches any of these are rendered
+    pub patterns: RegexSet,
+    /// Looked up in usual install locations (and `CHROME` env var) if not set
+    pub executable: Option<PathBuf>,
+    /// Extra command line arguments for Chromium, e.g. `--no-sandbox` when running as root
+    pub args: Vec<String>,
+    /// Max wait for a page to load
+    pub timeout: Duration,
+    /// Max pages rendered at once
+    pub tabs: usize,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            patterns: RegexSet::empty(),
+            executable: None,
+            args: vec![],
+            timeout: Duration::from_secs(30),
+            tabs: 4,
+        }
+    }
+}
+
+pub struct Renderer {
+    config: RenderConfig,
+    browser: OnceCell<Browser>,
+    tabs: Semaphore,
+}
+
+impl Renderer {
+    pub fn new(config: RenderConfig) -> Self {
+        Self {
+            tabs: Semaphore::new(config.tabs.max(1)),
+            config,
+            browser: OnceCell::new(),
+        }
+    }
+
+    pub fn matches(&self, url: &Url) -> bool {
+        self.config.patterns.is_match(url.as_str())
+    }
+
+    async fn browser(&self) -> anyhow::Result<&Browser> {
+        self.browser
+            .get_or_try_init(|| async {
+                let mut builder = BrowserConfig::builder()
+                    .request_timeout(self.config.timeout)
+                    .args(self.config.args.clone());
+                if let Some(executable) = &self.config.executable {
+                    builder = builder.chrome_executable(executable);
+                }
+                let config = builder.build().map_err(anyhow::Error::msg)?;
+                let (browser, mut handler) = Browser::launch(config)
+                    .await
+                    .context("Failed to launch Chromium")?;
+                // drives the connection, must keep running as long as the browser is used
+                tokio::spawn(async move {
+                    while let Some(event) = handler.next().await {
+                        if let Err(e) = event {
+                            debug!("Chromium connection: {:?}", e);
+                        }
+                    }
+                });
+                info!("Started Chromium for rendering");
+                Ok(browser)
+            })
+            .await
+    }
+
+    /// Html of the DOM once the page at `url` has loaded
+    pub async fn render(&self, url: &Url) -> anyhow::Result<String> {
+        let browser = self.browser().await?;
+        let _tab = self.tabs.acquire().await?;
+        let page = browser.new_page("about:blank").await?;
+        let content = tokio::time::timeout(self.config.timeout, async {
+            page.goto(url.as_str()).await?.wait_for_navigation().await?;
+            page.content().await
+        })
+        .await;
+        if let Err(e) = page.close().await {
+            debug!("Failed to close tab of {}: {:?}", url, e);
+        }
+        content
+            .map_err(|_| anyhow::anyhow!("Page didn't load in {:?}", self.config.timeout))?
+            .context(format!("Failed to render {url}"))
+    }
+}
@@ -1,15 +1,73 @@
-use select::predicate::Name;
+use select::predicate::{Name, Predicate};
+use serde::{Deserialize, Serialize};
 use url::Url;
 
+use crate::assets::{self, AssetKind};
+use crate::fetch::{self, FetchConfig};
+use crate::metadata::PageMetadata;
+use crate::simhash;
+
+#[derive(Serialize, Deserialize)]
 pub struct ScrapingResult {
+    pub status: u16,
+    #[serde(with = "crate::cluster::header_list")]
+    pub headers: reqwest::header::HeaderMap,
     pub links: Vec<Url>,
     pub html: String,
+    pub metadata: PageMetadata,
+    pub assets: Vec<(AssetKind, Url)>,
+    /// Url from `<link rel=canonical>`
+    pub canonical: Option<Url>,
+    /// SimHash of page text, `None` if there's too little text
+    pub fingerprint: Option<u64>,
+    /// Body was cut at `max_body_size`
+    pub truncated: bool,
+    /// Set by [`crate::proxy::ProxyPool`]
+    #[serde(default)]
+    pub proxy: Option<String>,
 }
 
-pub async fn scrap_links(url: &Url, client: reqwest::Client) -> anyhow::Result<ScrapingResult> {
+/// Fails with [`fetch::Rejected`] if the page is not html or is too large
+pub async fn scrap_links(
+    url: &Url,
+    client: reqwest::Client,
+    config: &FetchConfig,
+) -> anyhow::Result<ScrapingResult> {
     let reqwest_url: reqwest::Url = reqwest::Url::parse(url.as_ref())?;
-    let text = client.get(reqwest_url).send().await?.text().await?;
-    let links = select::document::Document::from(text.as_str())
+    let response = client.get(reqwest_url).send().await?;
+    let status = response.status().as_u16();
+    let headers = response.headers().clone();
+    fetch::check_html(&headers)?;
+    let body = fetch::read_body(response, config).await?;
+    let text = fetch::decode_text(&body.content, &headers);
+    let parsed = parse_page(url, &text);
+
+    Ok(ScrapingResult {
+        status,
+        headers,
+        links: parsed.links,
+        html: text,
+        metadata: parsed.metadata,
+        assets: parsed.assets,
+        canonical: parsed.canonical,
+        fingerprint: parsed.fingerprint,
+        truncated: body.truncated,
+        proxy: None,
+    })
+}
+
+/// What is extracted from html of a page, whether fetched or rendered
+pub struct ParsedPage {
+    pub links: Vec<Url>,
+    pub metadata: PageMetadata,
+    pub assets: Vec<(AssetKind, Url)>,
+    pub canonical: Option<Url>,
+    pub fingerprint: Option<u64>,
+}
+
+pub fn parse_page(url: &Url, html: &str) -> ParsedPage {
+    let document = select::document::Document::from(html);
+    let links = document
         .find(Name("a"))
         .filter_map(|n| {
             let value = n.attr("href")?;
@@ -24,6 +82,22 @@ pub async fn scrap_links(url: &Url, client: reqwest::Client) -> anyhow::Result<S
             }
         })
         .collect::<Vec<_>>();
+    let metadata = PageMetadata::extract(&document);
+    let assets = assets::html_references(url, &document);
+    let canonical = document
+        .find(Name("link").and(|n: &select::node::Node| {
+            n.attr("rel")
+                .map(|x| x.eq_ignore_ascii_case("canonical"))
+                .unwrap_or(false)
+        }))
+        .find_map(|n| url.join(n.attr("href")?).ok());
+    let fingerprint = simhash::simhash(&simhash::document_text(&document));
 
-    Ok(ScrapingResult { links, html: text })
+    ParsedPage {
+        links,
+        metadata,
+        assets,
+        canonical,
+        fingerprint,
+    }
 }
@@ -0,0 +1,228 @@
+//! Urls a crawl has already noticed, so each one is queued only once. [`MemorySeenSet`] is exact
+//! and the default, crawls with more urls than fit in memory can trade speed ([`SqliteSeenSet`])
+//! or exactness ([`BloomSeenSet`]) for memory.
+use std::path::Path;
+
+use anyhow::Context;
+use patricia_tree::PatriciaSet;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use crate::prelude::*;
+
+/// Set of urls used by [`crate::Orchestrator`], see [`crate::OrchestratorBuilder::seen_set`]
+pub trait SeenSet: Send {
+    /// Returns false if `url` was already in the set
+    fn insert(&mut self, url: &str) -> anyhow::Result<bool>;
+
+    fn contains(&self, url: &str) -> anyhow::Result<bool>;
+
+    /// Urls in the set
+    fn len(&self) -> u64;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate bytes of memory taken
+    fn memory_usage(&self) -> u64;
+
+    /// Whether lookups wait for the disk. The orchestrator then uses the set inside
+    /// [`tokio::task::block_in_place`], so they don't hold up other tasks of its thread.
+    fn is_blocking(&self) -> bool {
+        false
+    }
+}
+
+/// Seen set shared by the scraping tasks of a crawl.
+///
+/// Blocking sets are locked and used inside [`tokio::task::block_in_place`], tasks waiting for
+/// the lock included. Each of them hands its runtime thread's other tasks over to a new thread,
+/// which costs much less than stalling them behind a query. Lookups are still serialized by the
+/// lock, queuing them to a thread of their own would not make them any faster. On a current
+/// thread runtime `block_in_place` is not available and the set is used directly.
+pub(crate) struct SharedSeenSet {
+    set: Mutex<Box<dyn SeenSet>>,
+    blocking: bool,
+}
+
+impl SharedSeenSet {
+    pub fn new(set: Box<dyn SeenSet>) -> Self {
+        Self {
+            blocking: set.is_blocking(),
+            set: Mutex::new(set),
+        }
+    }
+
+    /// Run `f` with the set locked
+    pub fn with<T>(&self, f: impl FnOnce(&mut dyn SeenSet) -> T) -> T {
+        let run = || f(&mut **self.set.lock());
+        let multi_thread =
+            Handle::try_current().is_ok_and(|x| x.runtime_flavor() == RuntimeFlavor::MultiThread);
+        if self.blocking && multi_thread {
+            tokio::task::block_in_place(run)
+        } else {
+            run()
+        }
+    }
+}
+
+/// Bytes of a trie node, on top of the url bytes it stores
+const NODE_SIZE: u64 = 32;
+
+/// Exact, every url is kept in memory. Lost when the crawl ends.
+#[derive(Default)]
+pub struct MemorySeenSet {
+    urls: PatriciaSet,
+    len: u64,
+    bytes: u64,
+}
+
+impl MemorySeenSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SeenSet for MemorySeenSet {
+    fn insert(&mut self, url: &str) -> anyhow::Result<bool> {
+        let inserted = self.urls.insert(url);
+        if inserted {
+            self.len += 1;
+            self.bytes += url.len() as u64;
+        }
+        Ok(inserted)
+    }
+
+    fn contains(&self, url: &str) -> anyhow::Result<bool> {
+        Ok(self.urls.contains(url))
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// An upper bound, prefixes shared by urls are only stored once
+    fn memory_usage(&self) -> u64 {
+        self.bytes + self.len * NODE_SIZE
+    }
+}
+
+/// Bytes of sqlite's page cache
+const SQLITE_CACHE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Exact, kept in a sqlite file so it survives restarts (use with `--include-db-links`).
+/// Only sqlite's page cache is in memory, but every lookup is a query.
+pub struct SqliteSeenSet {
+    conn: rusqlite::Connection,
+    len: u64,
+}
+
+impl SqliteSeenSet {
+    /// Urls already in the file count as seen
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = rusqlite::Connection::open(path)
+            .with_context(|| format!("Failed to open seen set {}", path.display()))?;
+        // losing the last inserts on a crash only means scraping a few urls again
+        conn.execute_batch(&format!(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = OFF;
+            PRAGMA cache_size = -{};
+            CREATE TABLE IF NOT EXISTS seen (url TEXT PRIMARY KEY) WITHOUT ROWID;",
+            SQLITE_CACHE_SIZE / 1024
+        ))?;
+        let len: i64 = conn.query_row("SELECT count(*) FROM seen", [], |row| row.get(0))?;
+        debug!("Seen set {} has {} urls", path.display(), len);
+        Ok(Self {
+            conn,
+            len: len as u64,
+        })
+    }
+}
+
+impl SeenSet for SqliteSeenSet {
+    fn insert(&mut self, url: &str) -> anyhow::Result<bool> {
+        let inserted = self
+            .conn
+            .prepare_cached("INSERT OR IGNORE INTO seen (url) VALUES (?1)")?
+            .execute([url])?
+            == 1;
+        if inserted {
+            self.len += 1;
+        }
+        Ok(inserted)
+    }
+
+    fn contains(&self, url: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .conn
+            .prepare_cached("SELECT 1 FROM seen WHERE url = ?1")?
+            .exists([url])?)
+    }
+
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Size of the page cache, it's only filled as the file grows
+    fn memory_usage(&self) -> u64 {
+        SQLITE_CACHE_SIZE
+    }
+
+    fn is_blocking(&self) -> bool {
+        true
+    }
+}
+
+/// Bloom filter sized for `capacity` urls, its memory is allocated upfront.
+/// A seen url is never queued again, but `fp_rate` of new urls are taken as seen and skipped,
+/// more once it holds over `capacity` urls. Lost when the crawl ends.
+pub struct BloomSeenSet {
+    filter: fastbloom::BloomFilter,
+    len: u64,
+    capacity: u64,
+}
+
+impl BloomSeenSet {
+    /// `fp_rate` has to be between 0 and 1
+    pub fn new(fp_rate: f64, capacity: u64) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            fp_rate > 0.0 && fp_rate < 1.0,
+            "False positive rate has to be between 0 and 1, got {fp_rate}"
+        );
+        Ok(Self {
+            filter: fastbloom::BloomFilter::with_false_pos(fp_rate)
+                .expected_items(capacity.max(1) as usize),
+            len: 0,
+            capacity,
+        })
+    }
+}
+
+impl SeenSet for BloomSeenSet {
+    fn insert(&mut self, url: &str) -> anyhow::Result<bool> {
+        if self.filter.insert(url) {
+            return Ok(false);
+        }
+        self.len += 1;
+        if self.len == self.capacity + 1 {
+            warn!(
+                "Seen set is over its capacity of {} urls, more new urls will be skipped as seen",
+                self.capacity
+            );
+        }
+        Ok(true)
+    }
+
+    fn contains(&self, url: &str) -> anyhow::Result<bool> {
+        Ok(self.filter.contains(url))
+    }
+
+    /// Urls taken as new, false positives are not counted
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn memory_usage(&self) -> u64 {
+        self.filter.num_bits() as u64 / 8
+    }
+}
@@ -0,0 +1,414 @@
+//! Local HTTP/JSON api to start, control and observe crawls, used by `waper serve`.
+//!
+//! All crawls write to the same sqlite file, which is also what `/pages` reads from.
+//! Crawls only live as long as the server, their results stay in the file.
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{FromRequest, FromRequestParts, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, patch, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+use url::Url;
+
+use crate::metrics;
+use crate::prelude::*;
+use crate::session::{Session, Stats};
+use crate::{CrawlControl, Database, Orchestrator, RuntimeConfig};
+
+pub struct Server {
+    db: Database,
+    config: RuntimeConfig,
+    crawls: Mutex<Vec<Crawl>>,
+}
+
+struct Crawl {
+    seeds: Vec<Url>,
+    control: CrawlControl,
+    result: Arc<Mutex<Option<Result<Session, String>>>>,
+}
+
+/// Returned by every `/crawls` endpoint
+#[derive(Debug, Serialize)]
+struct CrawlStatus {
+    /// Index in the list of crawls started by this server
+    id: usize,
+    /// `running`, `paused`, `offline` (connection lost), `finished` or `failed`
+    state: &'static str,
+    seeds: Vec<String>,
+    #[serde(flatten)]
+    stats: Stats,
+    exit_reason: Option<&'static str>,
+    error: Option<String>,
+}
+
+/// Changes to [`RuntimeConfig`], `None` fields are left as they are
+#[derive(Debug, Default, Deserialize)]
+struct ConfigChange {
+    whitelist: Option<Vec<String>>,
+    blacklist: Option<Vec<String>>,
+    max_parallel_requests: Option<u64>,
+    max_pages: Option<u64>,
+    /// Seconds
+    max_duration: Option<u64>,
+}
+
+impl ConfigChange {
+    /// Nothing is changed if any regex is invalid
+    fn apply(self, config: &mut RuntimeConfig) -> Result<(), ApiError> {
+        let whitelist = self.whitelist.map(regex::RegexSet::new).transpose()?;
+        let blacklist = self.blacklist.map(regex::RegexSet::new).transpose()?;
+        if let Some(x) = whitelist {
+            config.filter.whitelist_re = x;
+        }
+        if let Some(x) = blacklist {
+            config.filter.blacklist_re = x;
+        }
+        if let Some(x) = self.max_parallel_requests {
+            config.rate_limit.set_max_parallel_requests(x);
+        }
+        if let Some(x) = self.max_pages {
+            config.limits.max_pages = Some(x);
+        }
+        if let Some(x) = self.max_duration {
+            config.limits.max_duration = Some(Duration::from_secs(x));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NewCrawl {
+    seeds: Vec<String>,
+    /// Also scrape unprocessed links left in the file by earlier crawls
+    #[serde(default)]
+    include_db_links: bool,
+    #[serde(flatten)]
+    config: ConfigChange,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewSeeds {
+    seeds: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PagesQuery {
+    /// Only pages whose url starts with this
+    #[serde(default)]
+    prefix: String,
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct PageQuery {
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PageSummary {
+    url: String,
+    time: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PageContent {
+    url: String,
+    time: String,
+    html: String,
+}
+
+/// Rendered as `{"error": "..."}`
+#[derive(Debug)]
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self(StatusCode::BAD_REQUEST, message.into())
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self(StatusCode::NOT_FOUND, message.into())
+    }
+}
+
+impl From<regex::Error> for ApiError {
+    fn from(e: regex::Error) -> Self {
+        Self::bad_request(e.to_string())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        error!("Api error: {:?}", e);
+        Self(StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}"))
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(e: JsonRejection) -> Self {
+        Self(e.status(), e.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(e: PathRejection) -> Self {
+        Self(e.status(), e.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> Self {
+        Self(e.status(), e.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+// Same as axum's extractors, but malformed requests also get an [`ApiError`]
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+struct Body<T>(T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+struct Path<T>(T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+struct Query<T>(T);
+
+/// Default number of pages returned by `/pages`
+const PAGE_LIMIT: i64 = 100;
+
+impl Server {
+    /// `config` is the starting point for every crawl, requests can change filters and limits
+    pub fn new(db: Database, config: RuntimeConfig) -> Self {
+        Self {
+            db,
+            config,
+            crawls: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/crawls", get(list_crawls).post(start_crawl))
+            .route("/crawls/:id", get(get_crawl))
+            .route("/crawls/:id/seeds", post(add_seeds))
+            .route("/crawls/:id/config", patch(change_config))
+            .route("/crawls/:id/pause", post(pause))
+            .route("/crawls/:id/resume", post(resume))
+            .route("/crawls/:id/stop", post(stop))
+            .route("/pages", get(list_pages))
+            .route("/page", get(get_page))
+            .with_state(self)
+            .merge(metrics::router())
+    }
+
+    /// Runs till the process is stopped
+    pub async fn serve(self, addr: SocketAddr) -> anyhow::Result<()> {
+        let server =
+            axum::Server::try_bind(&addr)?.serve(Arc::new(self).router().into_make_service());
+        info!("Listening on http://{}", server.local_addr());
+        server.await?;
+        Ok(())
+    }
+
+    fn status(&self, id: usize) -> Result<CrawlStatus, ApiError> {
+        let crawls = self.crawls.lock();
+        let crawl = crawls
+            .get(id)
+            .ok_or_else(|| ApiError::not_found(format!("No crawl with id {id}")))?;
+        Ok(crawl.status(id))
+    }
+
+    fn control(&self, id: usize) -> Result<CrawlControl, ApiError> {
+        self.crawls
+            .lock()
+            .get(id)
+            .map(|x| x.control.clone())
+            .ok_or_else(|| ApiError::not_found(format!("No crawl with id {id}")))
+    }
+}
+
+impl Crawl {
+    fn status(&self, id: usize) -> CrawlStatus {
+        let result = self.result.lock();
+        let state = match &*result {
+            None if self.control.is_paused() => "paused",
+            None if self.control.is_offline() => "offline",
+            None => "running",
+            Some(Ok(_)) => "finished",
+            Some(Err(_)) => "failed",
+        };
+        CrawlStatus {
+            id,
+            state,
+            seeds: self.seeds.iter().map(|x| x.to_string()).collect(),
+            stats: self.control.stats(),
+            exit_reason: result
+                .as_ref()
+                .and_then(|x| x.as_ref().ok())
+                .map(|x| x.exit_reason.as_str()),
+            error: result.as_ref().and_then(|x| x.as_ref().err()).cloned(),
+        }
+    }
+}
+
+fn parse_urls(urls: &[String]) -> Result<Vec<Url>, ApiError> {
+    urls.iter()
+        .map(|x| {
+            x.parse::<Url>()
+                .map_err(|e| ApiError::bad_request(format!("Invalid url {x}: {e}")))
+        })
+        .collect()
+}
+
+async fn list_crawls(State(server): State<Arc<Server>>) -> Json<Vec<CrawlStatus>> {
+    let crawls = server.crawls.lock();
+    Json(
+        crawls
+            .iter()
+            .enumerate()
+            .map(|(id, x)| x.status(id))
+            .collect(),
+    )
+}
+
+async fn start_crawl(
+    State(server): State<Arc<Server>>,
+    Body(request): Body<NewCrawl>,
+) -> Result<(StatusCode, Json<CrawlStatus>), ApiError> {
+    let seeds = parse_urls(&request.seeds)?;
+    if seeds.is_empty() && !request.include_db_links {
+        return Err(ApiError::bad_request("Nothing to crawl, no seeds given"));
+    }
+    let mut config = server.config.clone();
+    request.config.apply(&mut config)?;
+
+    let mut orchestrator = Orchestrator::builder(server.db.clone())
+        .seeds(seeds.clone())
+        .config(config)
+        .build()?;
+    let result = Arc::new(Mutex::new(None));
+    let id = {
+        let mut crawls = server.crawls.lock();
+        crawls.push(Crawl {
+            seeds,
+            control: orchestrator.control(),
+            result: result.clone(),
+        });
+        crawls.len() - 1
+    };
+    let include_db_links = request.include_db_links;
+    tokio::spawn(
+        async move {
+            let session = orchestrator.start(include_db_links).await;
+            match &session {
+                Ok(x) => info!("Crawl {}: {}", id, x),
+                Err(e) => error!("Crawl {} failed: {:?}", id, e),
+            }
+            *result.lock() = Some(session.map_err(|e| format!("{e:#}")));
+        }
+        // logs of the crawl can be told apart by id
+        .instrument(tracing::info_span!("api_crawl", id)),
+    );
+    info!("Started crawl {}", id);
+    Ok((StatusCode::CREATED, Json(server.status(id)?)))
+}
+
+async fn get_crawl(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<usize>,
+) -> ApiResult<CrawlStatus> {
+    Ok(Json(server.status(id)?))
+}
+
+async fn add_seeds(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<usize>,
+    Body(request): Body<NewSeeds>,
+) -> ApiResult<CrawlStatus> {
+    let seeds = parse_urls(&request.seeds)?;
+    server.control(id)?.add_seeds(seeds);
+    Ok(Json(server.status(id)?))
+}
+
+async fn change_config(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<usize>,
+    Body(request): Body<ConfigChange>,
+) -> ApiResult<CrawlStatus> {
+    let control = server.control(id)?;
+    request.apply(&mut control.config().lock())?;
+    Ok(Json(server.status(id)?))
+}
+
+async fn pause(State(server): State<Arc<Server>>, Path(id): Path<usize>) -> ApiResult<CrawlStatus> {
+    server.control(id)?.pause();
+    Ok(Json(server.status(id)?))
+}
+
+async fn resume(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<usize>,
+) -> ApiResult<CrawlStatus> {
+    server.control(id)?.resume();
+    Ok(Json(server.status(id)?))
+}
+
+/// Returns right away, the crawl is `finished` once in-flight requests are done
+async fn stop(State(server): State<Arc<Server>>, Path(id): Path<usize>) -> ApiResult<CrawlStatus> {
+    server.control(id)?.stop();
+    Ok(Json(server.status(id)?))
+}
+
+async fn list_pages(
+    State(server): State<Arc<Server>>,
+    Query(query): Query<PagesQuery>,
+) -> ApiResult<Vec<PageSummary>> {
+    let pages = server
+        .db
+        .get_pages(
+            &query.prefix,
+            query.limit.unwrap_or(PAGE_LIMIT),
+            query.offset,
+        )
+        .await?;
+    Ok(Json(
+        pages
+            .into_iter()
+            .map(|(url, time)| PageSummary { url, time })
+            .collect(),
+    ))
+}
+
+async fn get_page(
+    State(server): State<Arc<Server>>,
+    Query(query): Query<PageQuery>,
+) -> ApiResult<PageContent> {
+    let (html, time) = server
+        .db
+        .get_page(&query.url)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("{} was not scraped", query.url)))?;
+    Ok(Json(PageContent {
+        url: query.url,
+        time,
+        html,
+    }))
+}
@@ -0,0 +1,258 @@
+//! A single run of the [`crate::Orchestrator`]: what it did and why it stopped.
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::watch;
+
+use crate::metrics::metrics;
+use crate::seen::SeenSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Nothing left to scrape
+    Completed,
+    /// Stopped with [`StopHandle::stop`], e.g. on Ctrl+C
+    Interrupted,
+    /// One of the [`Limits`] was reached
+    MaxDuration,
+    MaxPages,
+    MaxBytes,
+    MaxErrors,
+    MaxErrorRate,
+}
+
+impl ExitReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExitReason::Completed => "completed",
+            ExitReason::Interrupted => "interrupted",
+            ExitReason::MaxDuration => "max_duration",
+            ExitReason::MaxPages => "max_pages",
+            ExitReason::MaxBytes => "max_bytes",
+            ExitReason::MaxErrors => "max_errors",
+            ExitReason::MaxErrorRate => "max_error_rate",
+        }
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error rate is only checked after this many requests, a single early failure shouldn't stop the crawl
+const ERROR_RATE_MIN_REQUESTS: u64 = 20;
+
+/// When to stop a crawl which still has urls left.
+/// In-flight requests are allowed to finish, so limits can be slightly exceeded.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub max_duration: Option<Duration>,
+    pub max_pages: Option<u64>,
+    /// Size of fetched pages and assets
+    pub max_bytes: Option<u64>,
+    pub max_errors: Option<u64>,
+    /// Fraction (0 to 1) of failed requests
+    pub max_error_rate: Option<f64>,
+}
+
+impl Limits {
+    pub(crate) fn exceeded(&self, elapsed: Duration, counters: &Counters) -> Option<ExitReason> {
+        let pages = counters.pages.load(Ordering::Relaxed);
+        let assets = counters.assets.load(Ordering::Relaxed);
+        let errors = counters.errors.load(Ordering::Relaxed);
+        let bytes = counters.bytes.load(Ordering::Relaxed);
+        let requests = pages + assets + errors;
+
+        let reached = |limit: Option<u64>, value: u64| limit.is_some_and(|x| value >= x);
+        if self.max_duration.is_some_and(|x| elapsed >= x) {
+            Some(ExitReason::MaxDuration)
+        } else if reached(self.max_pages, pages) {
+            Some(ExitReason::MaxPages)
+        } else if reached(self.max_bytes, bytes) {
+            Some(ExitReason::MaxBytes)
+        } else if reached(self.max_errors, errors) {
+            Some(ExitReason::MaxErrors)
+        } else if requests >= ERROR_RATE_MIN_REQUESTS
+            && self
+                .max_error_rate
+                .is_some_and(|x| errors as f64 / requests as f64 > x)
+        {
+            Some(ExitReason::MaxErrorRate)
+        } else {
+            None
+        }
+    }
+}
+
+/// Stored in `sessions` table when a run ends
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub started: SystemTime,
+    pub ended: SystemTime,
+    pub exit_reason: ExitReason,
+    pub pages: u64,
+    pub assets: u64,
+    pub errors: u64,
+    /// Size of fetched pages and assets
+    pub bytes: u64,
+    /// Urls which were queued but not scraped, picked up by `--include-db-links`
+    pub frontier: u64,
+}
+
+impl Session {
+    pub fn duration(&self) -> Duration {
+        self.ended.duration_since(self.started).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Scraped {} pages and {} assets ({} bytes) with {} errors in {:.1?}, {} urls left in frontier ({})",
+            self.pages,
+            self.assets,
+            self.bytes,
+            self.errors,
+            self.duration(),
+            self.frontier,
+            self.exit_reason
+        )
+    }
+}
+
+/// Seconds since unix epoch
+pub(crate) fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+/// Updated by scraping tasks as they finish
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub pages: AtomicU64,
+    pub assets: AtomicU64,
+    pub errors: AtomicU64,
+    pub bytes: AtomicU64,
+    /// Resources waiting in the queue
+    pub queued: AtomicU64,
+    pub in_flight: AtomicU64,
+    pub seen: AtomicU64,
+    pub seen_bytes: AtomicU64,
+}
+
+impl Counters {
+    pub fn page(&self, bytes: usize) {
+        self.pages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        metrics()
+            .bytes
+            .with_label_values(&["page"])
+            .inc_by(bytes as u64);
+    }
+
+    pub fn asset(&self, bytes: usize) {
+        self.assets.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        metrics()
+            .bytes
+            .with_label_values(&["asset"])
+            .inc_by(bytes as u64);
+    }
+
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn enqueue(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        metrics().queued.inc();
+    }
+
+    pub fn dequeue(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        metrics().queued.dec();
+    }
+
+    /// Queue was drained on shutdown
+    pub fn clear_queue(&self) {
+        let previous = self.queued.swap(0, Ordering::Relaxed);
+        metrics().queued.sub(previous as i64);
+    }
+
+    pub fn set_in_flight(&self, in_flight: u64) {
+        let previous = self.in_flight.swap(in_flight, Ordering::Relaxed);
+        metrics().in_flight.add(in_flight as i64 - previous as i64);
+    }
+
+    /// Size of the set after urls were added to it
+    pub fn seen(&self, seen: &dyn SeenSet) {
+        let previous = self.seen.swap(seen.len(), Ordering::Relaxed);
+        metrics().seen.add(seen.len() as i64 - previous as i64);
+        let previous = self.seen_bytes.swap(seen.memory_usage(), Ordering::Relaxed);
+        metrics()
+            .seen_bytes
+            .add(seen.memory_usage() as i64 - previous as i64);
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            pages: self.pages.load(Ordering::Relaxed),
+            assets: self.assets.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            seen: self.seen.load(Ordering::Relaxed),
+            seen_bytes: self.seen_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Progress of a running [`crate::Orchestrator`], see [`crate::CrawlControl::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Stats {
+    pub pages: u64,
+    pub assets: u64,
+    pub errors: u64,
+    /// Size of fetched pages and assets
+    pub bytes: u64,
+    /// Resources waiting for a free request slot
+    pub queued: u64,
+    /// Requests being made, and pages being processed
+    pub in_flight: u64,
+    /// Urls queued at some point, see [`crate::seen`]
+    pub seen: u64,
+    /// Approximate memory taken by the seen set
+    pub seen_bytes: u64,
+}
+
+/// Stops a running [`crate::Orchestrator`], see [`crate::Orchestrator::stop_handle`].
+/// No new requests are made, in-flight ones get the shutdown timeout to finish.
+#[derive(Clone)]
+pub struct StopHandle {
+    tx: Arc<watch::Sender<Option<ExitReason>>>,
+}
+
+impl StopHandle {
+    pub(crate) fn new() -> (Self, watch::Receiver<Option<ExitReason>>) {
+        let (tx, rx) = watch::channel(None);
+        (Self { tx: Arc::new(tx) }, rx)
+    }
+
+    /// Only the first reason is kept
+    pub fn stop(&self, reason: ExitReason) {
+        self.tx.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason);
+            true
+        });
+    }
+}
@@ -0,0 +1,140 @@
+//! SimHash fingerprints of page text, used to find near-duplicate pages
+//! served under different urls (print views, session ids, calendars etc.)
+use std::collections::HashMap;
+
+use select::document::Document;
+use select::node::Node;
+
+/// Number of words in each shingle
+const SHINGLE_SIZE: usize = 3;
+
+/// Visible text of the document, ignoring script/style contents
+pub fn document_text(document: &Document) -> String {
+    let mut rv = String::new();
+    for node in document.nth(0).into_iter() {
+        collect_text(node, &mut rv);
+    }
+    rv
+}
+
+fn collect_text(node: Node, rv: &mut String) {
+    if matches!(
+        node.name(),
+        Some("script" | "style" | "noscript" | "template")
+    ) {
+        return;
+    }
+    if let Some(text) = node.as_text() {
+        rv.push_str(text);
+        rv.push(' ');
+    }
+    for child in node.children() {
+        collect_text(child, rv);
+    }
+}
+
+/// Pages with fewer words are not fingerprinted. Near-empty pages (shells of single page apps,
+/// image galleries) would all be near-duplicates of each other.
+pub const MIN_WORDS: usize = 10;
+
+/// 64 bit SimHash of word shingles in `text`, `None` if it has less than [`MIN_WORDS`] words
+pub fn simhash(text: &str) -> Option<u64> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect();
+    if words.len() < MIN_WORDS {
+        return None;
+    }
+
+    let mut weights = [0i64; 64];
+    for shingle in words.windows(SHINGLE_SIZE) {
+        let hash = fnv1a(shingle.join(" ").as_bytes());
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash & (1 << bit) != 0 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+
+    let fingerprint = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0, |acc, (bit, _)| acc | (1 << bit));
+    Some(fingerprint)
+}
+
+/// Stable across rust versions/platforms unlike `DefaultHasher`,
+/// important as fingerprints are persisted.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Finds fingerprints within `max_distance` bits of each other without comparing with all of them.
+/// Fingerprint is split into `max_distance + 1` bands, two fingerprints within `max_distance`
+/// must have at least one identical band.
+pub struct SimHashIndex<T> {
+    max_distance: u32,
+    bands: Vec<HashMap<u64, Vec<(u64, T)>>>,
+}
+
+impl<T> SimHashIndex<T> {
+    pub fn new(max_distance: u32) -> Self {
+        let max_distance = max_distance.min(63);
+        Self {
+            max_distance,
+            bands: (0..=max_distance).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    fn band_keys(&self, fingerprint: u64) -> impl Iterator<Item = u64> {
+        let count = self.bands.len() as u32;
+        (0..count).map(move |band| {
+            let start = band * 64 / count;
+            let end = (band + 1) * 64 / count;
+            let bits = end - start;
+            let mask = if bits == 64 {
+                u64::MAX
+            } else {
+                (1 << bits) - 1
+            };
+            (fingerprint >> start) & mask
+        })
+    }
+
+    /// First indexed value within `max_distance` of `fingerprint`
+    pub fn find(&self, fingerprint: u64) -> Option<(&T, u32)> {
+        for (band, key) in self.band_keys(fingerprint).enumerate() {
+            for (other, value) in self.bands[band].get(&key).into_iter().flatten() {
+                let d = distance(fingerprint, *other);
+                if d <= self.max_distance {
+                    return Some((value, d));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn insert(&mut self, fingerprint: u64, value: T)
+    where
+        T: Clone,
+    {
+        let keys: Vec<_> = self.band_keys(fingerprint).collect();
+        for (band, key) in keys.into_iter().enumerate() {
+            self.bands[band]
+                .entry(key)
+                .or_default()
+                .push((fingerprint, value.clone()));
+        }
+    }
+}
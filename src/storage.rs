@@ -0,0 +1,306 @@
+//! Where scraped data goes. [`crate::Database`] (sqlite) is the default backend,
+//! others are behind cargo features.
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+use url::Url;
+
+use crate::assets::AssetKind;
+use crate::metadata::PageMetadata;
+use crate::session::Session;
+
+mod batch;
+#[cfg(feature = "fs-storage")]
+mod fs;
+mod jsonl;
+#[cfg(feature = "postgres")]
+mod postgres;
+
+pub use batch::{Batched, Write};
+#[cfg(feature = "fs-storage")]
+pub use fs::FsStorage;
+pub use jsonl::JsonLinesStorage;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStorage;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Urls noticed while scraping, they form the frontier along with results and errors
+    async fn add_to_links(&self, urls: Vec<Url>) -> anyhow::Result<()>;
+
+    async fn add_to_results(&self, url: Url, html: String) -> anyhow::Result<()>;
+
+    async fn add_to_errors(&self, url: Url, msg: String) -> anyhow::Result<()>;
+
+    async fn add_to_metadata(&self, url: Url, metadata: &PageMetadata) -> anyhow::Result<()>;
+
+    async fn add_to_assets(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    ) -> anyhow::Result<()>;
+
+    /// Assets which failed or were rejected. Kept apart from errors and skipped
+    /// as assets are not part of the frontier.
+    async fn add_to_asset_errors(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        msg: String,
+    ) -> anyhow::Result<()>;
+
+    /// (original, normalized) pairs
+    async fn add_to_aliases(&self, aliases: Vec<(Url, Url)>) -> anyhow::Result<()>;
+
+    /// (url, reason) of urls skipped by crawler trap heuristics, processors or fetch checks.
+    /// They are not part of the frontier.
+    async fn add_to_skipped(&self, skipped: Vec<(Url, String)>) -> anyhow::Result<()>;
+
+    async fn add_to_fingerprints(
+        &self,
+        url: Url,
+        simhash: u64,
+        near_duplicate: bool,
+    ) -> anyhow::Result<()>;
+
+    /// DOM of a page after running its scripts, see [`crate::render`]
+    async fn add_to_rendered(&self, url: Url, html: String) -> anyhow::Result<()>;
+
+    /// Page or asset whose body was cut at [`crate::fetch::FetchConfig::max_body_size`]
+    async fn add_to_truncated(&self, url: Url) -> anyhow::Result<()>;
+
+    /// Proxy a page or asset was fetched through, see [`crate::proxy`]
+    async fn add_to_proxies(&self, url: Url, proxy: String) -> anyhow::Result<()>;
+
+    /// Data added by [`crate::pipeline::PageProcessor`]s
+    async fn add_to_page_data(&self, url: Url, data: &Map<String, Value>) -> anyhow::Result<()>;
+
+    /// Written once, when a run of the orchestrator ends
+    async fn add_to_sessions(&self, session: &Session) -> anyhow::Result<()>;
+
+    /// Frontier: links which are neither in results, errors nor skipped, oldest first
+    async fn get_unprocessed_links(&self) -> anyhow::Result<Vec<Url>>;
+
+    /// Many writes at once, used by [`Batched`].
+    /// Backends can override this with multi-row statements.
+    async fn write_batch(&self, batch: Vec<Write>) -> anyhow::Result<()> {
+        for write in batch {
+            write.apply(self).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait till everything written so far is stored
+    async fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: Storage + ?Sized> Storage for Arc<T> {
+    async fn add_to_links(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        (**self).add_to_links(urls).await
+    }
+
+    async fn add_to_results(&self, url: Url, html: String) -> anyhow::Result<()> {
+        (**self).add_to_results(url, html).await
+    }
+
+    async fn add_to_errors(&self, url: Url, msg: String) -> anyhow::Result<()> {
+        (**self).add_to_errors(url, msg).await
+    }
+
+    async fn add_to_metadata(&self, url: Url, metadata: &PageMetadata) -> anyhow::Result<()> {
+        (**self).add_to_metadata(url, metadata).await
+    }
+
+    async fn add_to_assets(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        (**self)
+            .add_to_assets(url, kind, content_type, content)
+            .await
+    }
+
+    async fn add_to_asset_errors(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        msg: String,
+    ) -> anyhow::Result<()> {
+        (**self).add_to_asset_errors(url, kind, msg).await
+    }
+
+    async fn add_to_aliases(&self, aliases: Vec<(Url, Url)>) -> anyhow::Result<()> {
+        (**self).add_to_aliases(aliases).await
+    }
+
+    async fn add_to_skipped(&self, skipped: Vec<(Url, String)>) -> anyhow::Result<()> {
+        (**self).add_to_skipped(skipped).await
+    }
+
+    async fn add_to_fingerprints(
+        &self,
+        url: Url,
+        simhash: u64,
+        near_duplicate: bool,
+    ) -> anyhow::Result<()> {
+        (**self)
+            .add_to_fingerprints(url, simhash, near_duplicate)
+            .await
+    }
+
+    async fn add_to_rendered(&self, url: Url, html: String) -> anyhow::Result<()> {
+        (**self).add_to_rendered(url, html).await
+    }
+
+    async fn add_to_truncated(&self, url: Url) -> anyhow::Result<()> {
+        (**self).add_to_truncated(url).await
+    }
+
+    async fn add_to_proxies(&self, url: Url, proxy: String) -> anyhow::Result<()> {
+        (**self).add_to_proxies(url, proxy).await
+    }
+
+    async fn add_to_page_data(&self, url: Url, data: &Map<String, Value>) -> anyhow::Result<()> {
+        (**self).add_to_page_data(url, data).await
+    }
+
+    async fn add_to_sessions(&self, session: &Session) -> anyhow::Result<()> {
+        (**self).add_to_sessions(session).await
+    }
+
+    async fn get_unprocessed_links(&self) -> anyhow::Result<Vec<Url>> {
+        (**self).get_unprocessed_links().await
+    }
+
+    async fn write_batch(&self, batch: Vec<Write>) -> anyhow::Result<()> {
+        (**self).write_batch(batch).await
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        (**self).flush().await
+    }
+}
+
+/// Writes to both storages, frontier is read from the first one.
+/// E.g. sqlite file plus [`JsonLinesStorage`] on stdout.
+pub struct Tee<A, B>(pub A, pub B);
+
+#[async_trait]
+impl<A: Storage, B: Storage> Storage for Tee<A, B> {
+    async fn add_to_links(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        self.0.add_to_links(urls.clone()).await?;
+        self.1.add_to_links(urls).await
+    }
+
+    async fn add_to_results(&self, url: Url, html: String) -> anyhow::Result<()> {
+        self.0.add_to_results(url.clone(), html.clone()).await?;
+        self.1.add_to_results(url, html).await
+    }
+
+    async fn add_to_errors(&self, url: Url, msg: String) -> anyhow::Result<()> {
+        self.0.add_to_errors(url.clone(), msg.clone()).await?;
+        self.1.add_to_errors(url, msg).await
+    }
+
+    async fn add_to_metadata(&self, url: Url, metadata: &PageMetadata) -> anyhow::Result<()> {
+        self.0.add_to_metadata(url.clone(), metadata).await?;
+        self.1.add_to_metadata(url, metadata).await
+    }
+
+    async fn add_to_assets(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        self.0
+            .add_to_assets(url.clone(), kind, content_type.clone(), content.clone())
+            .await?;
+        self.1.add_to_assets(url, kind, content_type, content).await
+    }
+
+    async fn add_to_asset_errors(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        msg: String,
+    ) -> anyhow::Result<()> {
+        self.0
+            .add_to_asset_errors(url.clone(), kind, msg.clone())
+            .await?;
+        self.1.add_to_asset_errors(url, kind, msg).await
+    }
+
+    async fn add_to_aliases(&self, aliases: Vec<(Url, Url)>) -> anyhow::Result<()> {
+        self.0.add_to_aliases(aliases.clone()).await?;
+        self.1.add_to_aliases(aliases).await
+    }
+
+    async fn add_to_skipped(&self, skipped: Vec<(Url, String)>) -> anyhow::Result<()> {
+        self.0.add_to_skipped(skipped.clone()).await?;
+        self.1.add_to_skipped(skipped).await
+    }
+
+    async fn add_to_fingerprints(
+        &self,
+        url: Url,
+        simhash: u64,
+        near_duplicate: bool,
+    ) -> anyhow::Result<()> {
+        self.0
+            .add_to_fingerprints(url.clone(), simhash, near_duplicate)
+            .await?;
+        self.1
+            .add_to_fingerprints(url, simhash, near_duplicate)
+            .await
+    }
+
+    async fn add_to_rendered(&self, url: Url, html: String) -> anyhow::Result<()> {
+        self.0.add_to_rendered(url.clone(), html.clone()).await?;
+        self.1.add_to_rendered(url, html).await
+    }
+
+    async fn add_to_truncated(&self, url: Url) -> anyhow::Result<()> {
+        self.0.add_to_truncated(url.clone()).await?;
+        self.1.add_to_truncated(url).await
+    }
+
+    async fn add_to_proxies(&self, url: Url, proxy: String) -> anyhow::Result<()> {
+        self.0.add_to_proxies(url.clone(), proxy.clone()).await?;
+        self.1.add_to_proxies(url, proxy).await
+    }
+
+    async fn add_to_page_data(&self, url: Url, data: &Map<String, Value>) -> anyhow::Result<()> {
+        self.0.add_to_page_data(url.clone(), data).await?;
+        self.1.add_to_page_data(url, data).await
+    }
+
+    async fn add_to_sessions(&self, session: &Session) -> anyhow::Result<()> {
+        self.0.add_to_sessions(session).await?;
+        self.1.add_to_sessions(session).await
+    }
+
+    async fn get_unprocessed_links(&self) -> anyhow::Result<Vec<Url>> {
+        self.0.get_unprocessed_links().await
+    }
+
+    async fn write_batch(&self, batch: Vec<Write>) -> anyhow::Result<()> {
+        self.0.write_batch(batch.clone()).await?;
+        self.1.write_batch(batch).await
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        self.0.flush().await?;
+        self.1.flush().await
+    }
+}
@@ -0,0 +1,310 @@
+//! Writes from all scraping tasks go through a bounded channel to a single writer task,
+//! which hands them to the storage in batches. When the storage falls behind,
+//! the channel fills up and scraping tasks wait on it.
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+use tokio::sync::{mpsc, oneshot};
+use url::Url;
+
+use super::Storage;
+use crate::assets::AssetKind;
+use crate::metadata::PageMetadata;
+use crate::prelude::*;
+use crate::session::Session;
+
+/// A single [`Storage`] call, see [`Storage::write_batch`]
+#[derive(Debug, Clone)]
+pub enum Write {
+    Link(Url),
+    Result(Url, String),
+    Error(Url, String),
+    Metadata(Url, PageMetadata),
+    Asset {
+        url: Url,
+        kind: AssetKind,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    },
+    AssetError(Url, AssetKind, String),
+    Alias(Url, Url),
+    Skipped(Url, String),
+    Fingerprint {
+        url: Url,
+        simhash: u64,
+        near_duplicate: bool,
+    },
+    Rendered(Url, String),
+    Truncated(Url),
+    Proxy(Url, String),
+    PageData(Url, Map<String, Value>),
+    Session(Session),
+}
+
+impl Write {
+    /// Write using the single row methods
+    pub async fn apply<S: Storage + ?Sized>(self, storage: &S) -> anyhow::Result<()> {
+        match self {
+            Write::Link(url) => storage.add_to_links(vec![url]).await,
+            Write::Result(url, html) => storage.add_to_results(url, html).await,
+            Write::Error(url, msg) => storage.add_to_errors(url, msg).await,
+            Write::Metadata(url, metadata) => storage.add_to_metadata(url, &metadata).await,
+            Write::Asset {
+                url,
+                kind,
+                content_type,
+                content,
+            } => {
+                storage
+                    .add_to_assets(url, kind, content_type, content)
+                    .await
+            }
+            Write::AssetError(url, kind, msg) => storage.add_to_asset_errors(url, kind, msg).await,
+            Write::Alias(original, url) => storage.add_to_aliases(vec![(original, url)]).await,
+            Write::Skipped(url, reason) => storage.add_to_skipped(vec![(url, reason)]).await,
+            Write::Fingerprint {
+                url,
+                simhash,
+                near_duplicate,
+            } => {
+                storage
+                    .add_to_fingerprints(url, simhash, near_duplicate)
+                    .await
+            }
+            Write::Rendered(url, html) => storage.add_to_rendered(url, html).await,
+            Write::Truncated(url) => storage.add_to_truncated(url).await,
+            Write::Proxy(url, proxy) => storage.add_to_proxies(url, proxy).await,
+            Write::PageData(url, data) => storage.add_to_page_data(url, &data).await,
+            Write::Session(session) => storage.add_to_sessions(&session).await,
+        }
+    }
+}
+
+enum Message {
+    Write(Write),
+    Flush(oneshot::Sender<anyhow::Result<()>>),
+}
+
+/// Wraps a storage with a writer task, must be created inside a tokio runtime.
+///
+/// Writes return as soon as they are queued. A batch the storage rejects is written
+/// again one write at a time, and if any of those fail too, every later write
+/// and [`Storage::flush`] returns the error so the crawl stops instead of losing pages.
+pub struct Batched<S> {
+    inner: Arc<S>,
+    tx: mpsc::Sender<Message>,
+    // set by the writer task once a write could not be stored
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl<S: Storage + 'static> Batched<S> {
+    /// At most `batch_size` writes are handed to the storage at once,
+    /// scraping tasks wait once `queue_size` writes are pending
+    pub fn new(inner: S, batch_size: usize, queue_size: usize) -> Self {
+        let inner = Arc::new(inner);
+        let (tx, rx) = mpsc::channel(queue_size.max(1));
+        let failure = Arc::new(Mutex::new(None));
+        tokio::spawn(write_loop(
+            inner.clone(),
+            rx,
+            batch_size.max(1),
+            failure.clone(),
+        ));
+        Self { inner, tx, failure }
+    }
+
+    async fn send(&self, write: Write) -> anyhow::Result<()> {
+        if let Some(failure) = &*self.failure.lock() {
+            anyhow::bail!("Storage failed earlier, not accepting writes: {failure}");
+        }
+        self.tx
+            .send(Message::Write(write))
+            .await
+            .map_err(|_| anyhow::anyhow!("Storage writer task has stopped"))
+    }
+}
+
+async fn write_loop<S: Storage>(
+    storage: Arc<S>,
+    mut rx: mpsc::Receiver<Message>,
+    batch_size: usize,
+    failure: Arc<Mutex<Option<String>>>,
+) {
+    while let Some(message) = rx.recv().await {
+        let mut batch = vec![];
+        let mut flush = None;
+        let mut next = Some(message);
+        while let Some(message) = next {
+            match message {
+                Message::Write(write) => batch.push(write),
+                Message::Flush(ack) => {
+                    // everything queued before the flush is in this batch
+                    flush = Some(ack);
+                    break;
+                }
+            }
+            next = if batch.len() < batch_size {
+                rx.try_recv().ok()
+            } else {
+                None
+            };
+        }
+
+        if !batch.is_empty() {
+            if let Err(e) = write_batch(&*storage, batch).await {
+                *failure.lock() = Some(format!("{e:#}"));
+            }
+        }
+        if let Some(ack) = flush {
+            let failed = failure.lock().clone();
+            let result = match failed {
+                Some(failure) => Err(anyhow::anyhow!("Storage writes failed: {failure}")),
+                None => storage.flush().await,
+            };
+            let _ = ack.send(result);
+        }
+    }
+}
+
+/// Write `batch` at once, falling back to one write at a time if the storage rejects it,
+/// so a single bad row doesn't lose the rest of the batch
+async fn write_batch<S: Storage>(storage: &S, batch: Vec<Write>) -> anyhow::Result<()> {
+    let len = batch.len();
+    let Err(e) = storage.write_batch(batch.clone()).await else {
+        return Ok(());
+    };
+    warn!(
+        "Failed to write batch of {}, writing one at a time: {:?}",
+        len, e
+    );
+    let mut failed = 0;
+    let mut last = None;
+    for write in batch {
+        if let Err(e) = write.apply(storage).await {
+            error!("Failed to write: {:?}", e);
+            failed += 1;
+            last = Some(e);
+        }
+    }
+    match last {
+        Some(e) => Err(e.context(format!("{failed} of {len} writes failed"))),
+        None => Ok(()),
+    }
+}
+
+#[async_trait]
+impl<S: Storage + 'static> Storage for Batched<S> {
+    async fn add_to_links(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        for url in urls {
+            self.send(Write::Link(url)).await?;
+        }
+        Ok(())
+    }
+
+    async fn add_to_results(&self, url: Url, html: String) -> anyhow::Result<()> {
+        self.send(Write::Result(url, html)).await
+    }
+
+    async fn add_to_errors(&self, url: Url, msg: String) -> anyhow::Result<()> {
+        self.send(Write::Error(url, msg)).await
+    }
+
+    async fn add_to_metadata(&self, url: Url, metadata: &PageMetadata) -> anyhow::Result<()> {
+        self.send(Write::Metadata(url, metadata.clone())).await
+    }
+
+    async fn add_to_assets(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        self.send(Write::Asset {
+            url,
+            kind,
+            content_type,
+            content,
+        })
+        .await
+    }
+
+    async fn add_to_asset_errors(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        msg: String,
+    ) -> anyhow::Result<()> {
+        self.send(Write::AssetError(url, kind, msg)).await
+    }
+
+    async fn add_to_aliases(&self, aliases: Vec<(Url, Url)>) -> anyhow::Result<()> {
+        for (original, url) in aliases {
+            self.send(Write::Alias(original, url)).await?;
+        }
+        Ok(())
+    }
+
+    async fn add_to_skipped(&self, skipped: Vec<(Url, String)>) -> anyhow::Result<()> {
+        for (url, reason) in skipped {
+            self.send(Write::Skipped(url, reason)).await?;
+        }
+        Ok(())
+    }
+
+    async fn add_to_fingerprints(
+        &self,
+        url: Url,
+        simhash: u64,
+        near_duplicate: bool,
+    ) -> anyhow::Result<()> {
+        self.send(Write::Fingerprint {
+            url,
+            simhash,
+            near_duplicate,
+        })
+        .await
+    }
+
+    async fn add_to_rendered(&self, url: Url, html: String) -> anyhow::Result<()> {
+        self.send(Write::Rendered(url, html)).await
+    }
+
+    async fn add_to_truncated(&self, url: Url) -> anyhow::Result<()> {
+        self.send(Write::Truncated(url)).await
+    }
+
+    async fn add_to_proxies(&self, url: Url, proxy: String) -> anyhow::Result<()> {
+        self.send(Write::Proxy(url, proxy)).await
+    }
+
+    async fn add_to_page_data(&self, url: Url, data: &Map<String, Value>) -> anyhow::Result<()> {
+        self.send(Write::PageData(url, data.clone())).await
+    }
+
+    async fn add_to_sessions(&self, session: &Session) -> anyhow::Result<()> {
+        self.send(Write::Session(session.clone())).await
+    }
+
+    async fn write_batch(&self, batch: Vec<Write>) -> anyhow::Result<()> {
+        for write in batch {
+            self.send(write).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        let (ack, done) = oneshot::channel();
+        self.tx
+            .send(Message::Flush(ack))
+            .await
+            .map_err(|_| anyhow::anyhow!("Storage writer task has stopped"))?;
+        done.await?
+    }
+
+    async fn get_unprocessed_links(&self) -> anyhow::Result<Vec<Url>> {
+        self.flush().await?;
+        self.inner.get_unprocessed_links().await
+    }
+}
@@ -0,0 +1,350 @@
+//! One file per page/asset and a json lines index of everything else.
+//! ```text
+//! out_dir/
+//!   index.jsonl      {"kind": "result", "url": "...", "file": "pages/1a2b..html", "time": 1683441453}
+//!   pages/<hash>.html
+//!   rendered/<hash>.html
+//!   assets/<hash>
+//! ```
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use url::Url;
+
+use super::Storage;
+use crate::assets::AssetKind;
+use crate::metadata::PageMetadata;
+use crate::session::{unix_time, Session};
+use crate::simhash::fnv1a;
+
+const INDEX_FILE: &str = "index.jsonl";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum IndexEntry {
+    Link {
+        url: String,
+    },
+    Result {
+        url: String,
+        file: String,
+    },
+    Error {
+        url: String,
+        msg: String,
+    },
+    Metadata {
+        url: String,
+        metadata: PageMetadata,
+    },
+    Asset {
+        url: String,
+        asset_kind: String,
+        content_type: Option<String>,
+        file: String,
+    },
+    AssetError {
+        url: String,
+        asset_kind: String,
+        msg: String,
+    },
+    Alias {
+        original_url: String,
+        url: String,
+    },
+    Skipped {
+        url: String,
+        reason: String,
+    },
+    Fingerprint {
+        url: String,
+        simhash: u64,
+        near_duplicate: bool,
+    },
+    Rendered {
+        url: String,
+        file: String,
+    },
+    Truncated {
+        url: String,
+    },
+    Proxy {
+        url: String,
+        proxy: String,
+    },
+    PageData {
+        url: String,
+        data: Map<String, Value>,
+    },
+    Session {
+        started: u64,
+        ended: u64,
+        exit_reason: String,
+        pages: u64,
+        assets: u64,
+        errors: u64,
+        bytes: u64,
+        frontier: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexLine {
+    #[serde(flatten)]
+    entry: IndexEntry,
+    /// Seconds since unix epoch
+    time: u64,
+}
+
+pub struct FsStorage {
+    root: PathBuf,
+    index: Mutex<tokio::fs::File>,
+}
+
+impl FsStorage {
+    /// Use (or create) directory `root`, existing index is appended to
+    pub async fn open(root: &Path) -> anyhow::Result<Self> {
+        for dir in ["pages", "rendered", "assets"] {
+            tokio::fs::create_dir_all(root.join(dir))
+                .await
+                .context(format!("Can't create directory in {root:?}"))?;
+        }
+        let index = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(root.join(INDEX_FILE))
+            .await
+            .context("Can't open index file")?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            index: Mutex::new(index),
+        })
+    }
+
+    async fn append(&self, entries: Vec<IndexEntry>) -> anyhow::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut buf = vec![];
+        for entry in entries {
+            serde_json::to_writer(&mut buf, &IndexLine { entry, time })?;
+            buf.push(b'\n');
+        }
+        let mut index = self.index.lock().await;
+        index.write_all(&buf).await?;
+        index.flush().await.context("Failed to write index file")?;
+        Ok(())
+    }
+
+    /// Same url always maps to same file, so re-scraping replaces the old content
+    async fn write_file(
+        &self,
+        dir: &str,
+        url: &Url,
+        extension: &str,
+        content: &[u8],
+    ) -> anyhow::Result<String> {
+        let file = format!("{dir}/{:016x}{extension}", fnv1a(url.as_str().as_bytes()));
+        tokio::fs::write(self.root.join(&file), content)
+            .await
+            .context(format!("Failed to write file for uri: {url}"))?;
+        Ok(file)
+    }
+}
+
+#[async_trait]
+impl Storage for FsStorage {
+    async fn add_to_links(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        self.append(
+            urls.into_iter()
+                .map(|x| IndexEntry::Link { url: x.to_string() })
+                .collect(),
+        )
+        .await
+    }
+
+    async fn add_to_results(&self, url: Url, html: String) -> anyhow::Result<()> {
+        let file = self
+            .write_file("pages", &url, ".html", html.as_bytes())
+            .await?;
+        self.append(vec![IndexEntry::Result {
+            url: url.to_string(),
+            file,
+        }])
+        .await
+    }
+
+    async fn add_to_errors(&self, url: Url, msg: String) -> anyhow::Result<()> {
+        self.append(vec![IndexEntry::Error {
+            url: url.to_string(),
+            msg,
+        }])
+        .await
+    }
+
+    async fn add_to_metadata(&self, url: Url, metadata: &PageMetadata) -> anyhow::Result<()> {
+        self.append(vec![IndexEntry::Metadata {
+            url: url.to_string(),
+            metadata: metadata.clone(),
+        }])
+        .await
+    }
+
+    async fn add_to_assets(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let file = self.write_file("assets", &url, "", &content).await?;
+        self.append(vec![IndexEntry::Asset {
+            url: url.to_string(),
+            asset_kind: kind.to_string(),
+            content_type,
+            file,
+        }])
+        .await
+    }
+
+    async fn add_to_asset_errors(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        msg: String,
+    ) -> anyhow::Result<()> {
+        self.append(vec![IndexEntry::AssetError {
+            url: url.to_string(),
+            asset_kind: kind.to_string(),
+            msg,
+        }])
+        .await
+    }
+
+    async fn add_to_aliases(&self, aliases: Vec<(Url, Url)>) -> anyhow::Result<()> {
+        self.append(
+            aliases
+                .into_iter()
+                .map(|(original, url)| IndexEntry::Alias {
+                    original_url: original.to_string(),
+                    url: url.to_string(),
+                })
+                .collect(),
+        )
+        .await
+    }
+
+    async fn add_to_skipped(&self, skipped: Vec<(Url, String)>) -> anyhow::Result<()> {
+        self.append(
+            skipped
+                .into_iter()
+                .map(|(url, reason)| IndexEntry::Skipped {
+                    url: url.to_string(),
+                    reason,
+                })
+                .collect(),
+        )
+        .await
+    }
+
+    async fn add_to_fingerprints(
+        &self,
+        url: Url,
+        simhash: u64,
+        near_duplicate: bool,
+    ) -> anyhow::Result<()> {
+        self.append(vec![IndexEntry::Fingerprint {
+            url: url.to_string(),
+            simhash,
+            near_duplicate,
+        }])
+        .await
+    }
+
+    async fn add_to_rendered(&self, url: Url, html: String) -> anyhow::Result<()> {
+        let file = self
+            .write_file("rendered", &url, ".html", html.as_bytes())
+            .await?;
+        self.append(vec![IndexEntry::Rendered {
+            url: url.to_string(),
+            file,
+        }])
+        .await
+    }
+
+    async fn add_to_truncated(&self, url: Url) -> anyhow::Result<()> {
+        self.append(vec![IndexEntry::Truncated {
+            url: url.to_string(),
+        }])
+        .await
+    }
+
+    async fn add_to_proxies(&self, url: Url, proxy: String) -> anyhow::Result<()> {
+        self.append(vec![IndexEntry::Proxy {
+            url: url.to_string(),
+            proxy,
+        }])
+        .await
+    }
+
+    async fn add_to_page_data(&self, url: Url, data: &Map<String, Value>) -> anyhow::Result<()> {
+        self.append(vec![IndexEntry::PageData {
+            url: url.to_string(),
+            data: data.clone(),
+        }])
+        .await
+    }
+
+    async fn add_to_sessions(&self, session: &Session) -> anyhow::Result<()> {
+        self.append(vec![IndexEntry::Session {
+            started: unix_time(session.started),
+            ended: unix_time(session.ended),
+            exit_reason: session.exit_reason.to_string(),
+            pages: session.pages,
+            assets: session.assets,
+            errors: session.errors,
+            bytes: session.bytes,
+            frontier: session.frontier,
+        }])
+        .await
+    }
+
+    async fn get_unprocessed_links(&self) -> anyhow::Result<Vec<Url>> {
+        let index = tokio::fs::read_to_string(self.root.join(INDEX_FILE))
+            .await
+            .context("Failed to read index file")?;
+        let mut links = vec![];
+        let mut processed = HashSet::new();
+        for line in index.lines().filter(|x| !x.trim().is_empty()) {
+            let line: IndexLine =
+                serde_json::from_str(line).context("Invalid line in index file")?;
+            match line.entry {
+                IndexEntry::Link { url } => links.push(url),
+                IndexEntry::Result { url, .. }
+                | IndexEntry::Error { url, .. }
+                | IndexEntry::Skipped { url, .. } => {
+                    processed.insert(url);
+                }
+                _ => {}
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut rv = vec![];
+        for link in links {
+            if processed.contains(&link) || !seen.insert(link.clone()) {
+                continue;
+            }
+            rv.push(link.parse::<Url>().context("Invalid Url in index file")?);
+        }
+        Ok(rv)
+    }
+}
@@ -0,0 +1,166 @@
+//! Results and errors as json lines, for piping a crawl into `jq` and friends.
+//! ```text
+//! {"kind": "result", "url": "...", "content": "<html>...", "time": 1683441453}
+//! {"kind": "error", "url": "...", "msg": "...", "time": 1683441453}
+//! {"kind": "rendered", "url": "...", "content": "<html>...", "time": 1683441453}
+//! ```
+//! Nothing else is written, so a crawl streamed this way can't be continued later.
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use url::Url;
+
+use super::Storage;
+use crate::assets::AssetKind;
+use crate::metadata::PageMetadata;
+use crate::session::{unix_time, Session};
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Line<'a> {
+    Result {
+        url: &'a str,
+        content: &'a str,
+        time: u64,
+    },
+    Error {
+        url: &'a str,
+        msg: &'a str,
+        time: u64,
+    },
+    Rendered {
+        url: &'a str,
+        content: &'a str,
+        time: u64,
+    },
+}
+
+pub struct JsonLinesStorage<W> {
+    writer: Mutex<W>,
+}
+
+impl JsonLinesStorage<tokio::io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(tokio::io::stdout())
+    }
+}
+
+impl<W: AsyncWrite + Send + Unpin> JsonLinesStorage<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    async fn write(&self, line: Line<'_>) -> anyhow::Result<()> {
+        let mut buf = serde_json::to_vec(&line)?;
+        buf.push(b'\n');
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&buf).await?;
+        // flush every line, consumers want results as they come
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    unix_time(SystemTime::now())
+}
+
+#[async_trait]
+impl<W: AsyncWrite + Send + Unpin> Storage for JsonLinesStorage<W> {
+    async fn add_to_links(&self, _urls: Vec<Url>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_results(&self, url: Url, html: String) -> anyhow::Result<()> {
+        self.write(Line::Result {
+            url: url.as_str(),
+            content: &html,
+            time: now(),
+        })
+        .await
+    }
+
+    async fn add_to_errors(&self, url: Url, msg: String) -> anyhow::Result<()> {
+        self.write(Line::Error {
+            url: url.as_str(),
+            msg: &msg,
+            time: now(),
+        })
+        .await
+    }
+
+    async fn add_to_metadata(&self, _url: Url, _metadata: &PageMetadata) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_assets(
+        &self,
+        _url: Url,
+        _kind: AssetKind,
+        _content_type: Option<String>,
+        _content: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_asset_errors(
+        &self,
+        _url: Url,
+        _kind: AssetKind,
+        _msg: String,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_aliases(&self, _aliases: Vec<(Url, Url)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_skipped(&self, _skipped: Vec<(Url, String)>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_fingerprints(
+        &self,
+        _url: Url,
+        _simhash: u64,
+        _near_duplicate: bool,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_rendered(&self, url: Url, html: String) -> anyhow::Result<()> {
+        self.write(Line::Rendered {
+            url: url.as_str(),
+            content: &html,
+            time: now(),
+        })
+        .await
+    }
+
+    async fn add_to_truncated(&self, _url: Url) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_proxies(&self, _url: Url, _proxy: String) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_page_data(&self, _url: Url, _data: &Map<String, Value>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn add_to_sessions(&self, _session: &Session) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get_unprocessed_links(&self) -> anyhow::Result<Vec<Url>> {
+        Ok(vec![])
+    }
+}
@@ -0,0 +1,115 @@
+//! Heuristics to avoid crawler traps: infinite calendars, faceted search,
+//! ever growing paths (`/a/b/a/b/a/b`) etc.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use url::Url;
+
+#[derive(Debug, Clone)]
+pub struct TrapConfig {
+    /// Skip urls longer than this
+    pub max_url_length: Option<usize>,
+    /// Skip urls where a single path segment occurs more than this many times
+    pub max_repeated_segments: Option<usize>,
+    /// Skip urls once a path has been seen with this many distinct query strings
+    pub max_query_variants: Option<usize>,
+    /// Skip urls once this many pages have been queued for their host
+    pub max_pages_per_host: Option<u64>,
+}
+
+impl Default for TrapConfig {
+    fn default() -> Self {
+        Self {
+            max_url_length: Some(2048),
+            max_repeated_segments: None,
+            max_query_variants: None,
+            max_pages_per_host: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrapReason {
+    UrlTooLong(usize),
+    RepeatedSegment(String, usize),
+    TooManyQueryVariants(usize),
+    HostBudgetExhausted(u64),
+}
+
+impl fmt::Display for TrapReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrapReason::UrlTooLong(len) => write!(f, "url length {len} exceeds limit"),
+            TrapReason::RepeatedSegment(segment, count) => {
+                write!(f, "path segment {segment:?} repeated {count} times")
+            }
+            TrapReason::TooManyQueryVariants(limit) => {
+                write!(f, "path already seen with {limit} distinct query strings")
+            }
+            TrapReason::HostBudgetExhausted(limit) => {
+                write!(f, "host already has {limit} pages")
+            }
+        }
+    }
+}
+
+/// Keeps the state required by `TrapConfig` checks
+#[derive(Default)]
+pub struct TrapDetector {
+    // origin + path => hashes of query strings seen
+    query_variants: HashMap<String, HashSet<u64>>,
+    pages_per_host: HashMap<String, u64>,
+}
+
+impl TrapDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check `url` against the limits, accepted urls are counted towards the limits
+    pub fn check(&mut self, config: &TrapConfig, url: &Url) -> Result<(), TrapReason> {
+        if let Some(limit) = config.max_url_length {
+            let len = url.as_str().len();
+            if len > limit {
+                return Err(TrapReason::UrlTooLong(len));
+            }
+        }
+
+        if let Some(limit) = config.max_repeated_segments {
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for segment in url.path_segments().into_iter().flatten() {
+                if segment.is_empty() {
+                    continue;
+                }
+                let count = counts.entry(segment).or_default();
+                *count += 1;
+                if *count > limit {
+                    return Err(TrapReason::RepeatedSegment(segment.to_string(), *count));
+                }
+            }
+        }
+
+        let host = url.host_str().unwrap_or_default().to_string();
+        if let Some(limit) = config.max_pages_per_host {
+            if self.pages_per_host.get(&host).copied().unwrap_or(0) >= limit {
+                return Err(TrapReason::HostBudgetExhausted(limit));
+            }
+        }
+
+        if let (Some(limit), Some(query)) = (config.max_query_variants, url.query()) {
+            let key = format!("{}{}", url.origin().ascii_serialization(), url.path());
+            let variants = self.query_variants.entry(key).or_default();
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            query.hash(&mut hasher);
+            let hash = hasher.finish();
+            if !variants.contains(&hash) && variants.len() >= limit {
+                return Err(TrapReason::TooManyQueryVariants(limit));
+            }
+            variants.insert(hash);
+        }
+
+        *self.pages_per_host.entry(host).or_default() += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Asset references found in html, css and js, and how assets are crawled next to pages
+
+mod common;
+
+use axum::{http::StatusCode, response::Html, routing::get, Router};
+use common::{serve, TempDir};
+use select::document::Document;
+use url::Url;
+use waper::assets::{css_references, html_references, js_string_urls, AssetConfig, AssetKind};
+use waper::{Database, Orchestrator, RuntimeConfig, Storage};
+
+fn base() -> Url {
+    "https://example.com/css/site.css".parse().unwrap()
+}
+
+fn strings(urls: impl IntoIterator<Item = Url>) -> Vec<String> {
+    urls.into_iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn css_urls() {
+    let css = r#"
+        body { background: url(bg.png) }
+        .a { background: url( "/img/a.png" ) }
+        .b { background: url('../b.png#frag') }
+        .c { background: url(data:image/png;base64,AAAA) }
+        .d { background: url("https://cdn.example.org/d.png"), url(ftp://example.com/e.png) }
+    "#;
+    let references = css_references(&base(), css);
+    assert!(references.iter().all(|(kind, _)| *kind == AssetKind::Image));
+    assert_eq!(
+        strings(references.into_iter().map(|x| x.1)),
+        [
+            "https://example.com/css/bg.png",
+            "https://example.com/img/a.png",
+            "https://example.com/b.png",
+            "https://cdn.example.org/d.png",
+        ]
+    );
+}
+
+#[test]
+fn css_fonts() {
+    let css = r#"
+        @font-face {
+            font-family: "A";
+            src: url(/fonts/a) format("woff2"), url("a.TTF") format("truetype");
+        }
+        body { background: url(bg.png) }
+        .icon { src: url(icons.woff?v=2) }
+    "#;
+    assert_eq!(
+        css_references(&base(), css)
+            .into_iter()
+            .map(|(kind, url)| (kind, url.to_string()))
+            .collect::<Vec<_>>(),
+        [
+            // in `@font-face`, with or without an extension
+            (AssetKind::Font, "https://example.com/fonts/a".to_string()),
+            (AssetKind::Font, "https://example.com/css/a.TTF".to_string()),
+            (
+                AssetKind::Image,
+                "https://example.com/css/bg.png".to_string()
+            ),
+            // or by extension anywhere
+            (
+                AssetKind::Font,
+                "https://example.com/css/icons.woff?v=2".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn css_imports() {
+    let css = r#"
+        @import "reset.css";
+        @import url("/theme.css") screen;
+        @import url(print.css);
+        body { background: url(bg.png) }
+    "#;
+    // imports are stylesheets, not also images
+    assert_eq!(
+        css_references(&base(), css)
+            .into_iter()
+            .map(|(kind, url)| (kind, url.to_string()))
+            .collect::<Vec<_>>(),
+        [
+            (
+                AssetKind::Stylesheet,
+                "https://example.com/css/reset.css".to_string()
+            ),
+            (
+                AssetKind::Stylesheet,
+                "https://example.com/theme.css".to_string()
+            ),
+            (
+                AssetKind::Stylesheet,
+                "https://example.com/css/print.css".to_string()
+            ),
+            (
+                AssetKind::Image,
+                "https://example.com/css/bg.png".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn js_literals() {
+    let base: Url = "https://example.com/js/app.js".parse().unwrap();
+    let js = r#"
+        fetch("/api/items?page=1");
+        const cdn = 'https://cdn.example.org/lib.js';
+        const tpl = `/docs/intro`;
+        const relative = "docs/intro", root = "/", protocol = "//cdn.example.org/x.js";
+        const html = "<a href='/x'>", text = "not a url", spaced = "/a b";
+        const other = "mailto:a@example.com";
+    "#;
+    assert_eq!(
+        strings(js_string_urls(&base, js)),
+        [
+            "https://example.com/api/items?page=1",
+            "https://cdn.example.org/lib.js",
+            "https://example.com/docs/intro",
+            "https://example.com/x",
+        ]
+    );
+}
+
+#[test]
+fn html_assets() {
+    let base: Url = "https://example.com/docs/".parse().unwrap();
+    let document = Document::from(
+        r#"<script src="app.js"></script> <script>inline()</script>
+        <link rel="stylesheet" href="/site.css"> <link rel="icon" href="/favicon.ico">
+        <img src="logo.png"> <style>body { background: url(/bg.png) }</style>
+        <div style="background: url('tile.png')"></div>"#,
+    );
+    assert_eq!(
+        html_references(&base, &document)
+            .into_iter()
+            .map(|(kind, url)| (kind, url.to_string()))
+            .collect::<Vec<_>>(),
+        [
+            (
+                AssetKind::Script,
+                "https://example.com/docs/app.js".to_string()
+            ),
+            (
+                AssetKind::Stylesheet,
+                "https://example.com/site.css".to_string()
+            ),
+            (
+                AssetKind::Image,
+                "https://example.com/docs/logo.png".to_string()
+            ),
+            (AssetKind::Image, "https://example.com/bg.png".to_string()),
+            (
+                AssetKind::Image,
+                "https://example.com/docs/tile.png".to_string()
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn assets_are_kept_apart_from_pages() -> anyhow::Result<()> {
+    // `/both` is an image first, then a link
+    let addr = serve(
+        Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    Html(r#"<img src="/both"> <img src="/missing.png"> <a href="/both">both</a>"#)
+                }),
+            )
+            .route("/both", get(|| async { Html("<p>both</p>") }))
+            .route("/missing.png", get(|| async { StatusCode::NOT_FOUND })),
+    );
+    let dir = TempDir::new("assets-apart");
+    let db_path = dir.join("out.sqlite");
+    let db = Database::connect(&db_path).await?;
+    let mut config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+    config.assets = AssetConfig {
+        kinds: vec![AssetKind::Image],
+        ..Default::default()
+    };
+
+    Orchestrator::builder(db.clone())
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config)
+        .build()?
+        .start(false)
+        .await?;
+
+    let mut pages = db.get_result_urls().await?;
+    pages.sort();
+    assert_eq!(
+        pages,
+        [format!("http://{addr}/"), format!("http://{addr}/both")]
+    );
+    assert_eq!(db.get_asset_urls().await?, [format!("http://{addr}/both")]);
+
+    let conn = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_path.display())).await?;
+    let errors: Vec<(String,)> = sqlx::query_as("SELECT url FROM errors")
+        .fetch_all(&conn)
+        .await?;
+    assert!(errors.is_empty(), "{errors:?}");
+    let asset_errors: Vec<(String, String)> = sqlx::query_as("SELECT url, kind FROM asset_errors")
+        .fetch_all(&conn)
+        .await?;
+    assert_eq!(
+        asset_errors,
+        [(format!("http://{addr}/missing.png"), "image".to_string())]
+    );
+    assert!(db.get_unprocessed_links().await?.is_empty());
+    Ok(())
+}
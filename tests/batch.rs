@@ -0,0 +1,186 @@
+//! `Batched` writes against a storage which rejects batches or single writes
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{response::Html, routing::get, Router};
+use common::{serve, TempDir};
+use serde_json::{Map, Value};
+use url::Url;
+use waper::assets::AssetKind;
+use waper::metadata::PageMetadata;
+use waper::session::Session;
+use waper::storage::{Batched, Write};
+use waper::{Database, Orchestrator, RuntimeConfig, Storage};
+
+/// Rejects every `write_batch`, and results for urls containing `poison`
+struct Flaky {
+    db: Database,
+    poison: Option<&'static str>,
+    batches: AtomicUsize,
+}
+
+impl Flaky {
+    async fn new(dir: &TempDir, poison: Option<&'static str>) -> anyhow::Result<Self> {
+        Ok(Self {
+            db: Database::connect(&dir.join("out.sqlite")).await?,
+            poison,
+            batches: AtomicUsize::new(0),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for Flaky {
+    async fn add_to_links(&self, urls: Vec<Url>) -> anyhow::Result<()> {
+        self.db.add_to_links(urls).await
+    }
+
+    async fn add_to_results(&self, url: Url, html: String) -> anyhow::Result<()> {
+        if self.poison.map_or(false, |x| url.as_str().contains(x)) {
+            anyhow::bail!("poisoned {url}");
+        }
+        self.db.add_to_results(url, html).await
+    }
+
+    async fn add_to_errors(&self, url: Url, msg: String) -> anyhow::Result<()> {
+        self.db.add_to_errors(url, msg).await
+    }
+
+    async fn add_to_metadata(&self, url: Url, metadata: &PageMetadata) -> anyhow::Result<()> {
+        self.db.add_to_metadata(url, metadata).await
+    }
+
+    async fn add_to_assets(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        content_type: Option<String>,
+        content: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        self.db
+            .add_to_assets(url, kind, content_type, content)
+            .await
+    }
+
+    async fn add_to_asset_errors(
+        &self,
+        url: Url,
+        kind: AssetKind,
+        msg: String,
+    ) -> anyhow::Result<()> {
+        self.db.add_to_asset_errors(url, kind, msg).await
+    }
+
+    async fn add_to_aliases(&self, aliases: Vec<(Url, Url)>) -> anyhow::Result<()> {
+        self.db.add_to_aliases(aliases).await
+    }
+
+    async fn add_to_skipped(&self, skipped: Vec<(Url, String)>) -> anyhow::Result<()> {
+        self.db.add_to_skipped(skipped).await
+    }
+
+    async fn add_to_fingerprints(
+        &self,
+        url: Url,
+        simhash: u64,
+        near_duplicate: bool,
+    ) -> anyhow::Result<()> {
+        self.db
+            .add_to_fingerprints(url, simhash, near_duplicate)
+            .await
+    }
+
+    async fn add_to_rendered(&self, url: Url, html: String) -> anyhow::Result<()> {
+        self.db.add_to_rendered(url, html).await
+    }
+
+    async fn add_to_truncated(&self, url: Url) -> anyhow::Result<()> {
+        self.db.add_to_truncated(url).await
+    }
+
+    async fn add_to_proxies(&self, url: Url, proxy: String) -> anyhow::Result<()> {
+        self.db.add_to_proxies(url, proxy).await
+    }
+
+    async fn add_to_page_data(&self, url: Url, data: &Map<String, Value>) -> anyhow::Result<()> {
+        self.db.add_to_page_data(url, data).await
+    }
+
+    async fn add_to_sessions(&self, session: &Session) -> anyhow::Result<()> {
+        self.db.add_to_sessions(session).await
+    }
+
+    async fn get_unprocessed_links(&self) -> anyhow::Result<Vec<Url>> {
+        self.db.get_unprocessed_links().await
+    }
+
+    async fn write_batch(&self, _batch: Vec<Write>) -> anyhow::Result<()> {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        anyhow::bail!("batch rejected")
+    }
+}
+
+fn url(path: &str) -> Url {
+    format!("https://example.com/{path}").parse().unwrap()
+}
+
+#[tokio::test]
+async fn rejected_batch_is_written_one_at_a_time() -> anyhow::Result<()> {
+    let dir = TempDir::new("batch-rejected");
+    let flaky = Arc::new(Flaky::new(&dir, None).await?);
+    let storage = Batched::new(flaky.clone(), 10, 10);
+    for path in ["a", "b", "c"] {
+        storage.add_to_results(url(path), "<p></p>".into()).await?;
+    }
+    storage.flush().await?;
+
+    assert!(flaky.batches.load(Ordering::Relaxed) > 0);
+    let mut results = flaky.db.get_result_urls().await?;
+    results.sort();
+    assert_eq!(results, [url("a"), url("b"), url("c")].map(String::from));
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_write_fails_later_writes() -> anyhow::Result<()> {
+    let dir = TempDir::new("batch-failed");
+    let flaky = Arc::new(Flaky::new(&dir, Some("bad")).await?);
+    let storage = Batched::new(flaky.clone(), 10, 10);
+    storage.add_to_results(url("a"), "<p></p>".into()).await?;
+    storage.add_to_results(url("bad"), "<p></p>".into()).await?;
+    storage.add_to_results(url("b"), "<p></p>".into()).await?;
+
+    let error = storage.flush().await.unwrap_err();
+    assert!(format!("{error:#}").contains("poisoned"), "{error:#}");
+    // the rest of the batch is still stored
+    let mut results = flaky.db.get_result_urls().await?;
+    results.sort();
+    assert_eq!(results, [url("a"), url("b")].map(String::from));
+
+    assert!(storage.add_to_links(vec![url("c")]).await.is_err());
+    assert!(storage.flush().await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn crawl_fails_when_storage_does() -> anyhow::Result<()> {
+    let addr = serve(
+        Router::new()
+            .route("/", get(|| async { Html(r#"<a href="/bad">bad</a>"#) }))
+            .route("/bad", get(|| async { Html("<p>bad</p>") })),
+    );
+    let dir = TempDir::new("batch-crawl");
+    let flaky = Arc::new(Flaky::new(&dir, Some("bad")).await?);
+    let result = Orchestrator::builder(Arc::new(Batched::new(flaky, 10, 10)))
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?)
+        .build()?
+        .start(false)
+        .await;
+    assert!(result.is_err());
+    Ok(())
+}
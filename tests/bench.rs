@@ -0,0 +1,68 @@
+//! Crawl throughput against an in-process fake server, with and without batched writes.
+//! cargo test --release --test bench -- --ignored --nocapture | tee bench_output.txt
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::{extract::Path, response::Html};
+use common::TempDir;
+use waper::storage::Batched;
+use waper::{Database, Orchestrator, RateLimit, RuntimeConfig, Storage};
+
+const PAGES: usize = 3000;
+const LINKS_PER_PAGE: usize = 20;
+
+/// Page `n` links to the next pages of a tree, so the crawl fans out quickly
+async fn page(path: Option<Path<usize>>) -> Html<String> {
+    let n = path.map(|x| x.0).unwrap_or_default();
+    let mut html = format!("<h1>Page {n}</h1>");
+    for link in (n * LINKS_PER_PAGE + 1..=(n + 1) * LINKS_PER_PAGE).filter(|x| *x < PAGES) {
+        html += &format!(r#"<p>Some text about {link}: <a href="/{link}">link {link}</a></p>"#);
+    }
+    Html(html)
+}
+
+async fn crawl(name: &str, storage: impl Storage + 'static) -> anyhow::Result<()> {
+    let addr = common::site(page);
+    let mut config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+    config.rate_limit = RateLimit::new(50);
+    let start = Instant::now();
+    let session = Orchestrator::builder(storage)
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config)
+        .build()?
+        .start(false)
+        .await?;
+    let elapsed = start.elapsed();
+    println!(
+        "{name}: {} pages in {:.2?} ({:.0} pages/s)",
+        session.pages,
+        elapsed,
+        session.pages as f64 / elapsed.as_secs_f64()
+    );
+    assert_eq!(session.pages as usize, PAGES);
+    Ok(())
+}
+
+#[ignore]
+#[tokio::test(flavor = "multi_thread")]
+async fn bench_storage_writes() -> anyhow::Result<()> {
+    let dir = TempDir::new("bench");
+    crawl(
+        "direct",
+        Database::connect(&dir.join("direct.sqlite")).await?,
+    )
+    .await?;
+
+    for batch_size in [50, 500] {
+        let db = Database::connect(&dir.join(&format!("batched-{batch_size}.sqlite"))).await?;
+        crawl(
+            &format!("batched ({batch_size})"),
+            Arc::new(Batched::new(db, batch_size, 5000)),
+        )
+        .await?;
+    }
+    Ok(())
+}
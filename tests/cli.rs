@@ -0,0 +1,28 @@
+//! Argument combinations the binary refuses before crawling
+
+use std::process::Command;
+
+fn waper(args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_waper"))
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn stdout_output_with_other_storage() {
+    let seed = ["-s", "http://127.0.0.1:1/", "-o", "-"];
+    for extra in [
+        &["--storage", "fs"][..],
+        &["--storage", "postgres", "--postgres-url", "postgres://x"],
+        &["--postgres-url", "postgres://x"],
+    ] {
+        let (success, stderr) = waper(&[&seed[..], extra].concat());
+        assert!(!success, "{extra:?}");
+        assert!(stderr.contains("--stream jsonl"), "{extra:?}: {stderr}");
+    }
+}
@@ -0,0 +1,176 @@
+//! Crawls whose requests are made by workers leasing urls from a coordinator, all in-process
+
+mod common;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::{extract::Path, http::header, response::Html};
+use common::TempDir;
+use serde_json::{json, Value};
+use waper::cluster::{Coordinator, Worker, WorkerConfig};
+use waper::{Database, ExitReason, Orchestrator, RuntimeConfig};
+
+async fn page(path: Option<Path<String>>) -> axum::response::Response {
+    use axum::response::IntoResponse;
+    match path.map(|x| x.0).unwrap_or_default().as_str() {
+        "" => Html(
+            r#"<a href="/a">a</a> <a href="/b">b</a> <a href="/c">c</a>
+            <a href="/file.pdf">pdf</a> <a href="http://127.0.0.1:1/down">down</a>"#,
+        )
+        .into_response(),
+        "a" => Html(r#"<a href="/d">d</a> <a href="/">home</a>"#).into_response(),
+        "file.pdf" => ([(header::CONTENT_TYPE, "application/pdf")], "%PDF").into_response(),
+        _ => Html("").into_response(),
+    }
+}
+
+fn worker(coordinator: SocketAddr, name: &str) -> Worker {
+    let mut config = WorkerConfig::new(format!("http://{coordinator}").parse().unwrap());
+    config.name = name.to_string();
+    config.max_parallel_requests = 2;
+    Worker::new(config).unwrap()
+}
+
+async fn crawl(
+    db: Database,
+    site: SocketAddr,
+    coordinator: Coordinator,
+) -> anyhow::Result<waper::Session> {
+    // also lets the crawl follow the link to the closed port
+    let config = RuntimeConfig::default().with_whitelist([r"http://127\.0\.0\.1:.*"])?;
+    let mut orchestrator = Orchestrator::builder(db)
+        .seeds([format!("http://{site}/").parse()?])
+        .config(config)
+        .fetcher(coordinator)
+        .build()?;
+    orchestrator.start(false).await
+}
+
+#[tokio::test]
+async fn crawl_with_workers() -> anyhow::Result<()> {
+    let site = common::site(page);
+    let dir = TempDir::new("cluster");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let coordinator = Coordinator::new(Duration::from_secs(30));
+    let addr = coordinator.spawn_server(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+
+    let workers: Vec<_> = ["one", "two"]
+        .into_iter()
+        .map(|name| {
+            let worker = worker(addr, name);
+            tokio::spawn(async move { worker.run().await })
+        })
+        .collect();
+    let session = crawl(db.clone(), site, coordinator.clone()).await?;
+    coordinator.finish().await;
+
+    let mut fetched = 0;
+    for worker in workers {
+        fetched += worker.await??;
+    }
+    // 5 pages, the pdf and the closed port
+    assert_eq!(session.exit_reason, ExitReason::Completed);
+    assert_eq!((session.pages, session.errors), (5, 1));
+    assert_eq!(fetched, 7);
+    let metrics = waper::metrics::metrics().encode();
+    assert!(
+        metrics.contains(r#"waper_errors_total{class="connect"}"#),
+        "{metrics}"
+    );
+
+    let mut urls: Vec<_> = db
+        .get_pages(&format!("http://{site}/"), 100, 0)
+        .await?
+        .into_iter()
+        .map(|(url, _)| url.replace(&format!("http://{site}"), ""))
+        .collect();
+    urls.sort();
+    assert_eq!(urls, vec!["/", "/a", "/b", "/c", "/d"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn expired_leases_go_to_other_workers() -> anyhow::Result<()> {
+    let site = common::site(page);
+    let dir = TempDir::new("cluster-lease");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let coordinator = Coordinator::new(Duration::from_millis(200));
+    let addr = coordinator.spawn_server(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+    let crawl = tokio::spawn(crawl(db.clone(), site, coordinator.clone()));
+
+    // leases the seed and never reports it
+    let client = reqwest::Client::new();
+    let call = |path: &'static str, body: Value| {
+        let request = client
+            .post(format!("http://{addr}/{path}"))
+            .header("content-type", "application/json")
+            .body(body.to_string());
+        async move {
+            let response = request.send().await.unwrap().error_for_status().unwrap();
+            serde_json::from_slice::<Value>(&response.bytes().await.unwrap()).unwrap()
+        }
+    };
+    let lease = call(
+        "lease",
+        json!({ "worker": "lost", "max": 10, "wait": true }),
+    )
+    .await;
+    assert_eq!(lease["jobs"].as_array().unwrap().len(), 1);
+    let job = lease["jobs"][0].clone();
+    assert_eq!(job["url"], format!("http://{site}/"));
+
+    let worker = worker(addr, "good");
+    let worker = tokio::spawn(async move { worker.run().await });
+    let session = crawl.await??;
+    coordinator.finish().await;
+    worker.await??;
+    assert_eq!(session.pages, 5);
+
+    // too late, the other worker reported it
+    let reported = call(
+        "report",
+        json!({
+            "worker": "lost",
+            "results": [{ "id": job["id"], "outcome": { "error": { "message": "lost", "class": "other" } } }],
+        }),
+    )
+    .await;
+    assert_eq!(reported["accepted"], 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn token_is_required() -> anyhow::Result<()> {
+    let coordinator = Coordinator::new(Duration::from_secs(30)).token("secret");
+    let addr = coordinator.spawn_server(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+    coordinator.finish().await;
+
+    let client = reqwest::Client::new();
+    let lease = json!({ "worker": "w", "max": 1, "wait": false });
+    for (path, body) in [
+        ("lease", lease),
+        ("report", json!({ "worker": "w", "results": [] })),
+    ] {
+        for auth in [None, Some("Bearer wrong"), Some("secret")] {
+            let mut request = client
+                .post(format!("http://{addr}/{path}"))
+                .header("content-type", "application/json")
+                .body(body.to_string());
+            if let Some(auth) = auth {
+                request = request.header("authorization", auth);
+            }
+            assert_eq!(request.send().await?.status(), 401, "{path} {auth:?}");
+        }
+    }
+
+    let mut config = WorkerConfig::new(format!("http://{addr}").parse()?);
+    config.retry_for = Duration::ZERO;
+    let error = Worker::new(config.clone())?.run().await.unwrap_err();
+    assert!(format!("{error:?}").contains("401"), "{error:?}");
+
+    // coordinator has finished, so a worker it lets in exits right away
+    config.token = Some("secret".to_string());
+    assert_eq!(Worker::new(config)?.run().await?, 0);
+    Ok(())
+}
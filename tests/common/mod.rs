@@ -0,0 +1,60 @@
+//! Fixtures shared by the integration tests
+// every test crate compiles its own copy and only uses some of them
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use axum::handler::Handler;
+use axum::{routing::get, Router};
+
+/// Serve `app` on a random port of 127.0.0.1 for the rest of the test
+pub fn serve(app: Router) -> SocketAddr {
+    let server =
+        axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+/// Serve `page` for `/` and `/:page`
+pub fn site<H, T>(page: H) -> SocketAddr
+where
+    H: Handler<T, ()>,
+    T: 'static,
+{
+    serve(
+        Router::new()
+            .route("/", get(page.clone()))
+            .route("/:page", get(page)),
+    )
+}
+
+/// Directory for the files of a test, removed with everything in it (sqlite `-wal`/`-shm` too) on drop
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// `name` has to be unique among tests of the same file, they run in one process
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("waper-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.path.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
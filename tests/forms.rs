@@ -0,0 +1,172 @@
+//! GET forms submitted with configured values to reach pages only linked from search results
+
+mod common;
+
+use std::net::SocketAddr;
+
+use axum::extract::{Path, Query};
+use axum::{response::Html, routing::get, Router};
+use common::TempDir;
+use url::Url;
+use waper::forms::{form_urls, FormRule, Selector};
+use waper::{Database, Orchestrator, RuntimeConfig};
+
+const HOME: &str = r#"
+<form id="search" action="/search#results">
+  <input type="hidden" name="lang" value="en">
+  <input name="q" placeholder="Search">
+  <select name="sort">
+    <option value="relevance">Relevance</option>
+    <option value="date" selected>Newest</option>
+  </select>
+  <input type="checkbox" name="exact">
+  <button type="submit" name="go">Search</button>
+</form>
+<form id="search" method="post" action="/subscribe">
+  <input name="q">
+</form>
+<form class="other" action="/other">
+  <input name="q">
+</form>
+"#;
+
+async fn search(Query(query): Query<Vec<(String, String)>>) -> Html<String> {
+    let q = query
+        .iter()
+        .find(|(name, _)| name == "q")
+        .map(|(_, value)| value.clone())
+        .unwrap_or_default();
+    Html(format!(r#"<a href="/item/{q}">{q}</a>"#))
+}
+
+fn site() -> SocketAddr {
+    let app = Router::new()
+        .route("/", get(|| async { Html(HOME) }))
+        .route("/search", get(search))
+        .route("/item/:name", get(|_: Path<String>| async { Html("") }));
+    common::serve(app)
+}
+
+#[test]
+fn get_forms_with_defaults() -> anyhow::Result<()> {
+    let url: Url = "https://example.com/".parse()?;
+    let rule = FormRule::parse(
+        r#"example\.com/$ form#search q=rust,go sort=relevance,date"#,
+        100,
+    )?;
+    let urls: Vec<_> = form_urls(&[rule], &url, HOME)
+        .into_iter()
+        .map(|x| x.to_string())
+        .collect();
+    // post form and forms not matching the selector are left alone
+    assert_eq!(
+        urls,
+        [
+            "https://example.com/search?lang=en&q=rust&sort=relevance",
+            "https://example.com/search?lang=en&q=rust&sort=date",
+            "https://example.com/search?lang=en&q=go&sort=relevance",
+            "https://example.com/search?lang=en&q=go&sort=date",
+        ]
+    );
+
+    // fields the form doesn't have are added
+    let rule = FormRule::parse("example form.other page=2", 100)?;
+    let urls = form_urls(&[rule], &url, HOME);
+    assert_eq!(urls.len(), 1);
+    assert_eq!(urls[0].as_str(), "https://example.com/other?q=&page=2");
+
+    // other pages
+    let rule = FormRule::parse(r#"/about$ form q=rust"#, 100)?;
+    assert!(form_urls(&[rule], &url, HOME).is_empty());
+    Ok(())
+}
+
+#[test]
+fn wordlist_is_capped() -> anyhow::Result<()> {
+    let dir = TempDir::new("forms-words");
+    let wordlist = dir.join("words.txt");
+    let words: String = (0..50).map(|i| format!("word{i}\n\n")).collect();
+    std::fs::write(&wordlist, words)?;
+    let spec = format!(
+        "example form[id=search] q=@{} sort=relevance,date",
+        wordlist.display()
+    );
+    let rule = FormRule::parse(&spec, 30)?;
+    assert_eq!(rule.fields[0].1.len(), 50);
+
+    let urls = form_urls(&[rule], &"https://example.com/".parse()?, HOME);
+    assert_eq!(urls.len(), 30);
+    assert!(urls[29].as_str().ends_with("q=word14&sort=date"));
+
+    assert!(FormRule::parse("example form q=@/nonexistent/words.txt", 30).is_err());
+    Ok(())
+}
+
+#[test]
+fn selectors() {
+    for ok in [
+        "form",
+        "form#search",
+        ".a.b",
+        "*[action]",
+        r#"form[method="get"].x"#,
+    ] {
+        assert!(ok.parse::<Selector>().is_ok(), "{ok}");
+    }
+    for unsupported in [
+        "div form",
+        "form > input",
+        "form:first-child",
+        "form[",
+        "#",
+        "formé",
+        "form.é",
+    ] {
+        let error = unsupported.parse::<Selector>().unwrap_err();
+        assert!(
+            error.to_string().starts_with("Unsupported selector"),
+            "{error}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn crawl_search_results() -> anyhow::Result<()> {
+    let site = site();
+    let dir = TempDir::new("forms");
+    let db_path = dir.join("out.sqlite");
+    let db = Database::connect(&db_path).await?;
+
+    let config = RuntimeConfig {
+        forms: [FormRule::parse(
+            &format!(r#"^http://{site}/$ form#search q=rust,go"#),
+            100,
+        )?]
+        .into(),
+        ..Default::default()
+    };
+    let mut orchestrator = Orchestrator::builder(db)
+        .seeds([format!("http://{site}/").parse()?])
+        .config(config)
+        .build()?;
+    let session = orchestrator.start(false).await?;
+    assert_eq!((session.pages, session.errors), (5, 0));
+
+    let conn = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_path.display())).await?;
+    let mut urls: Vec<(String,)> = sqlx::query_as("SELECT url FROM results")
+        .fetch_all(&conn)
+        .await?;
+    urls.sort();
+    let expected: Vec<_> = [
+        "/",
+        "/item/go",
+        "/item/rust",
+        "/search?lang=en&q=go&sort=date",
+        "/search?lang=en&q=rust&sort=date",
+    ]
+    .iter()
+    .map(|path| (format!("http://{site}{path}"),))
+    .collect();
+    assert_eq!(urls, expected);
+    Ok(())
+}
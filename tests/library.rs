@@ -0,0 +1,406 @@
+//! Runs the library api against a small in-process server
+
+mod common;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use axum::{extract::Path, http::header, response::Html, routing::get, Router};
+use common::{serve, TempDir};
+use waper::fetch::Oversize;
+use waper::pipeline::{Outcome, Page, PageProcessor, TextExtractor};
+use waper::storage::{Batched, JsonLinesStorage, Tee};
+use waper::{CrawlEvent, Database, ExitReason, Orchestrator, RuntimeConfig, Storage};
+
+fn pages() -> HashMap<&'static str, &'static str> {
+    HashMap::from([
+        (
+            "",
+            r#"<a href="/a">a</a> <a href="/b?utm_source=test">b</a>"#,
+        ),
+        ("a", r#"<a href="/">home</a> <a href="/b">b</a>"#),
+        (
+            "b",
+            r#"<meta property="og:title" content="B"><a href="https://example.invalid/">out</a>"#,
+        ),
+    ])
+}
+
+async fn page(path: Option<Path<String>>) -> Html<String> {
+    let key = path.map(|x| x.0).unwrap_or_default();
+    Html(pages().get(key.as_str()).unwrap_or(&"").to_string())
+}
+
+/// Serve `pages()` on a random port
+fn start_server() -> SocketAddr {
+    common::site(page)
+}
+
+#[tokio::test]
+async fn scrape_with_builder() -> anyhow::Result<()> {
+    let addr = start_server();
+    let dir = TempDir::new("builder");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+    let mut orchestrator = Orchestrator::builder(db.clone())
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config)
+        .events(events_tx)
+        .build()?;
+    orchestrator.start(false).await?;
+    drop(orchestrator);
+
+    let mut scraped = vec![];
+    while let Some(event) = events_rx.recv().await {
+        if let CrawlEvent::Page { url, metadata, .. } = event {
+            if url.path() == "/b" {
+                assert_eq!(metadata.opengraph["og:title"], vec!["B".to_string()]);
+            }
+            scraped.push(url.path().to_string());
+        }
+    }
+    scraped.sort();
+    // `/b?utm_source=test` is normalized to `/b` and external link is not whitelisted
+    assert_eq!(scraped, vec!["/", "/a", "/b"]);
+    assert!(db.get_unprocessed_links().await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stop_and_resume() -> anyhow::Result<()> {
+    let addr = start_server();
+    let dir = TempDir::new("resume");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+
+    let mut orchestrator = Orchestrator::builder(db.clone())
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config.clone())
+        .build()?;
+    // stopped before anything is scheduled, seed is still allowed to finish
+    orchestrator.stop_handle().stop(ExitReason::Interrupted);
+    let session = orchestrator.start(false).await?;
+    assert_eq!(session.exit_reason, ExitReason::Interrupted);
+    assert_eq!(session.pages, 1);
+    assert_eq!(session.frontier, 2);
+    assert_eq!(db.get_unprocessed_links().await?.len(), 2);
+
+    let session = Orchestrator::builder(db.clone())
+        .config(config)
+        .build()?
+        .start(true)
+        .await?;
+    assert_eq!(session.exit_reason, ExitReason::Completed);
+    assert!(db.get_unprocessed_links().await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stop_on_max_pages() -> anyhow::Result<()> {
+    let addr = start_server();
+    let dir = TempDir::new("limits");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let mut config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+    config.limits.max_pages = Some(1);
+
+    let session = Orchestrator::builder(db.clone())
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config)
+        .build()?
+        .start(false)
+        .await?;
+    assert_eq!(session.exit_reason, ExitReason::MaxPages);
+    assert_eq!(session.pages, 1);
+    assert_eq!(db.get_unprocessed_links().await?.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn truncate_large_and_skip_non_html() -> anyhow::Result<()> {
+    let addr = serve(
+        Router::new()
+            .route(
+                "/",
+                get(|| async { Html(r#"<a href="/big">big</a> <a href="/doc.pdf">pdf</a>"#) }),
+            )
+            .route("/big", get(|| async { Html("a".repeat(100_000)) }))
+            .route(
+                "/doc.pdf",
+                get(|| async { ([(header::CONTENT_TYPE, "application/pdf")], "%PDF") }),
+            ),
+    );
+    let dir = TempDir::new("body");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let mut config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+    config.fetch.max_body_size = Some(1000);
+    config.fetch.oversize = Oversize::Truncate;
+
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+    Orchestrator::builder(db.clone())
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config)
+        .events(events_tx)
+        .build()?
+        .start(false)
+        .await?;
+
+    let mut scraped = vec![];
+    while let Some(event) = events_rx.recv().await {
+        if let CrawlEvent::Page { url, html, .. } = event {
+            if url.path() == "/big" {
+                assert_eq!(html.len(), 1000);
+            }
+            scraped.push(url.path().to_string());
+        }
+    }
+    scraped.sort();
+    assert_eq!(scraped, vec!["/", "/big"]);
+    // pdf is skipped, so it's not part of the frontier
+    assert!(db.get_unprocessed_links().await?.is_empty());
+
+    Ok(())
+}
+
+/// Drops `/a`, so it's links are not followed
+struct DropA;
+
+#[async_trait::async_trait]
+impl PageProcessor for DropA {
+    fn name(&self) -> &str {
+        "drop-a"
+    }
+
+    async fn process(&self, page: &mut Page) -> anyhow::Result<Outcome> {
+        Ok(if page.url.path() == "/a" {
+            Outcome::Drop
+        } else {
+            Outcome::Continue
+        })
+    }
+}
+
+#[tokio::test]
+async fn scrape_with_processors() -> anyhow::Result<()> {
+    let addr = start_server();
+    let dir = TempDir::new("processors");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+    Orchestrator::builder(db.clone())
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config)
+        .processor(DropA)
+        .processor(TextExtractor)
+        .events(events_tx)
+        .build()?
+        .start(false)
+        .await?;
+
+    let mut scraped = vec![];
+    while let Some(event) = events_rx.recv().await {
+        if let CrawlEvent::Page { url, data, .. } = event {
+            assert!(data.contains_key("text"));
+            scraped.push(url.path().to_string());
+        }
+    }
+    scraped.sort();
+    assert_eq!(scraped, vec!["/", "/b"]);
+
+    Ok(())
+}
+
+/// Crawl `pages()` into `storage`, returns scraped paths
+async fn crawl(storage: impl Storage + Clone + 'static) -> anyhow::Result<Vec<String>> {
+    let addr = start_server();
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+    Orchestrator::builder(storage.clone())
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config)
+        .events(events_tx)
+        .build()?
+        .start(false)
+        .await?;
+
+    let mut scraped = vec![];
+    while let Some(event) = events_rx.recv().await {
+        if let CrawlEvent::Page { url, .. } = event {
+            scraped.push(url.path().to_string());
+        }
+    }
+    scraped.sort();
+    assert!(storage.get_unprocessed_links().await?.is_empty());
+    Ok(scraped)
+}
+
+#[tokio::test]
+async fn scrape_to_sqlite_and_json_lines() -> anyhow::Result<()> {
+    let dir = TempDir::new("jsonl");
+    let jsonl_path = dir.join("out.jsonl");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let jsonl = JsonLinesStorage::new(tokio::fs::File::create(&jsonl_path).await?);
+    let storage = std::sync::Arc::new(Tee(db.clone(), jsonl));
+    assert_eq!(crawl(storage).await?, vec!["/", "/a", "/b"]);
+    assert_eq!(db.get_result_urls().await?.len(), 3);
+
+    let lines = std::fs::read_to_string(&jsonl_path)?;
+    let kinds: Vec<_> = lines
+        .lines()
+        .map(|x| serde_json::from_str::<serde_json::Value>(x).unwrap()["kind"].clone())
+        .collect();
+    assert_eq!(kinds, vec!["result"; 3]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn scrape_with_batched_writes() -> anyhow::Result<()> {
+    let dir = TempDir::new("batched");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    // tiny batches and queue, so writes have to wait for the writer task
+    let storage = std::sync::Arc::new(Batched::new(db.clone(), 2, 1));
+    assert_eq!(crawl(storage).await?, vec!["/", "/a", "/b"]);
+    assert_eq!(db.get_result_urls().await?.len(), 3);
+
+    Ok(())
+}
+
+#[cfg(feature = "fs-storage")]
+#[tokio::test]
+async fn scrape_to_fs_storage() -> anyhow::Result<()> {
+    let dir = TempDir::new("fs");
+    let storage = std::sync::Arc::new(waper::storage::FsStorage::open(dir.path()).await?);
+    assert_eq!(crawl(storage).await?, vec!["/", "/a", "/b"]);
+    assert_eq!(std::fs::read_dir(dir.join("pages"))?.count(), 3);
+    Ok(())
+}
+
+/// Needs a running postgres, e.g. WAPER_TEST_POSTGRES_URL=postgres://postgres@localhost/waper_test,
+/// skipped without it
+#[cfg(feature = "postgres")]
+#[tokio::test]
+async fn scrape_to_postgres_storage() -> anyhow::Result<()> {
+    let Ok(url) = std::env::var("WAPER_TEST_POSTGRES_URL") else {
+        eprintln!("skipped: WAPER_TEST_POSTGRES_URL is not set");
+        return Ok(());
+    };
+    let storage = waper::storage::PostgresStorage::connect(&url).await?;
+    assert_eq!(crawl(storage).await?, vec!["/", "/a", "/b"]);
+    Ok(())
+}
+
+/// Needs Chromium installed, found in usual locations or `CHROME` env var
+#[cfg(feature = "render")]
+#[ignore]
+#[tokio::test]
+async fn scrape_with_renderer() -> anyhow::Result<()> {
+    use waper::render::{RenderConfig, Renderer};
+
+    let addr = serve(
+        Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    Html(r#"<body><script>document.body.innerHTML = '<a href="/app">app</a>'</script></body>"#)
+                }),
+            )
+            .route("/app", get(|| async { Html("<h1>App</h1>") })),
+    );
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+    let renderer = Renderer::new(RenderConfig {
+        patterns: regex::RegexSet::new([format!("^http://{addr}/$")])?,
+        args: vec!["--no-sandbox".into()],
+        ..Default::default()
+    });
+    let dir = TempDir::new("render");
+    Orchestrator::builder(Database::connect(&dir.join("out.sqlite")).await?)
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config)
+        .events(events_tx)
+        .renderer(renderer)
+        .build()?
+        .start(false)
+        .await?;
+
+    let mut scraped = vec![];
+    while let Some(event) = events_rx.recv().await {
+        if let CrawlEvent::Page { url, .. } = event {
+            scraped.push(url.path().to_string());
+        }
+    }
+    scraped.sort();
+    // only reachable through the link added by the script
+    assert_eq!(scraped, vec!["/", "/app"]);
+    Ok(())
+}
+
+#[cfg(feature = "js")]
+#[tokio::test]
+async fn scrape_with_js_evaluation() -> anyhow::Result<()> {
+    use waper::js::{JsConfig, JsEvaluator};
+
+    let addr = serve(
+        Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    Html(
+                        r#"<script>
+                        if (location.pathname === "/") location.href = "/next";
+                        window.addEventListener("load", () => fetch("/api?page=" + 1));
+                        </script>
+                        <script src="/app.js"></script>
+                        <script src="https://example.invalid/tracker.js"></script>"#,
+                    )
+                }),
+            )
+            .route(
+                "/app.js",
+                get(|| async {
+                    let js = r#"document.getElementById("menu").innerHTML = '<a href="/menu">menu</a>';
+                        setTimeout(function () { undefinedFunction(); });
+                        setTimeout(function () { while (true) {} });
+                        const a = document.createElement("a"); a.href = "/created";"#;
+                    ([(header::CONTENT_TYPE, "application/javascript")], js)
+                }),
+            )
+            .route("/:page", get(|| async { Html("") })),
+    );
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+    let dir = TempDir::new("js");
+    Orchestrator::builder(Database::connect(&dir.join("out.sqlite")).await?)
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(config)
+        .events(events_tx)
+        .processor(JsEvaluator::new(
+            JsConfig {
+                timeout: std::time::Duration::from_millis(200),
+                ..Default::default()
+            },
+            reqwest::Client::new(),
+        ))
+        .build()?
+        .start(false)
+        .await?;
+
+    let mut scraped = vec![];
+    while let Some(event) = events_rx.recv().await {
+        if let CrawlEvent::Page { url, .. } = event {
+            scraped.push(url[url::Position::BeforePath..].to_string());
+        }
+    }
+    scraped.sort();
+    assert_eq!(
+        scraped,
+        vec!["/", "/api?page=1", "/created", "/menu", "/next"]
+    );
+    Ok(())
+}
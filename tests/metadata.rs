@@ -0,0 +1,100 @@
+//! JSON-LD, OpenGraph and microdata extracted from inline html
+
+use select::document::Document;
+use serde_json::json;
+use waper::metadata::PageMetadata;
+
+fn extract(html: &str) -> PageMetadata {
+    PageMetadata::extract(&Document::from(html))
+}
+
+#[test]
+fn json_ld() {
+    let metadata = extract(
+        r#"<script type="application/ld+json">
+            {"@context": "https://schema.org", "@type": "Article", "headline": "A"}
+        </script>
+        <script type="application/ld+json">{broken</script>
+        <script type="application/json">{"not": "ld"}</script>
+        <script type="application/ld+json">[{"@type": "Person"}]</script>"#,
+    );
+    assert_eq!(
+        metadata.json_ld,
+        [
+            json!({"@context": "https://schema.org", "@type": "Article", "headline": "A"}),
+            json!([{"@type": "Person"}]),
+        ]
+    );
+    assert!(metadata.opengraph.is_empty());
+    assert!(metadata.microdata.is_empty());
+}
+
+#[test]
+fn opengraph() {
+    let metadata = extract(
+        r#"<meta property="og:title" content=" Title ">
+        <meta property="og:image" content="/a.png"><meta property="og:image" content="/b.png">
+        <meta name="twitter:card" content="summary">
+        <meta name="description" content="not og">
+        <meta property="og:url">"#,
+    );
+    let opengraph: Vec<_> = metadata
+        .opengraph
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.join(" ")))
+        .collect();
+    assert_eq!(
+        opengraph,
+        [
+            ("og:image", "/a.png /b.png".to_string()),
+            ("og:title", "Title".to_string()),
+            ("twitter:card", "summary".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn microdata() {
+    let metadata = extract(
+        r#"<div itemscope itemtype="https://schema.org/Product" itemid="urn:1">
+            <h1 itemprop="name"> Lamp </h1>
+            <img itemprop="image" src="/lamp.png">
+            <a itemprop="url sameAs" href="/lamp">lamp</a>
+            <div itemprop="offers" itemscope itemtype="https://schema.org/Offer">
+                <meta itemprop="price" content="10">
+                <time itemprop="validFrom" datetime="2024-01-01">today</time>
+            </div>
+        </div>
+        <p itemscope><span itemprop="note">second</span></p>"#,
+    );
+    assert!(metadata.json_ld.is_empty());
+    // nested items are properties of their parent, not top level items
+    assert_eq!(
+        serde_json::to_value(&metadata.microdata).unwrap(),
+        json!([
+            {
+                "type": ["https://schema.org/Product"],
+                "id": "urn:1",
+                "properties": {
+                    "name": ["Lamp"],
+                    "image": ["/lamp.png"],
+                    "url": ["/lamp"],
+                    "sameAs": ["/lamp"],
+                    "offers": [{
+                        "type": ["https://schema.org/Offer"],
+                        "properties": {
+                            "price": ["10"],
+                            "validFrom": ["2024-01-01"],
+                        },
+                    }],
+                },
+            },
+            {"type": [], "properties": {"note": ["second"]}},
+        ])
+    );
+}
+
+#[test]
+fn nothing_to_extract() {
+    assert!(extract("<p>plain page</p>").is_empty());
+}
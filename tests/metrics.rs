@@ -0,0 +1,48 @@
+//! Labels of request metrics
+
+mod common;
+
+use axum::{extract::Host, response::Html, routing::get, Router};
+use common::{serve, TempDir};
+use waper::metrics::metrics;
+use waper::{Database, Orchestrator, RuntimeConfig};
+
+#[tokio::test]
+async fn only_seed_hosts_are_labelled() -> anyhow::Result<()> {
+    // same server under another host name
+    let addr = serve(
+        Router::new()
+            .route(
+                "/",
+                get(|Host(host): Host| async move {
+                    let other = host.replace("127.0.0.1", "localhost");
+                    Html(format!(r#"<a href="http://{other}/a">a</a>"#))
+                }),
+            )
+            .route("/a", get(|| async { Html("<p>a</p>") })),
+    );
+    let dir = TempDir::new("metrics-hosts");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    Orchestrator::builder(db.clone())
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(
+            RuntimeConfig::default()
+                .with_whitelist([format!("http://(127.0.0.1|localhost):{}/.*", addr.port())])?,
+        )
+        .build()?
+        .start(false)
+        .await?;
+    assert_eq!(db.get_result_urls().await?.len(), 2);
+
+    let metrics = metrics().encode();
+    assert!(
+        metrics.contains(r#"waper_requests_total{host="127.0.0.1",kind="page",status="2xx"} 1"#),
+        "{metrics}"
+    );
+    assert!(
+        metrics.contains(r#"waper_requests_total{host="other",kind="page",status="2xx"} 1"#),
+        "{metrics}"
+    );
+    assert!(!metrics.contains("localhost"), "{metrics}");
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! Output files written by older (and newer) waper
+
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::TempDir;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use waper::db::migrate::LATEST_VERSION;
+use waper::db::SchemaStatus;
+use waper::Database;
+
+async fn create(path: &Path, sql: &str) -> anyhow::Result<()> {
+    let conn = SqlitePool::connect_with(
+        SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true),
+    )
+    .await?;
+    sqlx::query(sql).execute(&conn).await?;
+    conn.close().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn upgrade_file_from_before_versioning() -> anyhow::Result<()> {
+    let dir = TempDir::new("migrate-baseline");
+    let path = dir.join("out.sqlite");
+    create(
+        &path,
+        &(include_str!("../sqls/migrations/0001_baseline.sql").to_string()
+            + "INSERT INTO results (url, content) VALUES ('http://example.com/', '<html>');
+               INSERT INTO links (url) VALUES ('http://example.com/next');"),
+    )
+    .await?;
+    assert_eq!(
+        Database::check(&path).await?,
+        SchemaStatus::Outdated { version: 1 }
+    );
+
+    let db = Database::connect(&path).await?;
+    assert_eq!(
+        Database::check(&path).await?,
+        SchemaStatus::UpToDate(LATEST_VERSION)
+    );
+    assert_eq!(db.get_result_urls().await?, vec!["http://example.com/"]);
+    assert!(db.get_metadata().await?.is_empty());
+
+    let backup = PathBuf::from(format!("{}.v1.bak", path.display()));
+    assert_eq!(
+        Database::check(&backup).await?,
+        SchemaStatus::Outdated { version: 1 }
+    );
+    // nothing left to do
+    assert_eq!(Database::migrate(&path).await?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn refuse_file_from_newer_waper() -> anyhow::Result<()> {
+    let dir = TempDir::new("migrate-newer");
+    let path = dir.join("out.sqlite");
+    Database::connect(&path).await?;
+    create_newer(&path).await?;
+
+    assert_eq!(
+        Database::check(&path).await?,
+        SchemaStatus::Newer {
+            version: LATEST_VERSION + 1
+        }
+    );
+    let err = Database::connect(&path).await.err().unwrap();
+    assert!(format!("{err}").contains("newer"), "{err}");
+    Ok(())
+}
+
+async fn create_newer(path: &Path) -> anyhow::Result<()> {
+    let conn = SqlitePool::connect_with(SqliteConnectOptions::new().filename(path)).await?;
+    sqlx::query("INSERT INTO schema_version (version) VALUES (?)")
+        .bind(LATEST_VERSION + 1)
+        .execute(&conn)
+        .await?;
+    conn.close().await;
+    Ok(())
+}
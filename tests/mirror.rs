@@ -0,0 +1,178 @@
+//! Offline mirror written from a database: file layout and rewritten links
+
+mod common;
+
+use std::path::Path;
+
+use common::TempDir;
+use waper::assets::AssetKind;
+use waper::{Database, Storage};
+
+const DEEP: &str = r##"<a href="/">home</a> <a href='../../docs/'>docs</a> <a href=/docs>docs</a>
+<img src="/img/logo.png"> <a href="https://other.org/x">other</a> <a href="#top">top</a>
+<a href="/search?q=a%20b#results">search</a> <a href="http://example.com:8080/x.html">port</a>"##;
+
+async fn mirror(dir: &TempDir) -> anyhow::Result<usize> {
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let pages = [
+        (
+            "https://example.com/",
+            r#"<a href="search?q=a%20b">search</a>"#,
+        ),
+        ("https://example.com/docs", ""),
+        ("https://example.com/docs/", ""),
+        ("https://example.com/search?q=a%20b", ""),
+        ("https://example.com/a/b/c.html", DEEP),
+        ("http://example.com:8080/x.html", ""),
+    ];
+    for (url, html) in pages {
+        db.add_to_results(url.parse()?, html.to_string()).await?;
+    }
+    let assets = [
+        ("https://example.com/img", AssetKind::Image, ""),
+        ("https://example.com/img/logo.png", AssetKind::Image, "png"),
+        (
+            "https://example.com/css/site.css",
+            AssetKind::Stylesheet,
+            r#"body { background: url("/img") } @import "/docs/";"#,
+        ),
+    ];
+    for (url, kind, content) in assets {
+        db.add_to_assets(url.parse()?, kind, None, content.as_bytes().to_vec())
+            .await?;
+    }
+    waper::mirror::mirror(&db, &dir.join("mirror")).await
+}
+
+fn read(dir: &TempDir, path: &str) -> String {
+    let path = Path::new("mirror").join(path);
+    std::fs::read_to_string(dir.path().join(&path))
+        .unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+}
+
+#[tokio::test]
+async fn file_layout() -> anyhow::Result<()> {
+    let dir = TempDir::new("mirror-layout");
+    assert_eq!(mirror(&dir).await?, 9);
+
+    let mut files: Vec<_> = walk(&dir.join("mirror"))
+        .into_iter()
+        .map(|x| x.replace('\\', "/"))
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "example.com/a/b/c.html",
+            "example.com/css/site.css",
+            // `/docs` and `/docs/` both want `docs/index.html`
+            "example.com/docs/index-1.html",
+            "example.com/docs/index.html",
+            // asset `/img` can't be a file, `/img/logo.png` needs the directory
+            "example.com/img/index",
+            "example.com/img/logo.png",
+            "example.com/index.html",
+            // pages without html extension become directories, the query goes in the file name
+            "example.com/search/index@q=a_20b.html",
+            "example.com_8080/x.html",
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn relative_links() -> anyhow::Result<()> {
+    let dir = TempDir::new("mirror-links");
+    mirror(&dir).await?;
+
+    let deep = read(&dir, "example.com/a/b/c.html");
+    for expected in [
+        r#"href="../../index.html""#,
+        r#"href='../../docs/index-1.html'"#,
+        r#"href="../../docs/index.html""#,
+        r#"src="../../img/logo.png""#,
+        // not mirrored, stays absolute
+        r#"href="https://other.org/x""#,
+        r##"href="#top""##,
+        r##"href="../../search/index@q=a_20b.html#results""##,
+        r#"href="../../../example.com_8080/x.html""#,
+    ] {
+        assert!(deep.contains(expected), "{expected} not in {deep}");
+    }
+
+    let home = read(&dir, "example.com/index.html");
+    assert!(
+        home.contains(r#"href="search/index@q=a_20b.html""#),
+        "{home}"
+    );
+
+    let css = read(&dir, "example.com/css/site.css");
+    assert_eq!(
+        css,
+        r#"body { background: url("../img/index") } @import "../docs/index-1.html";"#
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_links_are_rewritten() -> anyhow::Result<()> {
+    let dir = TempDir::new("mirror-html");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let page = r#"<!-- <a href="/docs/">commented out</a> -->
+<script>var a = '<a href="/docs/">'; img.src = "/img/a.png";</script>
+<style>body { background: url(/img/a.png) }</style>
+<a title='see href="/docs/"' HREF="/docs/" style="background: url('/img/b.png')">docs</a>
+<img srcset="/img/a.png 1x,/img/b.png 2x, data:image/png;base64,AA== 3x" src="/img/a.png">
+<p>text with url(/img/a.png) and href=/docs/</p>"#;
+    let base = r#"<head><base href="/img/" target="_blank"></head><img src="a.png"> <a href="../docs/">docs</a>"#;
+    for (url, html) in [
+        ("https://example.com/docs/", ""),
+        ("https://example.com/p/page.html", page),
+        ("https://example.com/base.html", base),
+    ] {
+        db.add_to_results(url.parse()?, html.to_string()).await?;
+    }
+    for url in [
+        "https://example.com/img/a.png",
+        "https://example.com/img/b.png",
+    ] {
+        db.add_to_assets(url.parse()?, AssetKind::Image, None, vec![])
+            .await?;
+    }
+    waper::mirror::mirror(&db, &dir.join("mirror")).await?;
+
+    // comments, scripts, other attributes and text are left alone
+    assert_eq!(
+        read(&dir, "example.com/p/page.html"),
+        r#"<!-- <a href="/docs/">commented out</a> -->
+<script>var a = '<a href="/docs/">'; img.src = "/img/a.png";</script>
+<style>body { background: url(../img/a.png) }</style>
+<a title='see href="/docs/"' HREF="../docs/index.html" style="background: url('../img/b.png')">docs</a>
+<img srcset="../img/a.png 1x, ../img/b.png 2x, data:image/png;base64,AA== 3x" src="../img/a.png">
+<p>text with url(/img/a.png) and href=/docs/</p>"#
+    );
+    // links are resolved against `<base>`, which has to go for relative links to work
+    assert_eq!(
+        read(&dir, "example.com/base.html"),
+        r#"<head></head><img src="img/a.png"> <a href="docs/index.html">docs</a>"#
+    );
+    Ok(())
+}
+
+/// Files under `root`, relative to it
+fn walk(root: &Path) -> Vec<String> {
+    let mut files = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                let relative = path.strip_prefix(root).unwrap();
+                files.push(relative.to_string_lossy().to_string());
+            }
+        }
+    }
+    files
+}
@@ -0,0 +1,66 @@
+//! Url normalization applied before filtering and deduplication
+
+use waper::normalize::Normalizer;
+
+fn check(normalizer: &Normalizer, cases: &[(&str, &str)]) {
+    for (url, expected) in cases {
+        let normalized = normalizer.normalize(&url.parse().unwrap());
+        assert_eq!(normalized.as_str(), *expected, "{url}");
+    }
+}
+
+#[test]
+fn defaults() {
+    check(
+        &Normalizer::default(),
+        &[
+            // host and scheme case, default ports and fragments
+            (
+                "HTTPS://Example.COM:443/Path#top",
+                "https://example.com/Path",
+            ),
+            ("http://example.com:80/", "http://example.com/"),
+            ("http://example.com:8080/", "http://example.com:8080/"),
+            // tracking params
+            (
+                "https://example.com/a?utm_source=x&id=1&utm_medium=y&gclid=z",
+                "https://example.com/a?id=1",
+            ),
+            ("https://example.com/a?fbclid=1", "https://example.com/a"),
+            ("https://example.com/a?utm=1", "https://example.com/a?utm=1"),
+            // sorted by name, repeated params keep their order, values are not re-encoded
+            (
+                "https://example.com/a?b=2&a=1&c=%20&a=0",
+                "https://example.com/a?a=1&a=0&b=2&c=%20",
+            ),
+            ("https://example.com/a?&&b&a", "https://example.com/a?a&b"),
+            // trailing slash is kept
+            ("https://example.com/a/", "https://example.com/a/"),
+        ],
+    );
+}
+
+#[test]
+fn options() {
+    let normalizer = Normalizer {
+        strip_params: vec!["session*".to_string(), "ref".to_string()],
+        sort_query: false,
+        fold_trailing_slash: true,
+        ..Default::default()
+    };
+    check(
+        &normalizer,
+        &[
+            (
+                "https://example.com/a/?sessionid=1&ref=x&b=2&a=1&utm_source=y",
+                "https://example.com/a?b=2&a=1&utm_source=y",
+            ),
+            ("https://example.com/a/b//", "https://example.com/a/b"),
+            ("https://example.com/", "https://example.com/"),
+            (
+                "https://example.com/a?refs=1",
+                "https://example.com/a?refs=1",
+            ),
+        ],
+    );
+}
@@ -0,0 +1,127 @@
+//! Crawls losing their connection midway, simulated with a fetcher which can be switched off
+
+mod common;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{extract::Path, response::Html};
+use common::TempDir;
+use url::Url;
+use waper::assets::{AssetKind, AssetResult};
+use waper::fetch::{FetchConfig, Fetcher};
+use waper::outage::OutageConfig;
+use waper::{Database, ExitReason, Orchestrator, RuntimeConfig, ScrapingResult};
+
+async fn page(path: Option<Path<String>>) -> Html<String> {
+    match path.map(|x| x.0).unwrap_or_default().as_str() {
+        "" => Html(
+            (0..20)
+                .map(|i| format!(r#"<a href="/p{i}">{i}</a>"#))
+                .chain((0..8).map(|i| format!(r#"<a href="http://127.0.0.1:1/dead{i}">x</a>"#)))
+                .collect(),
+        ),
+        _ => Html(String::new()),
+    }
+}
+
+/// Requests go to a closed port while offline, so they fail like without a network
+struct Flaky {
+    client: reqwest::Client,
+    online: Arc<AtomicBool>,
+    /// Goes offline after this many requests
+    offline_after: Option<usize>,
+    requests: AtomicUsize,
+}
+
+#[async_trait]
+impl Fetcher for Flaky {
+    async fn page(&self, url: &Url, config: &FetchConfig) -> anyhow::Result<ScrapingResult> {
+        if Some(self.requests.fetch_add(1, Ordering::SeqCst)) == self.offline_after {
+            self.online.store(false, Ordering::SeqCst);
+        }
+        if self.online.load(Ordering::SeqCst) {
+            self.client.page(url, config).await
+        } else {
+            self.client
+                .page(&"http://127.0.0.1:1/".parse()?, config)
+                .await
+        }
+    }
+
+    async fn asset(
+        &self,
+        _url: &Url,
+        _kind: AssetKind,
+        _discover_js_urls: bool,
+        _config: &FetchConfig,
+    ) -> anyhow::Result<AssetResult> {
+        unreachable!("no assets are fetched")
+    }
+}
+
+async fn crawl(name: &str, offline_after: Option<usize>) -> anyhow::Result<(waper::Session, bool)> {
+    let site = common::site(page);
+    let dir = TempDir::new(name);
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let online = Arc::new(AtomicBool::new(true));
+    let fetcher = Flaky {
+        client: FetchConfig::default().client()?,
+        online: online.clone(),
+        offline_after,
+        requests: AtomicUsize::new(0),
+    };
+
+    let mut config = RuntimeConfig::default();
+    config.rate_limit.set_max_parallel_requests(3);
+    config.outage = OutageConfig {
+        max_errors: 5,
+        probe_interval: Duration::from_millis(100),
+        probe_url: None,
+    };
+    let mut orchestrator = Orchestrator::builder(db)
+        .seeds([format!("http://{site}/").parse()?])
+        .config(config)
+        .fetcher(fetcher)
+        .build()?;
+
+    // the network comes back a while after the crawl noticed it's gone
+    let control = orchestrator.control();
+    let was_offline = Arc::new(AtomicBool::new(false));
+    let watcher = tokio::spawn({
+        let was_offline = was_offline.clone();
+        async move {
+            while !control.is_offline() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            was_offline.store(true, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            online.store(true, Ordering::SeqCst);
+        }
+    });
+    let session = orchestrator.start(false).await?;
+    watcher.abort();
+    Ok((session, was_offline.load(Ordering::SeqCst)))
+}
+
+#[tokio::test]
+async fn lost_connection_pauses_and_retries() -> anyhow::Result<()> {
+    // offline right after the first page
+    let (session, was_offline) = crawl("outage", Some(1)).await?;
+    assert!(was_offline);
+    assert_eq!(session.exit_reason, ExitReason::Completed);
+    // only the unreachable links failed
+    assert_eq!((session.pages, session.errors), (21, 8));
+    Ok(())
+}
+
+#[tokio::test]
+async fn unreachable_links_are_errors() -> anyhow::Result<()> {
+    // more unreachable links than `max_errors` but the site itself responds
+    let (session, was_offline) = crawl("outage-dead-links", None).await?;
+    assert!(!was_offline);
+    assert_eq!((session.pages, session.errors), (21, 8));
+    Ok(())
+}
@@ -0,0 +1,44 @@
+//! External commands run as page processors
+#![cfg(unix)]
+
+use std::time::{Duration, Instant};
+
+use waper::pipeline::{CommandProcessor, Outcome, Page, PageProcessor};
+
+fn page() -> Page {
+    Page {
+        url: "https://example.com/a/".parse().unwrap(),
+        status: 200,
+        headers: Default::default(),
+        html: r#"<a href="/b">b</a>"#.to_string(),
+        rendered: None,
+        links: vec!["https://example.com/b".parse().unwrap()],
+        data: Default::default(),
+    }
+}
+
+#[tokio::test]
+async fn command_output() -> anyhow::Result<()> {
+    let processor = CommandProcessor::from_shell(
+        r#"sh -c 'cat > /dev/null; echo "{\"data\": {\"class\": \"x\"}, \"links\": [], \"urls\": [\"c\"]}"'"#,
+    )?;
+    let mut page = page();
+    assert_eq!(processor.process(&mut page).await?, Outcome::Continue);
+    assert_eq!(page.data["class"], "x");
+    let links: Vec<_> = page.links.iter().map(|x| x.as_str()).collect();
+    assert_eq!(links, ["https://example.com/a/c"]);
+
+    let drop = CommandProcessor::from_shell(r#"echo '{"drop": true}'"#)?;
+    assert_eq!(drop.process(&mut page).await?, Outcome::Drop);
+    Ok(())
+}
+
+#[tokio::test]
+async fn command_timeout() -> anyhow::Result<()> {
+    let processor = CommandProcessor::from_shell("sleep 10")?.timeout(Duration::from_millis(200));
+    let started = Instant::now();
+    let error = processor.process(&mut page()).await.unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(error.to_string().contains("timed out"), "{error}");
+    Ok(())
+}
@@ -0,0 +1,150 @@
+//! Postgres schema versioning. Tests which need a database use
+//! `WAPER_TEST_POSTGRES_URL=postgres://postgres@localhost/waper_test` and are skipped without it.
+#![cfg(feature = "postgres")]
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use regex::Regex;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::Executor;
+use waper::db::migrate::{Migration, LATEST_VERSION, MIGRATIONS};
+use waper::db::SchemaStatus;
+use waper::storage::PostgresStorage;
+
+/// Migration files in `dir`, by name
+fn files(dir: &str) -> Vec<(String, String)> {
+    let mut files: Vec<_> = std::fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join(dir))
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .collect();
+    files.sort();
+    files
+        .into_iter()
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            (name, std::fs::read_to_string(&path).unwrap())
+        })
+        .collect()
+}
+
+/// Tables created by each migration file in `dir`, by name
+fn tables(dir: &str) -> Vec<(String, BTreeSet<String>)> {
+    let create = Regex::new(r"CREATE TABLE\s+(?:IF NOT EXISTS\s+)?(\w+)").unwrap();
+    files(dir)
+        .into_iter()
+        .map(|(name, sql)| {
+            let tables = create.captures_iter(&sql).map(|x| x[1].to_string());
+            (name, tables.collect())
+        })
+        .collect()
+}
+
+#[test]
+fn migrations_match_sqlite() {
+    let versions = |migrations: &[Migration]| {
+        migrations
+            .iter()
+            .map(|x| (x.version, x.description))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(versions(PostgresStorage::MIGRATIONS), versions(MIGRATIONS));
+    assert_eq!(
+        tables("sqls/postgres/migrations"),
+        tables("sqls/migrations")
+    );
+
+    // databases from before versioning may have any of the tables already
+    let create = Regex::new(r"CREATE (?:TABLE|INDEX) (\w+)").unwrap();
+    for (name, sql) in files("sqls/postgres/migrations") {
+        for statement in create.captures_iter(&sql) {
+            assert_eq!(&statement[1], "IF", "{name}: {}", &statement[0]);
+        }
+    }
+}
+
+/// Pool on a schema of its own, dropped first if left over from an earlier run
+async fn pool(schema: &str) -> anyhow::Result<Option<PgPool>> {
+    let Ok(url) = std::env::var("WAPER_TEST_POSTGRES_URL") else {
+        eprintln!("skipped: WAPER_TEST_POSTGRES_URL is not set");
+        return Ok(None);
+    };
+    let admin = PgPool::connect(&url).await?;
+    admin
+        .execute(format!("DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}").as_str())
+        .await?;
+    let search_path = format!("SET search_path TO {schema}");
+    let pool = PgPoolOptions::new()
+        .after_connect(move |conn, _| {
+            let search_path = search_path.clone();
+            Box::pin(async move {
+                conn.execute(search_path.as_str()).await?;
+                Ok(())
+            })
+        })
+        .connect(&url)
+        .await?;
+    Ok(Some(pool))
+}
+
+#[tokio::test]
+async fn create_schema() -> anyhow::Result<()> {
+    let Some(pool) = pool("waper_test_create").await? else {
+        return Ok(());
+    };
+    assert_eq!(
+        PostgresStorage::migrate(&pool).await?,
+        SchemaStatus::Outdated { version: 0 }
+    );
+    assert_eq!(
+        PostgresStorage::migrate(&pool).await?,
+        SchemaStatus::UpToDate(LATEST_VERSION)
+    );
+    let (tables,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = 'waper_test_create'",
+    )
+    .fetch_one(&pool)
+    .await?;
+    // and schema_version
+    assert_eq!(tables, 15);
+    Ok(())
+}
+
+#[tokio::test]
+async fn upgrade_database_from_before_versioning() -> anyhow::Result<()> {
+    let Some(pool) = pool("waper_test_upgrade").await? else {
+        return Ok(());
+    };
+    pool.execute(include_str!(
+        "../sqls/postgres/migrations/0001_baseline.sql"
+    ))
+    .await?;
+    pool.execute("INSERT INTO results (url, content) VALUES ('http://example.com/', '<html>')")
+        .await?;
+    assert_eq!(
+        PostgresStorage::migrate(&pool).await?,
+        SchemaStatus::Outdated { version: 1 }
+    );
+    let mut conn = pool.acquire().await?;
+    assert_eq!(PostgresStorage::version(&mut conn).await?, LATEST_VERSION);
+    let (results,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM results")
+        .fetch_one(&mut conn)
+        .await?;
+    assert_eq!(results, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn refuse_database_from_newer_waper() -> anyhow::Result<()> {
+    let Some(pool) = pool("waper_test_newer").await? else {
+        return Ok(());
+    };
+    PostgresStorage::migrate(&pool).await?;
+    sqlx::query("INSERT INTO schema_version (version) VALUES ($1)")
+        .bind(LATEST_VERSION + 1)
+        .execute(&pool)
+        .await?;
+    let error = PostgresStorage::migrate(&pool).await.unwrap_err();
+    assert!(error.to_string().contains("newer"), "{error}");
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Seen sets on their own, and a crawl continued with a seen set kept on disk
+
+mod common;
+
+use axum::{extract::Path, response::Html};
+use common::TempDir;
+use waper::seen::{BloomSeenSet, MemorySeenSet, SeenSet, SqliteSeenSet};
+use waper::{Database, Orchestrator, RuntimeConfig};
+
+async fn page(path: Option<Path<String>>) -> Html<&'static str> {
+    match path.map(|x| x.0).unwrap_or_default().as_str() {
+        "" => Html(r#"<a href="/a">a</a> <a href="/b">b</a>"#),
+        "a" => Html(r#"<a href="/">home</a> <a href="/c">c</a>"#),
+        _ => Html(""),
+    }
+}
+
+#[test]
+fn seen_sets_remember_urls() -> anyhow::Result<()> {
+    let dir = TempDir::new("seen-set");
+    let path = dir.join("seen.sqlite");
+    let sets: Vec<Box<dyn SeenSet>> = vec![
+        Box::new(MemorySeenSet::new()),
+        Box::new(SqliteSeenSet::open(&path)?),
+        Box::new(BloomSeenSet::new(0.0001, 1000)?),
+    ];
+    for mut set in sets {
+        assert!(set.is_empty());
+        assert!(set.insert("https://example.com/a")?);
+        assert!(!set.insert("https://example.com/a")?);
+        assert!(set.contains("https://example.com/a")?);
+        assert!(!set.contains("https://example.com/b")?);
+        assert_eq!(set.len(), 1);
+        assert!(set.memory_usage() > 0);
+    }
+    assert!(SqliteSeenSet::open(&path)?.is_blocking());
+    assert!(!MemorySeenSet::new().is_blocking());
+
+    // still there after a restart
+    let set = SqliteSeenSet::open(&path)?;
+    assert_eq!(set.len(), 1);
+    assert!(set.contains("https://example.com/a")?);
+    Ok(())
+}
+
+#[test]
+fn bloom_seen_set_never_forgets() -> anyhow::Result<()> {
+    assert!(BloomSeenSet::new(0.0, 1000).is_err());
+
+    let mut set = BloomSeenSet::new(0.0001, 1_000_000)?;
+    // ~19 bits per url
+    let memory = set.memory_usage();
+    assert!((2_000_000..3_000_000).contains(&memory), "{memory}");
+
+    let urls: Vec<_> = (0..10_000)
+        .map(|x| format!("https://example.com/{x}"))
+        .collect();
+    let mut new = 0;
+    for url in &urls {
+        new += set.insert(url)? as u64;
+    }
+    // a false positive is taken as seen
+    assert!(new > 9_990, "{new}");
+    assert_eq!(set.len(), new);
+    for url in &urls {
+        assert!(set.contains(url)?);
+    }
+    assert_eq!(set.memory_usage(), memory);
+    Ok(())
+}
+
+#[tokio::test]
+async fn continue_with_seen_set_on_disk() -> anyhow::Result<()> {
+    continue_with_seen_set("seen-crawl").await
+}
+
+/// The sqlite seen set is then used in `block_in_place`
+#[tokio::test(flavor = "multi_thread")]
+async fn continue_with_seen_set_on_disk_multi_thread() -> anyhow::Result<()> {
+    continue_with_seen_set("seen-crawl-threads").await
+}
+
+async fn continue_with_seen_set(name: &str) -> anyhow::Result<()> {
+    let addr = common::site(page);
+    let dir = TempDir::new(name);
+    let seen_path = dir.join("seen.sqlite");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let config = RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?;
+
+    let crawl = |seed: &str| {
+        Orchestrator::builder(db.clone())
+            .seeds([format!("http://{addr}{seed}").parse().unwrap()])
+            .config(config.clone())
+            .seen_set(SqliteSeenSet::open(&seen_path).unwrap())
+            .build()
+            .unwrap()
+    };
+    let mut orchestrator = crawl("/");
+    let control = orchestrator.control();
+    let session = orchestrator.start(false).await?;
+    assert_eq!(session.pages, 4);
+    assert_eq!(control.stats().seen, 4);
+    assert!(control.stats().seen_bytes > 0);
+
+    // links of the seed were seen by the first run
+    let session = crawl("/a").start(false).await?;
+    assert_eq!(session.pages, 1);
+    assert_eq!(SqliteSeenSet::open(&seen_path)?.len(), 4);
+    Ok(())
+}
@@ -0,0 +1,249 @@
+//! Drives crawls through the `waper serve` api, against a small in-process site
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{extract::Path, response::Html};
+use common::{serve, TempDir};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use waper::server::Server;
+use waper::{Database, RuntimeConfig};
+
+async fn page(path: Option<Path<String>>) -> Html<&'static str> {
+    match path.map(|x| x.0).unwrap_or_default().as_str() {
+        "" => {
+            // slow enough to pause the crawl while it's in flight
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Html(r#"<a href="/a">a</a> <a href="/b">b</a>"#)
+        }
+        "a" => Html(r#"<a href="/c">c</a>"#),
+        "d" => Html("added later"),
+        _ => Html(""),
+    }
+}
+
+struct Api {
+    base: String,
+    client: reqwest::Client,
+}
+
+impl Api {
+    async fn call(&self, method: Method, path: &str, body: Option<Value>) -> (StatusCode, Value) {
+        let mut request = self.client.request(method, format!("{}{path}", self.base));
+        if let Some(body) = body {
+            request = request
+                .header("content-type", "application/json")
+                .body(body.to_string());
+        }
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        (
+            status,
+            serde_json::from_str(&response.text().await.unwrap()).unwrap(),
+        )
+    }
+
+    /// Poll the crawl till `done` returns true
+    async fn wait_for(&self, id: u64, done: impl Fn(&Value) -> bool) -> Value {
+        for _ in 0..250 {
+            let (_, crawl) = self.call(Method::GET, &format!("/crawls/{id}"), None).await;
+            if done(&crawl) {
+                return crawl;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("crawl {id} didn't get there in time");
+    }
+}
+
+#[tokio::test]
+async fn control_crawl_over_http() -> anyhow::Result<()> {
+    let site = common::site(page);
+    let dir = TempDir::new("server");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    let server = Arc::new(Server::new(db, RuntimeConfig::default()));
+    let api = Api {
+        base: format!("http://{}", serve(server.router())),
+        client: reqwest::Client::new(),
+    };
+
+    let (status, crawl) = api
+        .call(
+            Method::POST,
+            "/crawls",
+            Some(json!({
+                "seeds": [format!("http://{site}/")],
+                "whitelist": [format!("http://{site}/.*")],
+                "max_parallel_requests": 1,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = crawl["id"].as_u64().unwrap();
+    let (_, crawl) = api
+        .call(Method::POST, &format!("/crawls/{id}/pause"), None)
+        .await;
+    assert_eq!(crawl["state"], "paused");
+
+    // seed finishes, links it found wait for resume
+    let crawl = api
+        .wait_for(id, |x| x["pages"] == 1 && x["in_flight"] == 0)
+        .await;
+    assert_eq!(
+        (crawl["pages"].as_u64(), crawl["queued"].as_u64()),
+        (Some(1), Some(2))
+    );
+
+    let (status, _) = api
+        .call(
+            Method::PATCH,
+            &format!("/crawls/{id}/config"),
+            Some(json!({ "blacklist": ["/c$"] })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, error) = api
+        .call(
+            Method::PATCH,
+            &format!("/crawls/{id}/config"),
+            Some(json!({ "whitelist": ["("] })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        error["error"].as_str().unwrap().contains("regex"),
+        "{error}"
+    );
+
+    api.call(
+        Method::POST,
+        &format!("/crawls/{id}/seeds"),
+        Some(json!({ "seeds": [format!("http://{site}/d")] })),
+    )
+    .await;
+    api.call(Method::POST, &format!("/crawls/{id}/resume"), None)
+        .await;
+    let crawl = api.wait_for(id, |x| x["state"] == "finished").await;
+    assert_eq!(crawl["exit_reason"], "completed");
+    assert_eq!(crawl["pages"], 4);
+
+    let (_, pages) = api
+        .call(Method::GET, &format!("/pages?prefix=http://{site}/"), None)
+        .await;
+    let mut urls: Vec<_> = pages
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| {
+            x["url"]
+                .as_str()
+                .unwrap()
+                .replace(&format!("http://{site}"), "")
+        })
+        .collect();
+    urls.sort();
+    assert_eq!(urls, vec!["/", "/a", "/b", "/d"]);
+
+    let (_, page) = api
+        .call(Method::GET, &format!("/page?url=http://{site}/d"), None)
+        .await;
+    assert_eq!(page["html"], "added later");
+    let (status, _) = api
+        .call(Method::GET, &format!("/page?url=http://{site}/c"), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = api.call(Method::GET, "/crawls/9", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, error) = api
+        .call(Method::POST, "/crawls", Some(json!({ "seed": [] })))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(
+        error["error"].as_str().unwrap().contains("seeds"),
+        "{error}"
+    );
+
+    let metrics = api
+        .client
+        .get(format!("{}/metrics", api.base))
+        .send()
+        .await?
+        .text()
+        .await?;
+    assert!(
+        metrics.contains(r#"waper_requests_total{host="127.0.0.1",kind="page",status="2xx"} 4"#),
+        "{metrics}"
+    );
+    assert!(
+        metrics.contains("waper_db_write_seconds_bucket"),
+        "{metrics}"
+    );
+    Ok(())
+}
+
+/// Kills the server on drop
+struct Child(std::process::Child);
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[tokio::test]
+async fn crawl_flags_apply_to_api_crawls() -> anyhow::Result<()> {
+    let site = common::site(|| async { Html(r#"<img src="/logo.png"> <a href="/a">a</a>"#) });
+    let dir = TempDir::new("server-flags");
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let _server = Child(
+        std::process::Command::new(env!("CARGO_BIN_EXE_waper"))
+            .args(["serve", "--addr", &addr.to_string(), "--assets", "img"])
+            .args(["--max-pages", "2", "-o"])
+            .arg(dir.join("out.sqlite"))
+            .spawn()?,
+    );
+    let api = Api {
+        base: format!("http://{addr}"),
+        client: reqwest::Client::new(),
+    };
+    for _ in 0..250 {
+        if api
+            .client
+            .get(format!("{}/crawls", api.base))
+            .send()
+            .await
+            .is_ok()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let (status, crawl) = api
+        .call(
+            Method::POST,
+            "/crawls",
+            Some(json!({ "seeds": [format!("http://{site}/")] })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{crawl}");
+    let id = crawl["id"].as_u64().unwrap();
+    let crawl = api.wait_for(id, |x| x["state"] == "finished").await;
+    assert_eq!(crawl["assets"], 1, "{crawl}");
+    // a body field still wins over the flag
+    let (_, crawl) = api
+        .call(
+            Method::POST,
+            "/crawls",
+            Some(json!({ "seeds": [format!("http://{site}/")], "max_pages": 1 })),
+        )
+        .await;
+    let id = crawl["id"].as_u64().unwrap();
+    let crawl = api.wait_for(id, |x| x["state"] == "finished").await;
+    assert_eq!(crawl["exit_reason"], "max_pages", "{crawl}");
+    Ok(())
+}
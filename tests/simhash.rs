@@ -0,0 +1,62 @@
+//! SimHash fingerprints used to find near-duplicate pages
+
+use select::document::Document;
+use waper::simhash::{distance, document_text, simhash, SimHashIndex};
+
+const ARTICLE: &str = "The quick brown fox jumps over the lazy dog while the farmer \
+    watches from the porch and wonders why the dog never bothers to chase anything at all \
+    on these long and quiet summer afternoons in the valley";
+
+#[test]
+fn identical_text() {
+    let a = simhash(ARTICLE).unwrap();
+    // case and punctuation don't matter
+    let b = simhash(&ARTICLE.to_uppercase().replace(' ', ", ")).unwrap();
+    assert_eq!(a, b);
+    assert_eq!(distance(a, b), 0);
+}
+
+#[test]
+fn near_text() {
+    let a = simhash(ARTICLE).unwrap();
+    let near = simhash(&ARTICLE.replace("summer", "winter")).unwrap();
+    let other = simhash(
+        "Prices of second hand bicycles went up again this month, dealers blame \
+         the shortage of parts and the popularity of cycling since the city opened new lanes",
+    )
+    .unwrap();
+    assert!(distance(a, near) > 0);
+    assert!(distance(a, near) < distance(a, other));
+    assert!(distance(a, other) > 10, "{}", distance(a, other));
+}
+
+#[test]
+fn too_little_text() {
+    assert_eq!(simhash(""), None);
+    assert_eq!(simhash("  \n, . "), None);
+    assert_eq!(simhash("Loading, please wait"), None);
+    assert!(simhash(ARTICLE).is_some());
+}
+
+#[test]
+fn text_ignores_scripts() {
+    let document = Document::from(
+        "<html><head><style>p { color: red }</style></head>\
+         <body><p>Hello</p><script>var x = 1;</script><p>world</p></body></html>",
+    );
+    let text = document_text(&document);
+    assert_eq!(
+        text.split_whitespace().collect::<Vec<_>>(),
+        ["Hello", "world"]
+    );
+}
+
+#[test]
+fn index_finds_within_distance() {
+    let mut index = SimHashIndex::new(3);
+    index.insert(0b1111, "a");
+    assert_eq!(index.find(0b1111), Some((&"a", 0)));
+    assert_eq!(index.find(0b1000), Some((&"a", 3)));
+    assert_eq!(index.find(0), None);
+    assert_eq!(index.find(u64::MAX << 32), None);
+}
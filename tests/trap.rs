@@ -0,0 +1,165 @@
+//! Crawler trap heuristics of `TrapDetector::check`
+
+mod common;
+
+use axum::{response::Html, routing::get, Router};
+use common::{serve, TempDir};
+use url::Url;
+use waper::trap::{TrapConfig, TrapDetector, TrapReason};
+use waper::{Database, Orchestrator, RuntimeConfig};
+
+fn url(s: &str) -> Url {
+    s.parse().unwrap()
+}
+
+fn disabled() -> TrapConfig {
+    TrapConfig {
+        max_url_length: None,
+        max_repeated_segments: None,
+        max_query_variants: None,
+        max_pages_per_host: None,
+    }
+}
+
+#[test]
+fn url_too_long() {
+    let config = TrapConfig {
+        max_url_length: Some(30),
+        ..disabled()
+    };
+    let mut detector = TrapDetector::new();
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/short")),
+        Ok(())
+    );
+    let long = url("https://example.com/a-bit-too-long");
+    assert_eq!(
+        detector.check(&config, &long),
+        Err(TrapReason::UrlTooLong(long.as_str().len()))
+    );
+}
+
+#[test]
+fn repeated_segments() {
+    let config = TrapConfig {
+        max_repeated_segments: Some(2),
+        ..disabled()
+    };
+    let mut detector = TrapDetector::new();
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/a/b/a/b")),
+        Ok(())
+    );
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/a/b/a/b/a/b")),
+        Err(TrapReason::RepeatedSegment("a".to_string(), 3))
+    );
+    // empty segments (`//`, trailing slash) don't count
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/a//a///")),
+        Ok(())
+    );
+}
+
+#[tokio::test]
+async fn repeated_segments_crawled_by_default() -> anyhow::Result<()> {
+    // versioned docs repeat segments without being a trap
+    let addr = serve(
+        Router::new()
+            .route(
+                "/",
+                get(|| async { Html(r#"<a href="/docs/v1/api/v1/ref/v1">ref</a>"#) }),
+            )
+            .route(
+                "/docs/v1/api/v1/ref/v1",
+                get(|| async { Html("<p>ref</p>") }),
+            ),
+    );
+    let dir = TempDir::new("trap-defaults");
+    let db = Database::connect(&dir.join("out.sqlite")).await?;
+    Orchestrator::builder(db.clone())
+        .seeds([format!("http://{addr}/").parse()?])
+        .config(RuntimeConfig::default().with_whitelist([format!("http://{addr}/.*")])?)
+        .build()?
+        .start(false)
+        .await?;
+
+    let mut pages = db.get_result_urls().await?;
+    pages.sort();
+    assert_eq!(
+        pages,
+        [
+            format!("http://{addr}/"),
+            format!("http://{addr}/docs/v1/api/v1/ref/v1")
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn too_many_query_variants() {
+    let config = TrapConfig {
+        max_query_variants: Some(2),
+        ..disabled()
+    };
+    let mut detector = TrapDetector::new();
+    for query in ["page=1", "page=2", "page=1"] {
+        let url = url(&format!("https://example.com/list?{query}"));
+        assert_eq!(detector.check(&config, &url), Ok(()));
+    }
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/list?page=3")),
+        Err(TrapReason::TooManyQueryVariants(2))
+    );
+    // counted per path, urls without a query aren't limited
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/other?page=3")),
+        Ok(())
+    );
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/list")),
+        Ok(())
+    );
+}
+
+#[test]
+fn host_budget_exhausted() {
+    let config = TrapConfig {
+        max_pages_per_host: Some(2),
+        ..disabled()
+    };
+    let mut detector = TrapDetector::new();
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/1")),
+        Ok(())
+    );
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/2")),
+        Ok(())
+    );
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/3")),
+        Err(TrapReason::HostBudgetExhausted(2))
+    );
+    assert_eq!(
+        detector.check(&config, &url("https://example.org/1")),
+        Ok(())
+    );
+}
+
+#[test]
+fn rejected_urls_are_not_counted() {
+    let config = TrapConfig {
+        max_url_length: Some(30),
+        max_pages_per_host: Some(1),
+        ..disabled()
+    };
+    let mut detector = TrapDetector::new();
+    assert!(detector
+        .check(&config, &url("https://example.com/a-bit-too-long"))
+        .is_err());
+    assert_eq!(
+        detector.check(&config, &url("https://example.com/1")),
+        Ok(())
+    );
+}